@@ -0,0 +1,7 @@
+-- This file should undo anything in `up.sql`
+
+ALTER TABLE login_session
+    DROP public_id,
+    DROP last_used_at,
+    DROP device_label,
+    DROP user_agent;
@@ -0,0 +1,10 @@
+-- Your SQL goes here
+
+-- `id` is the secret session token, so sessions are referred to by `public_id` in the API.
+ALTER TABLE login_session
+    ADD COLUMN public_id    SERIAL       NOT NULL UNIQUE,
+    ADD COLUMN last_used_at TIMESTAMPTZ  NULL,
+    ADD COLUMN device_label VARCHAR(128) NULL,
+    ADD COLUMN user_agent   VARCHAR(512) NULL;
+
+CREATE INDEX ON login_session (person_id);
@@ -0,0 +1,6 @@
+-- This file should undo anything in `up.sql`
+
+-- The original tokens can't be recovered from their digests, so end every session.
+DELETE FROM login_session;
+
+COMMENT ON COLUMN login_session.id IS NULL;
@@ -0,0 +1,7 @@
+-- Your SQL goes here
+
+-- Session tokens are now stored as the hex encoded SHA-256 digest of the token.
+-- Rehash existing sessions so that nobody is logged out.
+UPDATE login_session SET id = encode(sha256(convert_to(id, 'UTF8')), 'hex');
+
+COMMENT ON COLUMN login_session.id IS 'SHA-256 digest of the secret session token.';
@@ -0,0 +1,19 @@
+-- Your SQL goes here
+
+CREATE TABLE api_token (
+    id           SERIAL       PRIMARY KEY,
+    person_id    INTEGER      NOT NULL REFERENCES person(id) ON DELETE CASCADE ON UPDATE CASCADE,
+    name         VARCHAR(128) NOT NULL,
+    digest       CHAR(64)     NOT NULL UNIQUE,
+    scopes       TEXT[]       NOT NULL DEFAULT '{}',
+    last_used_at TIMESTAMPTZ  NULL,
+    created_at   TIMESTAMPTZ  NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at   TIMESTAMPTZ  NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX ON api_token (person_id);
+
+COMMENT ON TABLE api_token IS 'Personal access tokens, limited to a set of scopes, for scripts and integrations.';
+COMMENT ON COLUMN api_token.digest IS 'SHA-256 digest of the secret token.';
+
+SELECT diesel_manage_updated_at('api_token');
@@ -0,0 +1,6 @@
+-- This file should undo anything in `up.sql`
+
+-- Identifiers can't be returned to the format they were typed in, and merged
+-- people can't be split apart again. E.164 identifiers remain valid, so only
+-- the column comment is removed.
+COMMENT ON COLUMN identity.identifier IS NULL;
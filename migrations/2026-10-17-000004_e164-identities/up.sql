@@ -0,0 +1,10 @@
+-- Your SQL goes here
+
+-- Phone identities used to be stored as the country code followed by the number
+-- exactly as it was typed, so the same phone could end up as several identities
+-- (and several people). They are rewritten in E.164 format when the server
+-- starts, by `db::NormalizePhoneIdentities`, so that they are parsed exactly
+-- like a number typed at login. People whose identities turn out to be the same
+-- phone number are merged then too.
+
+COMMENT ON COLUMN identity.identifier IS 'Phone numbers are stored in E.164 format, e.g. +15552345678.';
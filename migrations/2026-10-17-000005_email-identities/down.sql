@@ -0,0 +1,9 @@
+-- This file should undo anything in `up.sql`
+
+DROP TABLE email_challenge;
+
+DELETE FROM identity WHERE kind <> 'phone';
+
+ALTER TABLE identity DROP CONSTRAINT identity_pkey;
+ALTER TABLE identity ADD PRIMARY KEY (identifier);
+ALTER TABLE identity DROP kind;
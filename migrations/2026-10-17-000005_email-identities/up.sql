@@ -0,0 +1,25 @@
+-- Your SQL goes here
+
+-- Record what kind of identifier each identity is, so that phone numbers
+-- and email addresses can never collide.
+ALTER TABLE identity
+    ADD COLUMN kind VARCHAR(16) NOT NULL DEFAULT 'phone' CHECK (kind IN ('phone', 'email'));
+
+ALTER TABLE identity DROP CONSTRAINT identity_pkey;
+ALTER TABLE identity ADD PRIMARY KEY (kind, identifier);
+
+CREATE INDEX ON identity (person_id);
+
+CREATE TABLE email_challenge (
+    id          SERIAL       PRIMARY KEY,
+    email       VARCHAR(254) NOT NULL,
+    digest      CHAR(64)     NOT NULL UNIQUE,
+    created_at  TIMESTAMPTZ  NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    expires_at  TIMESTAMPTZ  NOT NULL,
+    consumed_at TIMESTAMPTZ  NULL
+);
+
+CREATE INDEX ON email_challenge (email);
+
+COMMENT ON TABLE email_challenge IS 'Single-use login links that have been emailed to a person.';
+COMMENT ON COLUMN email_challenge.digest IS 'SHA-256 digest of the secret token in the login link.';
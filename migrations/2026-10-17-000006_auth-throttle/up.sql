@@ -0,0 +1,11 @@
+-- Your SQL goes here
+
+CREATE TABLE auth_throttle (
+    key       TEXT         PRIMARY KEY,
+    hits      INTEGER      NOT NULL,
+    resets_at TIMESTAMPTZ  NOT NULL
+);
+
+CREATE INDEX ON auth_throttle (resets_at);
+
+COMMENT ON TABLE auth_throttle IS 'Rate limit windows for login attempts, keyed by e.g. phone number or IP address.';
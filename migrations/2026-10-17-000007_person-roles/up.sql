@@ -0,0 +1,9 @@
+-- Your SQL goes here
+
+-- Everyone starts out as a member. The first site admin has to be promoted by hand:
+--   UPDATE person SET role = 'site_admin' WHERE id = ...;
+ALTER TABLE person
+    ADD COLUMN role VARCHAR(16) NOT NULL DEFAULT 'member'
+    CHECK (role IN ('member', 'bartender', 'club_admin', 'site_admin'));
+
+CREATE INDEX ON person (role) WHERE role <> 'member';
@@ -0,0 +1,8 @@
+-- Your SQL goes here
+
+-- People who have asked to delete their account are kept for a grace period,
+-- during which they can change their mind, and then purged along with
+-- everything that references them through ON DELETE CASCADE.
+ALTER TABLE person ADD COLUMN deletion_requested_at TIMESTAMPTZ NULL;
+
+CREATE INDEX ON person (deletion_requested_at) WHERE deletion_requested_at IS NOT NULL;
@@ -0,0 +1,10 @@
+-- This file should undo anything in `up.sql`
+
+DROP TABLE oidc_login;
+
+DELETE FROM identity WHERE kind = 'oidc';
+
+ALTER TABLE identity DROP CONSTRAINT identity_kind_check;
+ALTER TABLE identity ADD CONSTRAINT identity_kind_check CHECK (kind IN ('phone', 'email'));
+
+ALTER TABLE identity ALTER COLUMN identifier TYPE VARCHAR(128);
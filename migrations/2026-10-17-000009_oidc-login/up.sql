@@ -0,0 +1,19 @@
+-- Your SQL goes here
+
+-- OIDC identities are "{issuer}|{sub}", which can be a good deal longer than
+-- a phone number or email address.
+ALTER TABLE identity ALTER COLUMN identifier TYPE VARCHAR(512);
+
+ALTER TABLE identity DROP CONSTRAINT identity_kind_check;
+ALTER TABLE identity ADD CONSTRAINT identity_kind_check CHECK (kind IN ('phone', 'email', 'oidc'));
+
+CREATE TABLE oidc_login (
+    state         CHAR(64)     PRIMARY KEY,
+    nonce         VARCHAR(64)  NOT NULL,
+    code_verifier VARCHAR(128) NOT NULL,
+    created_at    TIMESTAMPTZ  NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    expires_at    TIMESTAMPTZ  NOT NULL
+);
+
+COMMENT ON TABLE oidc_login IS 'Logins with an OpenID Connect provider that have been started but not completed.';
+COMMENT ON COLUMN oidc_login.state IS 'SHA-256 digest of the state parameter sent to the provider.';
@@ -0,0 +1,18 @@
+-- Your SQL goes here
+
+CREATE TABLE auth_event (
+    id            SERIAL       PRIMARY KEY,
+    kind          VARCHAR(32)  NOT NULL,
+    person_id     INTEGER      NULL REFERENCES person (id) ON DELETE CASCADE,
+    identity_kind VARCHAR(16)  NULL,
+    identifier    VARCHAR(512) NULL,
+    ip_address    VARCHAR(64)  NULL,
+    user_agent    VARCHAR(512) NULL,
+    detail        VARCHAR(256) NULL,
+    created_at    TIMESTAMPTZ  NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX ON auth_event (person_id, id);
+CREATE INDEX ON auth_event (identifier, id);
+
+COMMENT ON TABLE auth_event IS 'Security log of verification attempts, lockouts and session changes.';
@@ -0,0 +1,9 @@
+-- This file should undo anything in `up.sql`
+
+ALTER TABLE person
+    DROP COLUMN display_name,
+    DROP COLUMN handle,
+    DROP COLUMN bio,
+    DROP COLUMN avatar_url,
+    DROP COLUMN timezone,
+    DROP COLUMN units;
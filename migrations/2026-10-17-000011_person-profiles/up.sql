@@ -0,0 +1,12 @@
+-- Your SQL goes here
+
+ALTER TABLE person
+    ADD COLUMN display_name VARCHAR(64)   NULL,
+    ADD COLUMN handle       VARCHAR(32)   NULL UNIQUE CHECK (handle ~ '^[a-z0-9_]{3,32}$'),
+    ADD COLUMN bio          VARCHAR(500)  NULL,
+    ADD COLUMN avatar_url   VARCHAR(2048) NULL,
+    ADD COLUMN timezone     VARCHAR(64)   NOT NULL DEFAULT 'UTC',
+    ADD COLUMN units        VARCHAR(16)   NOT NULL DEFAULT 'metric' CHECK (units IN ('metric', 'imperial'));
+
+COMMENT ON COLUMN person.handle IS 'Unique, lowercase name that others can find the person by.';
+COMMENT ON COLUMN person.timezone IS 'IANA name of the timezone the person drinks in, such as America/Chicago.';
@@ -0,0 +1,6 @@
+-- Your SQL goes here
+
+ALTER TABLE drink
+    ADD COLUMN shared BOOLEAN NOT NULL DEFAULT false;
+
+COMMENT ON COLUMN drink.shared IS 'Whether people other than the drinker can look at the drink.';
@@ -0,0 +1,10 @@
+-- This file should undo anything in `up.sql`
+
+DROP INDEX brewery_name_lower_key;
+DROP INDEX beer_brewery_name_lower_key;
+
+CREATE INDEX brewery_name_lower_idx ON brewery (LOWER(name));
+CREATE INDEX beer_name_lower_idx ON beer (LOWER(name));
+
+-- This fails if different breweries have beers with the same name
+ALTER TABLE beer ADD CONSTRAINT beer_name_key UNIQUE (name);
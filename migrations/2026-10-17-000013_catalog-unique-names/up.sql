@@ -0,0 +1,43 @@
+-- Your SQL goes here
+
+-- Names are looked up case-insensitively, so enforce that they are unique the
+-- same way. Beers only need unique names within their brewery, so different
+-- breweries can each have a beer with the same name.
+
+-- Merge breweries whose names differ only by case into the oldest of them
+UPDATE beer
+SET brewery_id = keeper.id
+FROM brewery duplicate,
+    (SELECT MIN(id) AS id, LOWER(name) AS name FROM brewery GROUP BY LOWER(name)) keeper
+WHERE beer.brewery_id = duplicate.id
+    AND LOWER(duplicate.name) = keeper.name
+    AND duplicate.id <> keeper.id;
+
+DELETE FROM brewery duplicate
+USING brewery keeper
+WHERE LOWER(duplicate.name) = LOWER(keeper.name)
+    AND keeper.id < duplicate.id;
+
+-- Then merge each brewery's beers whose names differ only by case
+UPDATE drink
+SET beer_id = keeper.id
+FROM beer duplicate,
+    (SELECT MIN(id) AS id, brewery_id, LOWER(name) AS name FROM beer GROUP BY brewery_id, LOWER(name)) keeper
+WHERE drink.beer_id = duplicate.id
+    AND duplicate.brewery_id = keeper.brewery_id
+    AND LOWER(duplicate.name) = keeper.name
+    AND duplicate.id <> keeper.id;
+
+DELETE FROM beer duplicate
+USING beer keeper
+WHERE duplicate.brewery_id = keeper.brewery_id
+    AND LOWER(duplicate.name) = LOWER(keeper.name)
+    AND keeper.id < duplicate.id;
+
+ALTER TABLE beer DROP CONSTRAINT beer_name_key;
+
+DROP INDEX brewery_name_lower_idx;
+DROP INDEX beer_name_lower_idx;
+
+CREATE UNIQUE INDEX brewery_name_lower_key ON brewery (LOWER(name));
+CREATE UNIQUE INDEX beer_brewery_name_lower_key ON beer (brewery_id, LOWER(name));
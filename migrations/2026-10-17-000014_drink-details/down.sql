@@ -0,0 +1,9 @@
+-- This file should undo anything in `up.sql`
+
+ALTER TABLE drink
+    DROP COLUMN drank_at,
+    DROP COLUMN serving,
+    DROP COLUMN volume_ml,
+    DROP COLUMN price_cents,
+    DROP COLUMN abv,
+    DROP COLUMN venue;
@@ -0,0 +1,13 @@
+-- Your SQL goes here
+
+ALTER TABLE drink
+    ADD COLUMN drank_at    TIMESTAMPTZ  NULL,
+    ADD COLUMN serving     VARCHAR(16)  NULL CHECK (serving IN ('draft', 'can', 'bottle', 'cask')),
+    ADD COLUMN volume_ml   INTEGER      NULL CHECK (volume_ml > 0),
+    ADD COLUMN price_cents INTEGER      NULL CHECK (price_cents >= 0),
+    ADD COLUMN abv         REAL         NULL CHECK (abv >= 0 AND abv <= 100),
+    ADD COLUMN venue       VARCHAR(200) NULL;
+
+COMMENT ON COLUMN drink.drank_at IS 'When exactly the drink was had, if known; drank_on is the local date.';
+COMMENT ON COLUMN drink.price_cents IS 'Price paid, in hundredths of the club''s currency.';
+COMMENT ON COLUMN drink.abv IS 'Alcohol by volume as a percentage, as served.';
@@ -0,0 +1,13 @@
+-- This file should undo anything in `up.sql`
+
+ALTER TABLE drink
+    ADD COLUMN rating SMALLINT NULL CHECK (rating >= 0 AND rating <= 5);
+
+-- Quarter stars can't be represented any more, so round to the nearest star
+UPDATE drink SET rating = ROUND(rating_quarters / 4.0);
+
+ALTER TABLE drink
+    ALTER COLUMN rating SET NOT NULL,
+    DROP COLUMN rating_quarters;
+
+CREATE INDEX ON drink (rating);
@@ -0,0 +1,14 @@
+-- Your SQL goes here
+
+ALTER TABLE drink
+    ADD COLUMN rating_quarters SMALLINT NULL CHECK (rating_quarters >= 0 AND rating_quarters <= 20);
+
+UPDATE drink SET rating_quarters = rating * 4;
+
+ALTER TABLE drink
+    ALTER COLUMN rating_quarters SET NOT NULL,
+    DROP COLUMN rating;
+
+CREATE INDEX ON drink (rating_quarters);
+
+COMMENT ON COLUMN drink.rating_quarters IS 'Rating out of 5 stars, counted in quarter stars.';
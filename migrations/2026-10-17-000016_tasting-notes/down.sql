@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+
+DROP TABLE tasting_note_descriptor;
+DROP TABLE flavor_descriptor;
+DROP TABLE tasting_note;
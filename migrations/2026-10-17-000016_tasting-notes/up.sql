@@ -0,0 +1,41 @@
+-- Your SQL goes here
+
+CREATE TABLE tasting_note (
+    drink_id   INTEGER     PRIMARY KEY REFERENCES drink (id) ON DELETE CASCADE,
+    appearance SMALLINT    NULL CHECK (appearance >= 0 AND appearance <= 3),
+    aroma      SMALLINT    NULL CHECK (aroma >= 0 AND aroma <= 12),
+    flavor     SMALLINT    NULL CHECK (flavor >= 0 AND flavor <= 20),
+    mouthfeel  SMALLINT    NULL CHECK (mouthfeel >= 0 AND mouthfeel <= 5),
+    overall    SMALLINT    NULL CHECK (overall >= 0 AND overall <= 10),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+SELECT diesel_manage_updated_at('tasting_note');
+
+COMMENT ON TABLE tasting_note IS 'Structured notes on a drink, scored out of 50 like a BJCP score sheet.';
+
+CREATE TABLE flavor_descriptor (
+    id   SERIAL      PRIMARY KEY,
+    name VARCHAR(32) NOT NULL UNIQUE
+);
+
+COMMENT ON TABLE flavor_descriptor IS 'Flavors and off-flavors that can be picked out in tasting notes.';
+
+INSERT INTO flavor_descriptor (name) VALUES
+    ('acetaldehyde'), ('astringent'), ('banana'), ('biscuit'), ('boozy'),
+    ('bready'), ('caramel'), ('chocolate'), ('citrus'), ('clove'),
+    ('coffee'), ('dark fruit'), ('diacetyl'), ('dms'), ('earthy'),
+    ('floral'), ('funky'), ('grassy'), ('herbal'), ('honey'),
+    ('metallic'), ('nutty'), ('oaky'), ('oxidized'), ('piney'),
+    ('roasty'), ('skunky'), ('smoky'), ('solvent'), ('sour'),
+    ('spicy'), ('stone fruit'), ('toffee'), ('tropical'), ('vanilla');
+
+CREATE TABLE tasting_note_descriptor (
+    drink_id      INTEGER NOT NULL REFERENCES tasting_note (drink_id) ON DELETE CASCADE,
+    descriptor_id INTEGER NOT NULL REFERENCES flavor_descriptor (id) ON DELETE CASCADE,
+
+    PRIMARY KEY (drink_id, descriptor_id)
+);
+
+CREATE INDEX ON tasting_note_descriptor (descriptor_id);
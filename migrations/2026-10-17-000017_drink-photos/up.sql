@@ -0,0 +1,20 @@
+-- Your SQL goes here
+
+CREATE TABLE drink_photo (
+    id            SERIAL      PRIMARY KEY,
+    drink_id      INTEGER     NOT NULL REFERENCES drink (id) ON DELETE CASCADE,
+    blob_key      VARCHAR(64) NOT NULL UNIQUE,
+    thumbnail_key VARCHAR(64) NOT NULL UNIQUE,
+    width         INTEGER     NOT NULL,
+    height        INTEGER     NOT NULL,
+    created_at    TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at    TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX ON drink_photo (drink_id);
+
+COMMENT ON TABLE drink_photo IS 'Photos of a drink, such as the pour or the label.';
+COMMENT ON COLUMN drink_photo.blob_key IS 'Key of the full size photo in the blob store.';
+COMMENT ON COLUMN drink_photo.thumbnail_key IS 'Key of the thumbnail in the blob store.';
+
+SELECT diesel_manage_updated_at('drink_photo');
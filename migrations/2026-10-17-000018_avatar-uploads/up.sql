@@ -0,0 +1,5 @@
+-- Your SQL goes here
+
+ALTER TABLE person ADD COLUMN avatar_key VARCHAR(64) NULL UNIQUE;
+
+COMMENT ON COLUMN person.avatar_key IS 'Key of an uploaded avatar in the blob store, which avatar_url points at.';
@@ -27,6 +27,19 @@ where
     pub status: ResponseStatus,
     pub data: Option<ApiResponseEnvelope<T>>,
     pub messages: Option<Vec<String>>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pagination: Option<Pagination>,
+}
+
+/// Where a paginated list is up to, returned alongside a page of results.
+#[derive(Serialize)]
+pub struct Pagination {
+    /// Pass back as `after` to get the next page; `None` on the last page.
+    pub next: Option<String>,
+
+    /// The most results returned in a page.
+    pub limit: i64,
 }
 
 impl<T> Serialize for ApiResponseEnvelope<T>
@@ -53,6 +66,7 @@ where
             status: ResponseStatus::Success,
             data: data.map(|data| ApiResponseEnvelope(data)),
             messages: None,
+            pagination: None,
         }
     }
 
@@ -61,6 +75,7 @@ where
             status: ResponseStatus::Success,
             data: Some(ApiResponseEnvelope(data)),
             messages: None,
+            pagination: None,
         }
     }
 
@@ -70,6 +85,7 @@ where
             status: ResponseStatus::Fail,
             data: Some(ApiResponseEnvelope(data)),
             messages: None,
+            pagination: None,
         }
     }
 
@@ -79,6 +95,7 @@ where
             status: ResponseStatus::Error,
             data: Some(ApiResponseEnvelope(data)),
             messages: None,
+            pagination: None,
         }
     }
 
@@ -93,6 +110,11 @@ where
         self
     }
 
+    pub fn with_pagination(mut self, pagination: Pagination) -> ApiResponse<T> {
+        self.pagination = Some(pagination);
+        self
+    }
+
     pub fn add_message(mut self, message: String) -> ApiResponse<T> {
         if self.messages.is_none() {
             self.messages = Some(Vec::new());
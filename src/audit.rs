@@ -0,0 +1,137 @@
+//! A security log of authentication events.
+//!
+//! Verification attempts, lockouts and session changes are recorded in the
+//! `auth_event` table along with the client's address and user agent, so that
+//! admins can look into abuse and people can review recent activity on their
+//! account. Events are written in the background; failing to record one is
+//! logged, but never fails the request that caused it.
+
+use actix_web::HttpRequest;
+
+use crate::db::{self, Pool, RecordAuthEvent};
+use crate::models::IdentityKind;
+
+/// The kinds of event that are recorded.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuthEventKind {
+    /// A code or login link was sent, or a provider login was started.
+    VerificationStarted,
+
+    VerificationSucceeded,
+
+    /// A wrong or expired code, link or provider login was submitted.
+    VerificationFailed,
+
+    /// Too many wrong codes were submitted for an identity.
+    LockedOut,
+
+    SessionStarted,
+
+    /// A session was ended by logging out or revoking it.
+    SessionRevoked,
+}
+
+impl AuthEventKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuthEventKind::VerificationStarted => "verification_started",
+            AuthEventKind::VerificationSucceeded => "verification_succeeded",
+            AuthEventKind::VerificationFailed => "verification_failed",
+            AuthEventKind::LockedOut => "locked_out",
+            AuthEventKind::SessionStarted => "session_started",
+            AuthEventKind::SessionRevoked => "session_revoked",
+        }
+    }
+}
+
+/// An event waiting to be recorded.
+pub struct Event {
+    pub kind: AuthEventKind,
+    pub person_id: Option<i32>,
+    pub identity: Option<(IdentityKind, String)>,
+    pub detail: Option<String>,
+}
+
+impl Event {
+    pub fn new(kind: AuthEventKind) -> Event {
+        Event {
+            kind,
+            person_id: None,
+            identity: None,
+            detail: None,
+        }
+    }
+
+    pub fn person(mut self, person_id: i32) -> Event {
+        self.person_id = Some(person_id);
+        self
+    }
+
+    pub fn identity(mut self, kind: IdentityKind, identifier: String) -> Event {
+        self.identity = Some((kind, identifier));
+        self
+    }
+
+    pub fn detail(mut self, detail: String) -> Event {
+        self.detail = Some(detail);
+        self
+    }
+}
+
+/// Where events are written, stored as application data.
+#[derive(Clone)]
+pub struct AuditLog {
+    pool: Pool,
+
+    /// Whether to take client addresses from the `X-Forwarded-For` header.
+    trust_forwarded: bool,
+}
+
+impl AuditLog {
+    pub fn new(pool: Pool, trust_forwarded: bool) -> AuditLog {
+        AuditLog {
+            pool,
+            trust_forwarded,
+        }
+    }
+}
+
+/// Record an event caused by a request, in the background.
+pub fn record(req: &HttpRequest, event: Event) {
+    let log = match req.app_data::<AuditLog>() {
+        Some(log) => log.clone(),
+        None => {
+            error!("Failed to access the audit log!");
+            return;
+        }
+    };
+
+    let user_agent = req
+        .headers()
+        .get(actix_web::http::header::USER_AGENT)
+        .and_then(|user_agent| user_agent.to_str().ok())
+        .map(|user_agent| user_agent.chars().take(512).collect::<String>());
+
+    let query = RecordAuthEvent {
+        kind: event.kind,
+        person_id: event.person_id,
+        identity: event.identity,
+        ip_address: Some(
+            crate::throttle::client_ip(req, log.trust_forwarded)
+                .chars()
+                .take(64)
+                .collect(),
+        ),
+        user_agent,
+        detail: event
+            .detail
+            .map(|detail| detail.chars().take(256).collect()),
+    };
+
+    actix_rt::spawn(async move {
+        if let Err(e) = db::execute(&log.pool, query).await {
+            error!("Failed to record authentication event! Error: {}", e);
+        }
+    });
+}
@@ -0,0 +1,356 @@
+//! Authorization for personal API tokens and staff roles.
+//!
+//! A login session may do anything the person may do, but a personal API token
+//! is limited to the scopes it was minted with. Handlers that scripts should be
+//! able to call take a `Scoped<S>` instead of a `Person`; every other handler
+//! only accepts login sessions.
+//!
+//! Administrative handlers take a `RequirePerson<R>`, which only accepts a login
+//! session for a person whose role is at least `R`.
+
+use std::fmt;
+use std::marker::PhantomData;
+use std::ops::Deref;
+use std::str::FromStr;
+
+use actix_web::{FromRequest, HttpRequest};
+use futures::future::{FutureExt, LocalBoxFuture};
+
+use crate::error::{Error, Result};
+use crate::models::{self, Person};
+
+/// Every personal API token starts with this prefix, which sets it apart from session tokens.
+pub const API_TOKEN_PREFIX: &str = "pat_";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Scope {
+    /// Read a person's drink log.
+    DrinksRead,
+
+    /// Record and delete drinks.
+    DrinksWrite,
+
+    /// Create and modify beers and breweries.
+    CatalogWrite,
+}
+
+impl Scope {
+    pub const ALL: [Scope; 3] = [Scope::DrinksRead, Scope::DrinksWrite, Scope::CatalogWrite];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Scope::DrinksRead => "drinks:read",
+            Scope::DrinksWrite => "drinks:write",
+            Scope::CatalogWrite => "catalog:write",
+        }
+    }
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Scope {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Scope, String> {
+        Scope::ALL
+            .iter()
+            .find(|scope| scope.as_str() == s)
+            .cloned()
+            .ok_or(format!("Unknown scope '{}'", s))
+    }
+}
+
+/// Parse a space separated list of scopes, as in OAuth 2.
+pub fn parse_scopes(scopes: &str) -> std::result::Result<Vec<Scope>, String> {
+    let mut parsed = Vec::new();
+
+    for scope in scopes.split_whitespace() {
+        let scope = Scope::from_str(scope)?;
+
+        if !parsed.contains(&scope) {
+            parsed.push(scope);
+        }
+    }
+
+    Ok(parsed)
+}
+
+/// Read the token from an `Authorization` header value, as described in RFC 6750.
+///
+/// Older clients send the bare token without the `Bearer` scheme, which is still accepted.
+pub fn parse_authorization(value: &str) -> Result<String> {
+    let value = value.trim();
+
+    let token = match value.find(' ') {
+        Some(index) => {
+            let (scheme, token) = value.split_at(index);
+
+            if !scheme.eq_ignore_ascii_case("Bearer") {
+                return Err(Error::MalformedToken);
+            }
+
+            token.trim_start()
+        }
+        None if value.eq_ignore_ascii_case("Bearer") => return Err(Error::MalformedToken),
+        None => value,
+    };
+
+    // The b64token syntax, which every token we hand out fits
+    let body = token.trim_end_matches('=');
+    let valid = !body.is_empty()
+        && body
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-._~+/".contains(c));
+
+    if valid {
+        Ok(token.to_string())
+    } else {
+        Err(Error::MalformedToken)
+    }
+}
+
+/// Type-level marker for a scope that a handler requires.
+pub trait ScopeMarker {
+    const SCOPE: Scope;
+}
+
+pub struct DrinksRead;
+pub struct DrinksWrite;
+pub struct CatalogWrite;
+
+impl ScopeMarker for DrinksRead {
+    const SCOPE: Scope = Scope::DrinksRead;
+}
+
+impl ScopeMarker for DrinksWrite {
+    const SCOPE: Scope = Scope::DrinksWrite;
+}
+
+impl ScopeMarker for CatalogWrite {
+    const SCOPE: Scope = Scope::CatalogWrite;
+}
+
+/// A `Person` authenticated either by a login session, or by a personal
+/// API token that has been granted the scope `S`.
+pub struct Scoped<S: ScopeMarker> {
+    pub person: Person,
+    scope: PhantomData<S>,
+}
+
+impl<S: ScopeMarker> Deref for Scoped<S> {
+    type Target = Person;
+
+    fn deref(&self) -> &Person {
+        &self.person
+    }
+}
+
+impl<S: ScopeMarker + 'static> FromRequest for Scoped<S> {
+    type Error = Error;
+    type Config = ();
+    type Future = LocalBoxFuture<'static, Result<Scoped<S>>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut actix_web::dev::Payload) -> Self::Future {
+        use crate::db::GetApiTokenPerson;
+
+        let token = match models::session_token(req) {
+            Ok(token) => token,
+            Err(e) => return futures::future::ready(Err(e)).boxed_local(),
+        };
+
+        let into_scoped = |res: Result<Person>| {
+            res.map(|person| Scoped {
+                person,
+                scope: PhantomData,
+            })
+        };
+
+        // Anything that isn't an API token is treated as a login session
+        if !token.starts_with(API_TOKEN_PREFIX) {
+            return Person::from_request(req, payload)
+                .map(into_scoped)
+                .boxed_local();
+        }
+
+        let pool = req
+            .app_data::<crate::db::Pool>()
+            .expect("Failed to access database pool!");
+
+        crate::db::execute(
+            &pool,
+            GetApiTokenPerson {
+                token,
+                scope: S::SCOPE,
+            },
+        )
+        .map(into_scoped)
+        .boxed_local()
+    }
+}
+
+/// What a person is allowed to do. Each role can do everything the roles before it can.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Role {
+    Member,
+
+    /// Can correct beer and brewery details.
+    Bartender,
+
+    /// Can also merge duplicate beers and breweries.
+    ClubAdmin,
+
+    /// Can also change other people's roles.
+    SiteAdmin,
+}
+
+impl Role {
+    pub const ALL: [Role; 4] = [
+        Role::Member,
+        Role::Bartender,
+        Role::ClubAdmin,
+        Role::SiteAdmin,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Member => "member",
+            Role::Bartender => "bartender",
+            Role::ClubAdmin => "club_admin",
+            Role::SiteAdmin => "site_admin",
+        }
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Role {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Role, String> {
+        Role::ALL
+            .iter()
+            .find(|role| role.as_str() == s)
+            .cloned()
+            .ok_or(format!("Unknown role '{}'", s))
+    }
+}
+
+/// Type-level marker for the least privileged role that a handler requires.
+pub trait RoleMarker {
+    const ROLE: Role;
+}
+
+pub struct Bartender;
+pub struct ClubAdmin;
+pub struct SiteAdmin;
+
+impl RoleMarker for Bartender {
+    const ROLE: Role = Role::Bartender;
+}
+
+impl RoleMarker for ClubAdmin {
+    const ROLE: Role = Role::ClubAdmin;
+}
+
+impl RoleMarker for SiteAdmin {
+    const ROLE: Role = Role::SiteAdmin;
+}
+
+/// A `Person`, logged in with a session, whose role is at least `R`.
+pub struct RequirePerson<R: RoleMarker> {
+    pub person: Person,
+    role: PhantomData<R>,
+}
+
+impl<R: RoleMarker> Deref for RequirePerson<R> {
+    type Target = Person;
+
+    fn deref(&self) -> &Person {
+        &self.person
+    }
+}
+
+impl<R: RoleMarker + 'static> FromRequest for RequirePerson<R> {
+    type Error = Error;
+    type Config = ();
+    type Future = LocalBoxFuture<'static, Result<RequirePerson<R>>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut actix_web::dev::Payload) -> Self::Future {
+        Person::from_request(req, payload)
+            .map(|res| {
+                let person = res?;
+
+                if person.role() < R::ROLE {
+                    warn!(
+                        "Person {} with role {} needs to be a {}",
+                        person.id,
+                        person.role(),
+                        R::ROLE
+                    );
+
+                    return Err(Error::InsufficientRole);
+                }
+
+                Ok(RequirePerson {
+                    person,
+                    role: PhantomData,
+                })
+            })
+            .boxed_local()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_scopes() {
+        assert_eq!(
+            Ok(vec![Scope::DrinksRead, Scope::CatalogWrite]),
+            parse_scopes(" drinks:read  catalog:write drinks:read")
+        );
+        assert_eq!(Ok(vec![]), parse_scopes(""));
+        assert!(parse_scopes("drinks:read drinks:delete").is_err());
+    }
+
+    #[test]
+    fn test_parse_authorization() {
+        assert_eq!("abc+/1==", parse_authorization("Bearer abc+/1==").unwrap());
+        assert_eq!("pat_abc", parse_authorization("bearer  pat_abc").unwrap());
+        assert_eq!("abc", parse_authorization("abc").unwrap());
+
+        for malformed in &[
+            "",
+            "Bearer",
+            "Bearer ",
+            "Basic abc",
+            "Bearer a b",
+            "Bearer a,b",
+        ] {
+            match parse_authorization(malformed) {
+                Err(Error::MalformedToken) => {}
+                other => panic!("'{}' parsed as {:?}", malformed, other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_roles() {
+        for role in Role::ALL.iter() {
+            assert_eq!(Ok(*role), Role::from_str(role.as_str()));
+        }
+
+        assert!(Role::Member < Role::Bartender);
+        assert!(Role::ClubAdmin < Role::SiteAdmin);
+        assert!(Role::from_str("admin").is_err());
+    }
+}
@@ -0,0 +1,260 @@
+use actix_web::cookie::SameSite;
+use chrono::Duration;
+
+use std::str::FromStr;
+
+/// Settings controlling how long login sessions stay valid.
+#[derive(Clone)]
+pub struct SessionConfig {
+    /// How long a new session is valid for, and how far sliding renewal pushes out its expiry.
+    pub lifetime: Duration,
+
+    /// Whether activity on a session pushes its expiry forward by `lifetime`.
+    pub sliding: bool,
+
+    /// The absolute maximum age of a session, regardless of renewal.
+    pub max_lifetime: Duration,
+
+    /// Set if browsers may keep their session in a cookie.
+    pub cookie: Option<CookieConfig>,
+}
+
+impl SessionConfig {
+    /// Read session settings from the environment.
+    ///
+    /// - `SESSION_LIFETIME_DAYS`: defaults to 14.
+    /// - `SESSION_SLIDING_RENEWAL`: `true` to enable sliding renewal, defaults to `false`.
+    /// - `SESSION_MAX_LIFETIME_DAYS`: defaults to 90.
+    /// - `SESSION_COOKIES`: `true` to enable cookie sessions, see `CookieConfig::from_env`.
+    pub fn from_env() -> SessionConfig {
+        let lifetime =
+            i64::from_str(&std::env::var("SESSION_LIFETIME_DAYS").unwrap_or("14".into()))
+                .expect("Failed to parse $SESSION_LIFETIME_DAYS!");
+
+        let sliding =
+            bool::from_str(&std::env::var("SESSION_SLIDING_RENEWAL").unwrap_or("false".into()))
+                .expect("Failed to parse $SESSION_SLIDING_RENEWAL!");
+
+        let max_lifetime =
+            i64::from_str(&std::env::var("SESSION_MAX_LIFETIME_DAYS").unwrap_or("90".into()))
+                .expect("Failed to parse $SESSION_MAX_LIFETIME_DAYS!");
+
+        SessionConfig {
+            lifetime: Duration::days(lifetime),
+            sliding,
+            max_lifetime: Duration::days(max_lifetime),
+            cookie: if var("SESSION_COOKIES", "false") {
+                Some(CookieConfig::from_env())
+            } else {
+                None
+            },
+        }
+    }
+}
+
+/// Settings for the cookies that hold browser sessions.
+#[derive(Clone)]
+pub struct CookieConfig {
+    /// The HttpOnly cookie holding the session token.
+    pub name: String,
+
+    /// The cookie holding the CSRF token, which scripts must be able to read.
+    pub csrf_name: String,
+
+    /// Only send the cookies over HTTPS; disable for local development.
+    pub secure: bool,
+
+    pub same_site: SameSite,
+
+    /// The domain to scope the cookies to, if not just the API's own host.
+    pub domain: Option<String>,
+}
+
+impl CookieConfig {
+    /// Read cookie settings from the environment.
+    ///
+    /// - `SESSION_COOKIE_NAME`: defaults to `mugclub_session`.
+    /// - `SESSION_CSRF_COOKIE_NAME`: defaults to `mugclub_csrf`.
+    /// - `SESSION_COOKIE_SECURE`: defaults to `true`.
+    /// - `SESSION_COOKIE_SAME_SITE`: `strict`, `lax` or `none`, defaults to `strict`.
+    /// - `SESSION_COOKIE_DOMAIN`: unset by default.
+    pub fn from_env() -> CookieConfig {
+        let same_site = match std::env::var("SESSION_COOKIE_SAME_SITE")
+            .unwrap_or("strict".into())
+            .to_lowercase()
+            .as_str()
+        {
+            "strict" => SameSite::Strict,
+            "lax" => SameSite::Lax,
+            "none" => SameSite::None,
+            _ => panic!("Failed to parse $SESSION_COOKIE_SAME_SITE!"),
+        };
+
+        CookieConfig {
+            name: std::env::var("SESSION_COOKIE_NAME").unwrap_or("mugclub_session".into()),
+            csrf_name: std::env::var("SESSION_CSRF_COOKIE_NAME").unwrap_or("mugclub_csrf".into()),
+            secure: var("SESSION_COOKIE_SECURE", "true"),
+            same_site,
+            domain: std::env::var("SESSION_COOKIE_DOMAIN").ok(),
+        }
+    }
+}
+
+/// Settings for logging in with an emailed link.
+#[derive(Clone)]
+pub struct EmailLoginConfig {
+    /// The front-end page that login links point to; the token is appended as `?token=`.
+    pub link_url: String,
+
+    /// How long a login link may be used for.
+    pub lifetime: Duration,
+}
+
+impl EmailLoginConfig {
+    /// Read email login settings from the environment.
+    ///
+    /// - `EMAIL_LOGIN_URL`: defaults to `http://localhost:8080/login/email`.
+    /// - `EMAIL_LOGIN_LIFETIME_MINUTES`: defaults to 15.
+    pub fn from_env() -> EmailLoginConfig {
+        let lifetime =
+            i64::from_str(&std::env::var("EMAIL_LOGIN_LIFETIME_MINUTES").unwrap_or("15".into()))
+                .expect("Failed to parse $EMAIL_LOGIN_LIFETIME_MINUTES!");
+
+        EmailLoginConfig {
+            link_url: std::env::var("EMAIL_LOGIN_URL")
+                .unwrap_or("http://localhost:8080/login/email".into()),
+            lifetime: Duration::minutes(lifetime),
+        }
+    }
+}
+
+/// Settings for deleting accounts.
+#[derive(Clone)]
+pub struct DeletionConfig {
+    /// How long a deleted account can still be restored before it is purged.
+    pub grace: Duration,
+}
+
+impl DeletionConfig {
+    /// Read account deletion settings from the environment.
+    ///
+    /// - `ACCOUNT_DELETION_GRACE_DAYS`: defaults to 7.
+    pub fn from_env() -> DeletionConfig {
+        DeletionConfig {
+            grace: Duration::days(var("ACCOUNT_DELETION_GRACE_DAYS", "7")),
+        }
+    }
+}
+
+/// A number of attempts allowed within a window of time.
+#[derive(Clone, Copy, Debug)]
+pub struct Limit {
+    pub max: u32,
+    pub window: Duration,
+}
+
+/// Rate limits for sending and checking login codes.
+#[derive(Clone)]
+pub struct ThrottleConfig {
+    /// Codes sent to a single phone number or email address.
+    pub start_per_identity: Limit,
+
+    /// Codes sent on behalf of a single IP address.
+    pub start_per_ip: Limit,
+
+    /// Codes checked for a single phone number.
+    pub verify_per_identity: Limit,
+
+    /// Codes checked from a single IP address.
+    pub verify_per_ip: Limit,
+
+    /// Wrong codes for a single phone number before it is locked out, and for how long.
+    pub failed_codes: Limit,
+
+    /// Whether to take client addresses from the last `X-Forwarded-For` entry,
+    /// which is the one appended by a proxy in front of the app.
+    pub trust_forwarded: bool,
+}
+
+impl ThrottleConfig {
+    /// Read rate limits from the environment.
+    ///
+    /// - `THROTTLE_WINDOW_MINUTES`: the window for the limits below, defaults to 15.
+    /// - `THROTTLE_START_PER_IDENTITY`: defaults to 3.
+    /// - `THROTTLE_START_PER_IP`: defaults to 10.
+    /// - `THROTTLE_VERIFY_PER_IDENTITY`: defaults to 10.
+    /// - `THROTTLE_VERIFY_PER_IP`: defaults to 30.
+    /// - `THROTTLE_MAX_FAILED_CODES`: defaults to 5.
+    /// - `THROTTLE_LOCKOUT_MINUTES`: defaults to 30.
+    /// - `THROTTLE_TRUST_FORWARDED`: `true` when running behind a proxy, defaults to `false`.
+    pub fn from_env() -> ThrottleConfig {
+        let window = Duration::minutes(var("THROTTLE_WINDOW_MINUTES", "15"));
+        let limit = |name, default| Limit {
+            max: var(name, default),
+            window,
+        };
+
+        ThrottleConfig {
+            start_per_identity: limit("THROTTLE_START_PER_IDENTITY", "3"),
+            start_per_ip: limit("THROTTLE_START_PER_IP", "10"),
+            verify_per_identity: limit("THROTTLE_VERIFY_PER_IDENTITY", "10"),
+            verify_per_ip: limit("THROTTLE_VERIFY_PER_IP", "30"),
+            failed_codes: Limit {
+                max: var("THROTTLE_MAX_FAILED_CODES", "5"),
+                window: Duration::minutes(var("THROTTLE_LOCKOUT_MINUTES", "30")),
+            },
+            trust_forwarded: var("THROTTLE_TRUST_FORWARDED", "false"),
+        }
+    }
+}
+
+/// Parse an environment variable, falling back to `default` if it isn't set.
+fn var<T: FromStr>(name: &str, default: &str) -> T {
+    T::from_str(&std::env::var(name).unwrap_or(default.into()))
+        .unwrap_or_else(|_| panic!("Failed to parse ${}!", name))
+}
+
+/// Settings for logging in with an OpenID Connect provider.
+#[derive(Clone)]
+pub struct OidcConfig {
+    /// The provider's issuer URL, under which its discovery document is published.
+    pub issuer: String,
+    pub client_id: String,
+
+    /// Only needed for confidential clients; public clients rely on PKCE alone.
+    pub client_secret: Option<String>,
+
+    /// The front-end page the provider sends people back to, which passes the
+    /// `code` and `state` it receives on to `POST /auth/oidc/verify`.
+    pub redirect_uri: String,
+
+    /// Space separated scopes to request, which must include `openid`.
+    pub scopes: String,
+
+    /// How long a person has to finish logging in with the provider.
+    pub lifetime: Duration,
+}
+
+impl OidcConfig {
+    /// Read OpenID Connect settings from the environment.
+    ///
+    /// Returns `None` unless `OIDC_ISSUER` is set, in which case:
+    ///
+    /// - `OIDC_CLIENT_ID` and `OIDC_REDIRECT_URI` are required.
+    /// - `OIDC_CLIENT_SECRET` is optional.
+    /// - `OIDC_SCOPES`: defaults to `openid`.
+    /// - `OIDC_LOGIN_LIFETIME_MINUTES`: defaults to 10.
+    pub fn from_env() -> Option<OidcConfig> {
+        let issuer = std::env::var("OIDC_ISSUER").ok()?;
+
+        Some(OidcConfig {
+            issuer,
+            client_id: std::env::var("OIDC_CLIENT_ID").expect("OIDC_CLIENT_ID must be set!"),
+            client_secret: std::env::var("OIDC_CLIENT_SECRET").ok(),
+            redirect_uri: std::env::var("OIDC_REDIRECT_URI")
+                .expect("OIDC_REDIRECT_URI must be set!"),
+            scopes: std::env::var("OIDC_SCOPES").unwrap_or("openid".into()),
+            lifetime: Duration::minutes(var("OIDC_LOGIN_LIFETIME_MINUTES", "10")),
+        })
+    }
+}
@@ -0,0 +1,159 @@
+//! Login sessions kept in cookies, for the web front-end.
+//!
+//! A browser can hold its session token in an HttpOnly cookie rather than
+//! somewhere scripts can read it. Browsers attach cookies to requests forged by
+//! other sites too, so any request that could change something must also echo
+//! the CSRF cookie's value in the `X-CSRF-Token` header. Only a script running
+//! on our own origin can read that cookie. Requests that send their token in
+//! the `Authorization` header are never affected.
+
+use actix_web::cookie::{Cookie, CookieBuilder};
+use actix_web::dev::HttpResponseBuilder;
+use actix_web::http::Method;
+use actix_web::{HttpMessage, HttpRequest};
+
+use crate::config::{CookieConfig, SessionConfig};
+use crate::error::{Error, Result};
+use crate::token;
+
+/// The header that must repeat the CSRF cookie on unsafe requests.
+pub const CSRF_HEADER: &str = "X-CSRF-Token";
+
+/// Add the session and CSRF cookies for a newly started session to a response.
+pub fn set_session(builder: &mut HttpResponseBuilder, config: &SessionConfig, session_token: &str) {
+    let cookie_config = match config.cookie {
+        Some(ref cookie_config) => cookie_config,
+        None => return,
+    };
+
+    // The session itself decides when it expires, the cookie just needs to outlive it
+    let max_age = config.max_lifetime;
+
+    builder.cookie(
+        cookie(cookie_config, &cookie_config.name, session_token)
+            .http_only(true)
+            .max_age(max_age)
+            .finish(),
+    );
+    builder.cookie(
+        cookie(
+            cookie_config,
+            &cookie_config.csrf_name,
+            &token::generate_hex(32),
+        )
+        .http_only(false)
+        .max_age(max_age)
+        .finish(),
+    );
+}
+
+/// Tell the browser to forget its session and CSRF cookies.
+pub fn clear_session(builder: &mut HttpResponseBuilder, config: &CookieConfig) {
+    for name in &[&config.name, &config.csrf_name] {
+        builder.cookie(
+            cookie(config, name, "")
+                .max_age(chrono::Duration::zero())
+                .finish(),
+        );
+    }
+}
+
+/// Read the session token from the session cookie, if the request has one.
+///
+/// Fails if the request could change something and doesn't carry a matching CSRF token.
+pub fn session_token(req: &HttpRequest, config: &CookieConfig) -> Result<Option<String>> {
+    let session = match req.cookie(&config.name) {
+        Some(session) if !session.value().is_empty() => session,
+        _ => return Ok(None),
+    };
+
+    let safe = [Method::GET, Method::HEAD, Method::OPTIONS].contains(req.method());
+
+    if !safe {
+        let expected = req.cookie(&config.csrf_name);
+        let submitted = req
+            .headers()
+            .get(CSRF_HEADER)
+            .and_then(|header| header.to_str().ok());
+
+        match (expected, submitted) {
+            (Some(ref expected), Some(submitted))
+                if !expected.value().is_empty()
+                    && constant_time_eq(expected.value(), submitted) => {}
+            _ => return Err(Error::CsrfFailed),
+        }
+    }
+
+    Ok(Some(session.value().to_string()))
+}
+
+fn cookie(config: &CookieConfig, name: &str, value: &str) -> CookieBuilder<'static> {
+    let builder = Cookie::build(name.to_string(), value.to_string())
+        .path("/")
+        .secure(config.secure)
+        .same_site(config.same_site);
+
+    match config.domain {
+        Some(ref domain) => builder.domain(domain.clone()),
+        None => builder,
+    }
+}
+
+/// Compare two tokens without leaking how much of them matched through timing.
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |acc, (x, y)| acc | (x ^ y))
+            == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::cookie::SameSite;
+    use actix_web::test::TestRequest;
+
+    fn config() -> CookieConfig {
+        CookieConfig {
+            name: "session".into(),
+            csrf_name: "csrf".into(),
+            secure: true,
+            same_site: SameSite::Strict,
+            domain: None,
+        }
+    }
+
+    #[test]
+    fn test_safe_requests_skip_csrf() {
+        let req = TestRequest::get()
+            .cookie(Cookie::new("session", "abc"))
+            .to_http_request();
+
+        assert_eq!(Some("abc".into()), session_token(&req, &config()).unwrap());
+        assert_eq!(
+            None,
+            session_token(&TestRequest::get().to_http_request(), &config()).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_unsafe_requests_need_csrf() {
+        let request = || {
+            TestRequest::post()
+                .cookie(Cookie::new("session", "abc"))
+                .cookie(Cookie::new("csrf", "1234"))
+        };
+
+        let missing = request().to_http_request();
+        let wrong = request().header(CSRF_HEADER, "4321").to_http_request();
+        let right = request().header(CSRF_HEADER, "1234").to_http_request();
+
+        assert!(session_token(&missing, &config()).is_err());
+        assert!(session_token(&wrong, &config()).is_err());
+        assert_eq!(
+            Some("abc".into()),
+            session_token(&right, &config()).unwrap()
+        );
+    }
+}
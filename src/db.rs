@@ -1,21 +1,33 @@
 use actix_web::web;
 use actix_web::Error as AWError;
 use chrono::naive::NaiveDate;
-use chrono::{Duration, Utc};
+use chrono::{DateTime, Duration, Utc};
 use diesel;
+use diesel::connection::Connection as DieselConnection;
 use diesel::prelude::*;
 use diesel::r2d2;
-use diesel::sql_types::Text;
+use diesel::sql_types::{Nullable, Text, Timestamptz};
 use futures::future::Future;
 use futures::prelude::*;
 use regex::Regex;
-use textnonce::TextNonce;
 
+use std::cmp;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
 use std::marker::Send;
+use std::str::FromStr;
 
+use super::audit::AuthEventKind;
+use super::auth::{Role, Scope, API_TOKEN_PREFIX};
+use super::config::SessionConfig;
+use super::drinks::Rating;
 use super::error::{Error, Result};
+use super::import;
 use super::models;
+use super::phone::PhoneNumber;
+use super::photos;
 use super::schema;
+use super::token;
 
 pub type Pool = r2d2::Pool<r2d2::ConnectionManager<PgConnection>>;
 pub type Connection = r2d2::PooledConnection<r2d2::ConnectionManager<PgConnection>>;
@@ -24,6 +36,8 @@ pub type Connection = r2d2::PooledConnection<r2d2::ConnectionManager<PgConnectio
 // See: https://github.com/diesel-rs/diesel/issues/560#issuecomment-270199166
 sql_function!(fn lower(x: Text) -> Text);
 
+sql_function!(fn coalesce(x: Nullable<Timestamptz>, y: Timestamptz) -> Timestamptz);
+
 pub trait Query {
     type Output: Send;
 
@@ -54,46 +68,558 @@ pub fn execute<T: Query + Send + 'static>(
     )
 }
 
-#[derive(Serialize, Queryable)]
+#[derive(Serialize)]
 #[serde(rename = "drink")]
 pub struct ExpandedDrink {
     pub id: i32,
     pub drank_on: NaiveDate,
+    pub beer_id: i32,
     pub name: String,
+    pub brewery_id: i32,
     pub brewery: String,
-    pub rating: i16,
+    pub rating: Rating,
     pub comment: Option<String>,
+
+    /// Whether people other than the drinker can look at the drink.
+    pub shared: bool,
+    pub drank_at: Option<DateTime<Utc>>,
+
+    /// How the beer was served: `draft`, `can`, `bottle` or `cask`.
+    pub serving: Option<String>,
+    pub volume_ml: Option<i32>,
+    pub price_cents: Option<i32>,
+    pub abv: Option<f32>,
+    pub venue: Option<String>,
+
+    pub tasting: Option<TastingNotes>,
+    pub photos: Vec<Photo>,
+}
+
+type DrinkColumns = (
+    i32,
+    NaiveDate,
+    i32,
+    String,
+    i32,
+    String,
+    Rating,
+    Option<String>,
+    bool,
+    Option<DateTime<Utc>>,
+    Option<String>,
+    Option<i32>,
+    Option<i32>,
+    Option<f32>,
+    Option<String>,
+    TastingRow,
+);
+
+/// The columns an `ExpandedDrink` is selected from.
+type DrinkSelection = (
+    schema::drink::id,
+    schema::drink::drank_on,
+    schema::beer::id,
+    schema::beer::name,
+    schema::brewery::id,
+    schema::brewery::name,
+    schema::drink::rating_quarters,
+    schema::drink::comment,
+    schema::drink::shared,
+    schema::drink::drank_at,
+    schema::drink::serving,
+    schema::drink::volume_ml,
+    schema::drink::price_cents,
+    schema::drink::abv,
+    schema::drink::venue,
+    diesel::dsl::Nullable<(
+        schema::tasting_note::drink_id,
+        schema::tasting_note::appearance,
+        schema::tasting_note::aroma,
+        schema::tasting_note::flavor,
+        schema::tasting_note::mouthfeel,
+        schema::tasting_note::overall,
+    )>,
+);
+
+/// Select an `ExpandedDrink` from drinks joined with their beer, its brewery
+/// and their tasting notes.
+fn drink_columns() -> DrinkSelection {
+    use super::schema::beer;
+    use super::schema::brewery;
+    use super::schema::drink;
+    use super::schema::tasting_note;
+
+    (
+        drink::id,
+        drink::drank_on,
+        beer::id,
+        beer::name,
+        brewery::id,
+        brewery::name,
+        drink::rating_quarters,
+        drink::comment,
+        drink::shared,
+        drink::drank_at,
+        drink::serving,
+        drink::volume_ml,
+        drink::price_cents,
+        drink::abv,
+        drink::venue,
+        (
+            tasting_note::drink_id,
+            tasting_note::appearance,
+            tasting_note::aroma,
+            tasting_note::flavor,
+            tasting_note::mouthfeel,
+            tasting_note::overall,
+        )
+            .nullable(),
+    )
+}
+
+// Photos and flavor descriptors are loaded by their own queries, so drinks are
+// built from their rows by hand rather than derived.
+impl<ST, DB> Queryable<ST, DB> for ExpandedDrink
+where
+    DB: diesel::backend::Backend,
+    DrinkColumns: Queryable<ST, DB>,
+{
+    type Row = <DrinkColumns as Queryable<ST, DB>>::Row;
+
+    fn build(row: Self::Row) -> Self {
+        let (
+            id,
+            drank_on,
+            beer_id,
+            name,
+            brewery_id,
+            brewery,
+            rating,
+            comment,
+            shared,
+            drank_at,
+            serving,
+            volume_ml,
+            price_cents,
+            abv,
+            venue,
+            tasting,
+        ) = DrinkColumns::build(row);
+
+        ExpandedDrink {
+            id,
+            drank_on,
+            beer_id,
+            name,
+            brewery_id,
+            brewery,
+            rating,
+            comment,
+            shared,
+            drank_at,
+            serving,
+            volume_ml,
+            price_cents,
+            abv,
+            venue,
+            tasting: tasting.into(),
+            photos: Vec::new(),
+        }
+    }
+}
+
+/// A photo of a drink, such as the pour or the label.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Photo {
+    pub id: i32,
+    pub url: String,
+    pub thumbnail_url: String,
+    pub width: i32,
+    pub height: i32,
+}
+
+impl From<models::DrinkPhoto> for Photo {
+    fn from(photo: models::DrinkPhoto) -> Photo {
+        Photo {
+            id: photo.id,
+            url: photos::url(&photo.blob_key),
+            thumbnail_url: photos::url(&photo.thumbnail_key),
+            width: photo.width,
+            height: photo.height,
+        }
+    }
+}
+
+/// Structured notes on a drink, scored like a BJCP score sheet.
+///
+/// Each score is out of its own maximum: appearance 3, aroma 12, flavor 20,
+/// mouthfeel 5 and overall impression 10, for 50 in all.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct TastingNotes {
+    pub appearance: Option<i16>,
+    pub aroma: Option<i16>,
+    pub flavor: Option<i16>,
+    pub mouthfeel: Option<i16>,
+    pub overall: Option<i16>,
+
+    /// Flavors picked out in the drink, such as `citrus` or `diacetyl`.
+    pub descriptors: Vec<String>,
+}
+
+type TastingColumns = (
+    i32,
+    Option<i16>,
+    Option<i16>,
+    Option<i16>,
+    Option<i16>,
+    Option<i16>,
+);
+
+/// A drink's tasting note scores as loaded through a left join, which are all
+/// null when the drink has no notes. Descriptors are loaded separately.
+pub struct TastingRow(Option<TastingColumns>);
+
+impl<ST, DB> Queryable<ST, DB> for TastingRow
+where
+    DB: diesel::backend::Backend,
+    Option<TastingColumns>: Queryable<ST, DB>,
+{
+    type Row = <Option<TastingColumns> as Queryable<ST, DB>>::Row;
+
+    fn build(row: Self::Row) -> Self {
+        TastingRow(Queryable::build(row))
+    }
+}
+
+impl From<TastingRow> for Option<TastingNotes> {
+    fn from(row: TastingRow) -> Option<TastingNotes> {
+        row.0.map(
+            |(_, appearance, aroma, flavor, mouthfeel, overall)| TastingNotes {
+                appearance,
+                aroma,
+                flavor,
+                mouthfeel,
+                overall,
+                descriptors: Vec::new(),
+            },
+        )
+    }
+}
+
+/// Fill in what is loaded separately from the drinks themselves: their photos
+/// and the flavor descriptors of any tasting notes.
+fn attach_details(conn: &PgConnection, drinks: &mut [ExpandedDrink]) -> QueryResult<()> {
+    attach_descriptors(conn, drinks)?;
+    attach_photos(conn, drinks)
+}
+
+fn attach_photos(conn: &PgConnection, drinks: &mut [ExpandedDrink]) -> QueryResult<()> {
+    use super::schema::drink_photo;
+
+    if drinks.is_empty() {
+        return Ok(());
+    }
+
+    let mut photos = HashMap::<i32, Vec<Photo>>::new();
+
+    for photo in drink_photo::table
+        .filter(
+            drink_photo::drink_id.eq_any(drinks.iter().map(|drink| drink.id).collect::<Vec<_>>()),
+        )
+        .order((drink_photo::created_at.asc(), drink_photo::id.asc()))
+        .load::<models::DrinkPhoto>(conn)?
+    {
+        photos.entry(photo.drink_id).or_default().push(photo.into());
+    }
+
+    for drink in drinks.iter_mut() {
+        drink.photos = photos.remove(&drink.id).unwrap_or_default();
+    }
+
+    Ok(())
+}
+
+fn attach_descriptors(conn: &PgConnection, drinks: &mut [ExpandedDrink]) -> QueryResult<()> {
+    use super::schema::flavor_descriptor;
+    use super::schema::tasting_note_descriptor;
+
+    let noted = drinks
+        .iter()
+        .filter(|drink| drink.tasting.is_some())
+        .map(|drink| drink.id)
+        .collect::<Vec<_>>();
+
+    if noted.is_empty() {
+        return Ok(());
+    }
+
+    let mut descriptors = HashMap::<i32, Vec<String>>::new();
+
+    for (drink_id, name) in tasting_note_descriptor::table
+        .inner_join(flavor_descriptor::table)
+        .select((tasting_note_descriptor::drink_id, flavor_descriptor::name))
+        .filter(tasting_note_descriptor::drink_id.eq_any(noted))
+        .order(flavor_descriptor::name.asc())
+        .load::<(i32, String)>(conn)?
+    {
+        descriptors.entry(drink_id).or_default().push(name);
+    }
+
+    for drink in drinks.iter_mut() {
+        if let (Some(tasting), Some(names)) =
+            (drink.tasting.as_mut(), descriptors.remove(&drink.id))
+        {
+            tasting.descriptors = names;
+        }
+    }
+
+    Ok(())
 }
 
 /*************************************/
-/** Create Drink message            **/
+/** Log Drink message               **/
 /*************************************/
 
-pub struct CreateDrink {
+/// What happened when saving a drink.
+pub enum DrinkOutcome {
+    Saved(ExpandedDrink),
+
+    /// The person has no drink with that id.
+    NotFound,
+
+    /// Tasting notes picked out flavors that aren't in the list of descriptors.
+    UnknownDescriptors(Vec<String>),
+}
+
+/// Record a drink, adding its beer and brewery to the catalog if they aren't
+/// listed yet. Everything happens in one transaction.
+pub struct LogDrink {
     pub person_id: i32,
     pub drank_on: NaiveDate,
-    pub beer_id: i32,
-    pub rating: i16,
+    pub brewery: String,
+    pub beer: String,
+    pub rating: Rating,
     pub comment: Option<String>,
+    pub shared: bool,
+    pub details: DrinkDetails,
+}
+
+/// The optional details of a drink, beyond what every drink has.
+#[derive(Clone, Default)]
+pub struct DrinkDetails {
+    pub drank_at: Option<DateTime<Utc>>,
+    pub serving: Option<String>,
+    pub volume_ml: Option<i32>,
+    pub price_cents: Option<i32>,
+    pub abv: Option<f32>,
+    pub venue: Option<String>,
 }
 
-impl Query for CreateDrink {
-    type Output = models::Drink;
+impl Query for LogDrink {
+    type Output = DrinkOutcome;
 
     fn execute(&self, conn: Connection) -> Result<Self::Output> {
         use self::schema::drink::dsl::*;
 
-        let new_drink = models::NewDrink {
-            person_id: &self.person_id,
-            drank_on: &self.drank_on,
-            beer_id: &self.beer_id,
-            rating: &self.rating,
-            comment: self.comment.as_ref(),
+        conn.transaction::<_, Error, _>(|| {
+            let found = find_or_create_beer(&conn, &self.brewery, &self.beer)?;
+
+            let new_drink = models::NewDrink {
+                person_id: &self.person_id,
+                drank_on: &self.drank_on,
+                beer_id: &found.id,
+                rating: &self.rating,
+                comment: self.comment.as_ref(),
+                shared: &self.shared,
+                drank_at: self.details.drank_at.as_ref(),
+                serving: self.details.serving.as_ref(),
+                volume_ml: self.details.volume_ml.as_ref(),
+                price_cents: self.details.price_cents.as_ref(),
+                abv: self.details.abv.as_ref(),
+                venue: self.details.venue.as_ref(),
+            };
+
+            let logged = diesel::insert_into(drink)
+                .values(&new_drink)
+                .get_result::<models::Drink>(&conn)?;
+
+            Ok(DrinkOutcome::Saved(expanded_drink(&conn, logged.id)?))
+        })
+    }
+}
+
+/// Look up a brewery and one of its beers by name, adding either of them to the
+/// catalog if they aren't listed yet.
+///
+/// This must be called inside a transaction.
+fn find_or_create_beer(
+    conn: &PgConnection,
+    brewery_name: &str,
+    beer_name: &str,
+) -> QueryResult<models::Beer> {
+    let get_brewery = GetBreweryByName {
+        name: brewery_name.to_string(),
+    };
+
+    let found_brewery = match get_brewery.find(conn)? {
+        Some(found) => found,
+        None => CreateBrewery {
+            name: brewery_name.to_string(),
+        }
+        .create(conn)?,
+    };
+
+    let get_beer = GetBeerByName {
+        name: beer_name.to_string(),
+        brewery_id: found_brewery.id,
+    };
+
+    match get_beer.find(conn)? {
+        Some(found) => Ok(found),
+        None => CreateBeer {
+            name: beer_name.to_string(),
+            brewery_id: found_brewery.id,
+        }
+        .create(conn),
+    }
+}
+
+/*************************************/
+/*************************************/
+
+/// Find a brewery by name, ignoring case.
+pub struct GetBreweryByName {
+    pub name: String,
+}
+
+impl GetBreweryByName {
+    pub fn find(&self, conn: &PgConnection) -> QueryResult<Option<models::Brewery>> {
+        use super::schema::brewery::dsl::*;
+
+        brewery
+            .filter(lower(name).eq(&self.name.to_lowercase()))
+            .first::<models::Brewery>(conn)
+            .optional()
+    }
+}
+
+impl Query for GetBreweryByName {
+    type Output = Option<models::Brewery>;
+
+    fn execute(&self, conn: Connection) -> Result<Self::Output> {
+        Ok(self.find(&conn)?)
+    }
+}
+
+/*************************************/
+/*************************************/
+
+/// Find one of a brewery's beers by name, ignoring case.
+pub struct GetBeerByName {
+    pub name: String,
+    pub brewery_id: i32,
+}
+
+impl GetBeerByName {
+    pub fn find(&self, conn: &PgConnection) -> QueryResult<Option<models::Beer>> {
+        use super::schema::beer::dsl::*;
+
+        beer.filter(
+            lower(name)
+                .eq(&self.name.to_lowercase())
+                .and(brewery_id.eq(&self.brewery_id)),
+        )
+        .first::<models::Beer>(conn)
+        .optional()
+    }
+}
+
+impl Query for GetBeerByName {
+    type Output = Option<models::Beer>;
+
+    fn execute(&self, conn: Connection) -> Result<Self::Output> {
+        Ok(self.find(&conn)?)
+    }
+}
+
+/*************************************/
+/*************************************/
+
+/// Add a brewery to the catalog.
+///
+/// If someone else adds the same name at the same time, the unique index makes
+/// the insert a no-op and their brewery is returned instead.
+pub struct CreateBrewery {
+    pub name: String,
+}
+
+impl CreateBrewery {
+    pub fn create(&self, conn: &PgConnection) -> QueryResult<models::Brewery> {
+        use super::schema::brewery::dsl::*;
+
+        let new_brewery = models::NewBrewery { name: &self.name };
+
+        diesel::insert_into(brewery)
+            .values(new_brewery)
+            .on_conflict_do_nothing()
+            .execute(conn)?;
+
+        GetBreweryByName {
+            name: self.name.clone(),
+        }
+        .find(conn)?
+        .ok_or(diesel::result::Error::NotFound)
+    }
+}
+
+impl Query for CreateBrewery {
+    type Output = models::Brewery;
+
+    fn execute(&self, conn: Connection) -> Result<Self::Output> {
+        Ok(self.create(&conn)?)
+    }
+}
+
+/*************************************/
+/*************************************/
+
+/// Add a beer to a brewery in the catalog.
+///
+/// If someone else adds the same name at the same time, the unique index makes
+/// the insert a no-op and their beer is returned instead.
+pub struct CreateBeer {
+    pub name: String,
+    pub brewery_id: i32,
+}
+
+impl CreateBeer {
+    pub fn create(&self, conn: &PgConnection) -> QueryResult<models::Beer> {
+        use super::schema::beer::dsl::*;
+
+        let new_beer = models::NewBeer {
+            name: &self.name,
+            brewery_id: self.brewery_id,
         };
 
-        Ok(diesel::insert_into(drink)
-            .values(&new_drink)
-            .get_result(&conn)?)
+        diesel::insert_into(beer)
+            .values(new_beer)
+            .on_conflict_do_nothing()
+            .execute(conn)?;
+
+        GetBeerByName {
+            name: self.name.clone(),
+            brewery_id: self.brewery_id,
+        }
+        .find(conn)?
+        .ok_or(diesel::result::Error::NotFound)
+    }
+}
+
+impl Query for CreateBeer {
+    type Output = models::Beer;
+
+    fn execute(&self, conn: Connection) -> Result<Self::Output> {
+        Ok(self.create(&conn)?)
     }
 }
 
@@ -101,317 +627,2629 @@ impl Query for CreateDrink {
 /** Get Drinks query                **/
 /*************************************/
 
+/// How a page of drinks is ordered. Ties are broken by the drink's id.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DrinkSort {
+    pub key: DrinkSortKey,
+    pub descending: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DrinkSortKey {
+    DrankOn,
+    Rating,
+}
+
+impl Default for DrinkSort {
+    fn default() -> DrinkSort {
+        DrinkSort {
+            key: DrinkSortKey::DrankOn,
+            descending: false,
+        }
+    }
+}
+
+impl fmt::Display for DrinkSort {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.descending {
+            f.write_str("-")?;
+        }
+
+        f.write_str(match self.key {
+            DrinkSortKey::DrankOn => "drank_on",
+            DrinkSortKey::Rating => "rating",
+        })
+    }
+}
+
+impl FromStr for DrinkSort {
+    type Err = String;
+
+    /// Parse a sort such as `rating`, or `-rating` for highest first.
+    fn from_str(s: &str) -> std::result::Result<DrinkSort, String> {
+        let (descending, key) = match s.trim() {
+            key if key.starts_with('-') => (true, &key[1..]),
+            key => (false, key),
+        };
+
+        let key = match key {
+            "drank_on" => DrinkSortKey::DrankOn,
+            "rating" => DrinkSortKey::Rating,
+            _ => return Err(format!("Unknown sort '{}', expected drank_on or rating", s)),
+        };
+
+        Ok(DrinkSort { key, descending })
+    }
+}
+
+/// Where a page of drinks left off, handed to clients as an opaque string.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DrinkCursor {
+    /// The sort the cursor was made for, as it can't be used with any other.
+    pub sort: DrinkSort,
+    pub drank_on: NaiveDate,
+    pub rating: Rating,
+    pub id: i32,
+}
+
+impl DrinkCursor {
+    fn after(drink: &ExpandedDrink, sort: DrinkSort) -> DrinkCursor {
+        DrinkCursor {
+            sort,
+            drank_on: drink.drank_on,
+            rating: drink.rating,
+            id: drink.id,
+        }
+    }
+}
+
+impl fmt::Display for DrinkCursor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&base64::encode_config(
+            format!(
+                "{}|{}|{}|{}",
+                self.sort, self.drank_on, self.rating, self.id
+            ),
+            base64::URL_SAFE_NO_PAD,
+        ))
+    }
+}
+
+impl FromStr for DrinkCursor {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<DrinkCursor, String> {
+        let invalid = || "Invalid cursor".to_string();
+        let decoded = base64::decode_config(s, base64::URL_SAFE_NO_PAD)
+            .ok()
+            .and_then(|decoded| String::from_utf8(decoded).ok())
+            .ok_or_else(invalid)?;
+
+        match decoded.split('|').collect::<Vec<_>>().as_slice() {
+            [sort, drank_on, rating, id] => Ok(DrinkCursor {
+                sort: sort.parse().map_err(|_| invalid())?,
+                drank_on: drank_on.parse().map_err(|_| invalid())?,
+                rating: rating.parse().map_err(|_| invalid())?,
+                id: id.parse().map_err(|_| invalid())?,
+            }),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// Narrow down which drinks are listed; unset fields don't filter anything.
+#[derive(Clone, Default)]
+pub struct DrinkFilter {
+    /// Drinks had on or after this date.
+    pub from: Option<NaiveDate>,
+
+    /// Drinks had on or before this date.
+    pub to: Option<NaiveDate>,
+    pub brewery_id: Option<i32>,
+    pub beer_id: Option<i32>,
+    pub min_rating: Option<Rating>,
+    pub max_rating: Option<Rating>,
+    pub has_comment: Option<bool>,
+    pub has_tasting_notes: Option<bool>,
+
+    /// Drinks with at least this overall impression score in their tasting notes.
+    pub min_overall: Option<i16>,
+
+    /// Drinks whose tasting notes pick out every one of these flavors.
+    pub descriptors: Vec<String>,
+}
+
+/// A page of drinks, with a cursor for the next one if there are more.
+pub struct DrinkPage {
+    pub drinks: Vec<ExpandedDrink>,
+    pub next: Option<DrinkCursor>,
+}
+
 #[derive(Clone)]
 pub struct GetDrinks {
     pub person_id: i32,
+    pub filter: DrinkFilter,
+    pub sort: DrinkSort,
+
+    /// Continue from where a previous page left off.
+    pub after: Option<DrinkCursor>,
+
+    /// The most drinks in a page, or every drink if there is no limit.
+    pub limit: Option<i64>,
 }
 
 impl Query for GetDrinks {
-    type Output = Vec<ExpandedDrink>;
+    type Output = DrinkPage;
 
     fn execute(&self, conn: Connection) -> Result<Self::Output> {
         use super::schema::beer;
-        use super::schema::beer::dsl::*;
         use super::schema::brewery;
         use super::schema::drink;
-        use super::schema::drink::dsl::*;
+        use super::schema::flavor_descriptor;
+        use super::schema::tasting_note;
+        use super::schema::tasting_note_descriptor;
 
-        Ok(drink
-            .inner_join(beer)
+        let mut query = drink::table
+            .inner_join(beer::table)
             .inner_join(brewery::table.on(beer::brewery_id.eq(brewery::id)))
-            .select((
-                drink::id,
-                drink::drank_on,
-                beer::name,
-                brewery::name,
-                drink::rating,
-                drink::comment,
-            ))
-            .filter(drink::person_id.eq(&self.person_id))
-            .order(drink::drank_on.asc())
-            .load::<ExpandedDrink>(&conn)?)
+            .left_join(tasting_note::table)
+            .select(drink_columns())
+            .filter(drink::person_id.eq(self.person_id))
+            .into_boxed();
+
+        let filter = &self.filter;
+
+        if let Some(from) = filter.from {
+            query = query.filter(drink::drank_on.ge(from));
+        }
+
+        if let Some(to) = filter.to {
+            query = query.filter(drink::drank_on.le(to));
+        }
+
+        if let Some(brewery_id) = filter.brewery_id {
+            query = query.filter(brewery::id.eq(brewery_id));
+        }
+
+        if let Some(beer_id) = filter.beer_id {
+            query = query.filter(beer::id.eq(beer_id));
+        }
+
+        if let Some(min_rating) = filter.min_rating {
+            query = query.filter(drink::rating_quarters.ge(min_rating));
+        }
+
+        if let Some(max_rating) = filter.max_rating {
+            query = query.filter(drink::rating_quarters.le(max_rating));
+        }
+
+        match filter.has_comment {
+            Some(true) => query = query.filter(drink::comment.ne("")),
+            Some(false) => query = query.filter(drink::comment.is_null().or(drink::comment.eq(""))),
+            None => {}
+        }
+
+        match filter.has_tasting_notes {
+            Some(true) => query = query.filter(tasting_note::drink_id.is_not_null()),
+            Some(false) => query = query.filter(tasting_note::drink_id.is_null()),
+            None => {}
+        }
+
+        if let Some(min_overall) = filter.min_overall {
+            query = query.filter(tasting_note::overall.ge(min_overall));
+        }
+
+        for descriptor in &filter.descriptors {
+            query = query.filter(
+                drink::id.eq_any(
+                    tasting_note_descriptor::table
+                        .inner_join(flavor_descriptor::table)
+                        .select(tasting_note_descriptor::drink_id)
+                        .filter(flavor_descriptor::name.eq(descriptor.to_lowercase())),
+                ),
+            );
+        }
+
+        // Keyset pagination: skip everything up to and including the cursor's
+        // drink in the chosen order
+        if let Some(after) = self.after {
+            query = match (self.sort.key, self.sort.descending) {
+                (DrinkSortKey::DrankOn, false) => query.filter(
+                    drink::drank_on.gt(after.drank_on).or(drink::drank_on
+                        .eq(after.drank_on)
+                        .and(drink::id.gt(after.id))),
+                ),
+                (DrinkSortKey::DrankOn, true) => query.filter(
+                    drink::drank_on.lt(after.drank_on).or(drink::drank_on
+                        .eq(after.drank_on)
+                        .and(drink::id.lt(after.id))),
+                ),
+                (DrinkSortKey::Rating, false) => query.filter(
+                    drink::rating_quarters
+                        .gt(after.rating)
+                        .or(drink::rating_quarters
+                            .eq(after.rating)
+                            .and(drink::id.gt(after.id))),
+                ),
+                (DrinkSortKey::Rating, true) => query.filter(
+                    drink::rating_quarters
+                        .lt(after.rating)
+                        .or(drink::rating_quarters
+                            .eq(after.rating)
+                            .and(drink::id.lt(after.id))),
+                ),
+            };
+        }
+
+        query = match (self.sort.key, self.sort.descending) {
+            (DrinkSortKey::DrankOn, false) => query.order((drink::drank_on.asc(), drink::id.asc())),
+            (DrinkSortKey::DrankOn, true) => {
+                query.order((drink::drank_on.desc(), drink::id.desc()))
+            }
+            (DrinkSortKey::Rating, false) => {
+                query.order((drink::rating_quarters.asc(), drink::id.asc()))
+            }
+            (DrinkSortKey::Rating, true) => {
+                query.order((drink::rating_quarters.desc(), drink::id.desc()))
+            }
+        };
+
+        let limit = match self.limit {
+            Some(limit) => limit,
+            None => {
+                let mut drinks = query.load::<ExpandedDrink>(&conn)?;
+
+                attach_details(&conn, &mut drinks)?;
+                return Ok(DrinkPage { drinks, next: None });
+            }
+        };
+
+        // Fetch one extra drink to find out whether there is another page
+        let mut drinks = query.limit(limit + 1).load::<ExpandedDrink>(&conn)?;
+
+        let next = if drinks.len() as i64 > limit {
+            drinks.truncate(limit as usize);
+            drinks
+                .last()
+                .map(|last| DrinkCursor::after(last, self.sort))
+        } else {
+            None
+        };
+
+        attach_details(&conn, &mut drinks)?;
+        Ok(DrinkPage { drinks, next })
     }
 }
 
+/// Load all of a person's drinks, oldest first.
+fn expanded_drinks(conn: &PgConnection, person_id: i32) -> QueryResult<Vec<ExpandedDrink>> {
+    use super::schema::beer;
+    use super::schema::brewery;
+    use super::schema::drink;
+    use super::schema::tasting_note;
+
+    let mut drinks = drink::table
+        .inner_join(beer::table)
+        .inner_join(brewery::table.on(beer::brewery_id.eq(brewery::id)))
+        .left_join(tasting_note::table)
+        .select(drink_columns())
+        .filter(drink::person_id.eq(person_id))
+        .order(drink::drank_on.asc())
+        .load::<ExpandedDrink>(conn)?;
+
+    attach_details(conn, &mut drinks)?;
+    Ok(drinks)
+}
+
 /*************************************/
 /** Get Drink message               **/
 /*************************************/
 
+/// Get a drink, as long as `viewer_id` drank it or it has been shared.
+///
+/// Returns `None` if the drink doesn't exist or the viewer may not see it.
 pub struct GetDrink {
     pub drink_id: i32,
+    pub viewer_id: i32,
+}
+
+impl GetDrink {
+    pub fn find(&self, conn: &PgConnection) -> QueryResult<Option<ExpandedDrink>> {
+        use super::schema::drink;
+        use super::schema::person;
+
+        // Shared drinks are hidden along with everything else while their
+        // drinker is deleting their account
+        let visible = drink::table
+            .inner_join(person::table)
+            .select(drink::id)
+            .filter(drink::id.eq(self.drink_id))
+            .filter(
+                drink::person_id.eq(self.viewer_id).or(drink::shared
+                    .eq(true)
+                    .and(person::deletion_requested_at.is_null())),
+            )
+            .first::<i32>(conn)
+            .optional()?;
+
+        visible
+            .map(|drink_id| expanded_drink(conn, drink_id))
+            .transpose()
+    }
 }
 
 impl Query for GetDrink {
-    type Output = ExpandedDrink;
+    type Output = Option<ExpandedDrink>;
 
     fn execute(&self, conn: Connection) -> Result<Self::Output> {
-        use super::schema::beer;
-        use super::schema::beer::dsl::*;
-        use super::schema::brewery;
-        use super::schema::drink;
+        Ok(self.find(&conn)?)
+    }
+}
+
+fn expanded_drink(conn: &PgConnection, drink_id: i32) -> QueryResult<ExpandedDrink> {
+    use super::schema::beer;
+    use super::schema::brewery;
+    use super::schema::drink;
+    use super::schema::tasting_note;
+
+    let mut found = drink::table
+        .inner_join(beer::table)
+        .inner_join(brewery::table.on(beer::brewery_id.eq(brewery::id)))
+        .left_join(tasting_note::table)
+        .select(drink_columns())
+        .filter(drink::id.eq(drink_id))
+        .first::<ExpandedDrink>(conn)?;
+
+    attach_details(conn, std::slice::from_mut(&mut found))?;
+    Ok(found)
+}
+
+/*************************************/
+/** Update Drink message            **/
+/*************************************/
+
+/// Change the details of one of a person's drinks.
+pub struct UpdateDrink {
+    pub drink_id: i32,
+    pub person_id: i32,
+
+    /// The names of a brewery and one of its beers to re-point the drink at,
+    /// which are added to the catalog like `LogDrink` does.
+    pub beer: Option<(String, String)>,
+    pub changes: models::DrinkChanges,
+}
+
+impl UpdateDrink {
+    pub fn update(&self, conn: &PgConnection) -> Result<DrinkOutcome> {
         use super::schema::drink::dsl::*;
 
-        Ok(drink
-            .inner_join(beer)
-            .inner_join(brewery::table.on(beer::brewery_id.eq(brewery::id)))
+        conn.transaction::<_, Error, _>(|| {
+            let owned = drink.filter(id.eq(self.drink_id).and(person_id.eq(self.person_id)));
+
+            // Check the drink is theirs before adding anything to the catalog
+            let found = owned
+                .select(id)
+                .for_update()
+                .first::<i32>(conn)
+                .optional()?;
+
+            if found.is_none() {
+                return Ok(DrinkOutcome::NotFound);
+            }
+
+            let mut changes = self.changes.clone();
+
+            if let Some((ref brewery_name, ref beer_name)) = self.beer {
+                changes.beer_id = Some(find_or_create_beer(conn, brewery_name, beer_name)?.id);
+            }
+
+            // Diesel refuses to run an update with nothing to set
+            if !changes.is_empty() {
+                diesel::update(owned).set(&changes).execute(conn)?;
+            }
+
+            Ok(DrinkOutcome::Saved(expanded_drink(conn, self.drink_id)?))
+        })
+    }
+}
+
+impl Query for UpdateDrink {
+    type Output = DrinkOutcome;
+
+    fn execute(&self, conn: Connection) -> Result<Self::Output> {
+        self.update(&conn)
+    }
+}
+
+/*************************************/
+/** Delete Drink message            **/
+/*************************************/
+
+/// Delete one of a person's drinks, along with its tasting notes and photos.
+///
+/// Returns the blob store keys of the drink's photos so they can be removed
+/// too, or `None` if the person has no such drink.
+pub struct DeleteDrink {
+    pub drink_id: i32,
+    pub person_id: i32,
+}
+
+impl Query for DeleteDrink {
+    type Output = Option<Vec<String>>;
+
+    fn execute(&self, conn: Connection) -> Result<Self::Output> {
+        use super::schema::drink;
+        use super::schema::drink_photo;
+
+        conn.transaction::<_, Error, _>(|| {
+            // Lock the drink so no photos can be added while it is deleted
+            let found = drink::table
+                .select(drink::id)
+                .filter(drink::id.eq(self.drink_id))
+                .filter(drink::person_id.eq(self.person_id))
+                .for_update()
+                .first::<i32>(&conn)
+                .optional()?;
+
+            if found.is_none() {
+                return Ok(None);
+            }
+
+            let photos = drink_photo::table
+                .filter(drink_photo::drink_id.eq(self.drink_id))
+                .load::<models::DrinkPhoto>(&conn)?;
+
+            diesel::delete(drink::table.find(self.drink_id)).execute(&conn)?;
+
+            Ok(Some(photo_keys(photos)))
+        })
+    }
+}
+
+/// Every blob store key used by some photos, thumbnails included.
+fn photo_keys(photos: Vec<models::DrinkPhoto>) -> Vec<String> {
+    photos
+        .into_iter()
+        .flat_map(|photo| vec![photo.blob_key, photo.thumbnail_key])
+        .collect()
+}
+
+/*************************************/
+/** Import Drinks message           **/
+/*************************************/
+
+/// Log many drinks at once, adding their beers and breweries to the catalog
+/// like `LogDrink` does.
+///
+/// Nothing is saved on a dry run or if any row has a problem, but the report
+/// still says which breweries and beers are already listed and which would be
+/// added. Otherwise everything is saved in one transaction.
+pub struct ImportDrinks {
+    pub person_id: i32,
+    pub rows: Vec<import::ImportRow>,
+
+    /// Problems found while reading the import, which stop it from being saved.
+    pub errors: Vec<import::RowError>,
+    pub dry_run: bool,
+}
+
+impl Query for ImportDrinks {
+    type Output = import::ImportReport;
+
+    fn execute(&self, conn: Connection) -> Result<Self::Output> {
+        let mut report = import::ImportReport {
+            dry_run: self.dry_run,
+            imported: 0,
+            breweries: Vec::new(),
+            beers: Vec::new(),
+            errors: self.errors.clone(),
+        };
+
+        conn.transaction::<_, Error, _>(|| {
+            plan_import(&conn, &self.rows, &mut report)?;
+
+            if !self.dry_run && report.errors.is_empty() {
+                report.imported = save_import(&conn, self.person_id, &self.rows, &mut report)?;
+            }
+
+            Ok(())
+        })?;
+
+        report.errors.sort_by_key(|error| error.row);
+
+        Ok(report)
+    }
+}
+
+/// Match the breweries and beers of an import against the catalog, the same
+/// way `find_or_create_beer` will when the import is saved.
+fn plan_import(
+    conn: &PgConnection,
+    rows: &[import::ImportRow],
+    report: &mut import::ImportReport,
+) -> QueryResult<()> {
+    // The id of each brewery by lowercase name, if it's already listed
+    let mut brewery_ids = HashMap::<String, Option<i32>>::new();
+    let mut seen_beers = HashSet::<(String, String)>::new();
+
+    for row in rows {
+        let brewery_key = row.brewery.to_lowercase();
+
+        let brewery_id = match brewery_ids.get(&brewery_key) {
+            Some(&brewery_id) => brewery_id,
+            None => {
+                let found = GetBreweryByName {
+                    name: row.brewery.clone(),
+                }
+                .find(conn)?
+                .map(|found| found.id);
+
+                report.breweries.push(import::CatalogMatch {
+                    name: row.brewery.clone(),
+                    brewery: None,
+                    id: found,
+                    created: found.is_none(),
+                });
+                brewery_ids.insert(brewery_key.clone(), found);
+
+                found
+            }
+        };
+
+        if seen_beers.insert((brewery_key, row.beer.to_lowercase())) {
+            // A new brewery can't have any beers listed yet
+            let found = match brewery_id {
+                Some(brewery_id) => GetBeerByName {
+                    name: row.beer.clone(),
+                    brewery_id,
+                }
+                .find(conn)?
+                .map(|found| found.id),
+                None => None,
+            };
+
+            report.beers.push(import::CatalogMatch {
+                name: row.beer.clone(),
+                brewery: Some(row.brewery.clone()),
+                id: found,
+                created: found.is_none(),
+            });
+        }
+    }
+
+    Ok(())
+}
+
+/// Save the drinks of an import, filling in the ids of any catalog entries it adds.
+///
+/// This must be called inside a transaction.
+fn save_import(
+    conn: &PgConnection,
+    person_id: i32,
+    rows: &[import::ImportRow],
+    report: &mut import::ImportReport,
+) -> Result<usize> {
+    use super::schema::drink;
+
+    // Beers are keyed by the lowercase names of their brewery and themselves
+    let beer_key = |brewery: &str, beer: &str| (brewery.to_lowercase(), beer.to_lowercase());
+    let mut beer_ids = HashMap::<(String, String), i32>::new();
+    let mut brewery_ids = HashMap::<String, i32>::new();
+
+    for row in rows {
+        let beer_key = beer_key(&row.brewery, &row.beer);
+
+        if beer_ids.contains_key(&beer_key) {
+            continue;
+        }
+
+        let found = find_or_create_beer(conn, &row.brewery, &row.beer)?;
+
+        beer_ids.insert(beer_key, found.id);
+        brewery_ids.insert(row.brewery.to_lowercase(), found.brewery_id);
+    }
+
+    let shared = false;
+    let new_drinks = rows
+        .iter()
+        .map(|row| models::NewDrink {
+            person_id: &person_id,
+            drank_on: &row.drank_on,
+            beer_id: &beer_ids[&beer_key(&row.brewery, &row.beer)],
+            rating: &row.rating,
+            comment: row.comment.as_ref(),
+            shared: &shared,
+            drank_at: None,
+            serving: None,
+            volume_ml: None,
+            price_cents: None,
+            abv: None,
+            venue: None,
+        })
+        .collect::<Vec<_>>();
+
+    // Stay well under the limit on parameters in a single statement
+    for chunk in new_drinks.chunks(1000) {
+        diesel::insert_into(drink::table)
+            .values(chunk)
+            .execute(conn)?;
+    }
+
+    for listed in report.breweries.iter_mut() {
+        listed.id = brewery_ids.get(&listed.name.to_lowercase()).cloned();
+    }
+
+    for listed in report.beers.iter_mut() {
+        let brewery = listed.brewery.as_ref().map_or("", String::as_str);
+
+        listed.id = beer_ids.get(&beer_key(brewery, &listed.name)).cloned();
+    }
+
+    Ok(new_drinks.len())
+}
+
+/*************************************/
+/** Drink Photo messages            **/
+/*************************************/
+
+/// Attach a photo, which is already in the blob store, to one of a person's drinks.
+pub struct AddDrinkPhoto {
+    pub drink_id: i32,
+    pub person_id: i32,
+    pub blob_key: String,
+    pub thumbnail_key: String,
+    pub width: i32,
+    pub height: i32,
+}
+
+impl Query for AddDrinkPhoto {
+    type Output = DrinkOutcome;
+
+    fn execute(&self, conn: Connection) -> Result<Self::Output> {
+        use super::schema::drink;
+        use super::schema::drink_photo;
+
+        conn.transaction::<_, Error, _>(|| {
+            let found = drink::table
+                .select(drink::id)
+                .filter(drink::id.eq(self.drink_id))
+                .filter(drink::person_id.eq(self.person_id))
+                .first::<i32>(&conn)
+                .optional()?;
+
+            if found.is_none() {
+                return Ok(DrinkOutcome::NotFound);
+            }
+
+            diesel::insert_into(drink_photo::table)
+                .values(&models::NewDrinkPhoto {
+                    drink_id: self.drink_id,
+                    blob_key: &self.blob_key,
+                    thumbnail_key: &self.thumbnail_key,
+                    width: self.width,
+                    height: self.height,
+                })
+                .execute(&conn)?;
+
+            Ok(DrinkOutcome::Saved(expanded_drink(&conn, self.drink_id)?))
+        })
+    }
+}
+
+/// Remove a photo from one of a person's drinks.
+///
+/// Returns the blob store keys of the photo so they can be removed too, or
+/// `None` if the person has no such photo.
+pub struct DeleteDrinkPhoto {
+    pub drink_id: i32,
+    pub photo_id: i32,
+    pub person_id: i32,
+}
+
+impl Query for DeleteDrinkPhoto {
+    type Output = Option<Vec<String>>;
+
+    fn execute(&self, conn: Connection) -> Result<Self::Output> {
+        use super::schema::drink;
+        use super::schema::drink_photo;
+
+        let owned = drink::table
+            .select(drink::id)
+            .filter(drink::id.eq(self.drink_id))
+            .filter(drink::person_id.eq(self.person_id));
+
+        let deleted = diesel::delete(
+            drink_photo::table
+                .filter(drink_photo::id.eq(self.photo_id))
+                .filter(drink_photo::drink_id.eq_any(owned)),
+        )
+        .get_results::<models::DrinkPhoto>(&conn)?;
+
+        if deleted.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(photo_keys(deleted)))
+        }
+    }
+}
+
+/// Who may fetch a photo, thumbnail or avatar from the blob store.
+#[derive(Debug, PartialEq)]
+pub enum PhotoAccess {
+    /// Anyone may, as it's an avatar or belongs to a shared drink.
+    Public,
+
+    /// Only the viewer may, as it belongs to a drink they had.
+    Owner,
+
+    /// The viewer may not, or nothing is kept under that key.
+    Hidden,
+}
+
+/// Check whether `viewer_id` may fetch what is kept under `key`.
+///
+/// Photos can be seen by whoever can see their drink, like with `GetDrink`.
+/// Avatars can be seen by anyone.
+pub struct GetPhotoAccess {
+    pub key: String,
+    pub viewer_id: Option<i32>,
+}
+
+impl Query for GetPhotoAccess {
+    type Output = PhotoAccess;
+
+    fn execute(&self, conn: Connection) -> Result<Self::Output> {
+        Ok(photo_access(&conn, &self.key, self.viewer_id)?)
+    }
+}
+
+fn photo_access(
+    conn: &PgConnection,
+    key: &str,
+    viewer_id: Option<i32>,
+) -> QueryResult<PhotoAccess> {
+    use super::schema::drink;
+    use super::schema::drink_photo;
+    use super::schema::person;
+
+    let avatar = person::table
+        .select(person::id)
+        .filter(person::avatar_key.eq(key))
+        .first::<i32>(conn)
+        .optional()?;
+
+    if avatar.is_some() {
+        return Ok(PhotoAccess::Public);
+    }
+
+    // Shared drinks are hidden while their drinker is deleting their account
+    let found = drink_photo::table
+        .inner_join(drink::table.inner_join(person::table))
+        .select((
+            drink::person_id,
+            drink::shared,
+            person::deletion_requested_at.is_null(),
+        ))
+        .filter(
+            drink_photo::blob_key
+                .eq(key)
+                .or(drink_photo::thumbnail_key.eq(key)),
+        )
+        .first::<(i32, bool, bool)>(conn)
+        .optional()?;
+
+    Ok(match found {
+        Some((_, true, true)) => PhotoAccess::Public,
+        Some((drinker, _, _)) if Some(drinker) == viewer_id => PhotoAccess::Owner,
+        _ => PhotoAccess::Hidden,
+    })
+}
+
+/*************************************/
+/** Tasting Notes messages          **/
+/*************************************/
+
+/// Replace the tasting notes on one of a person's drinks.
+pub struct SetTastingNotes {
+    pub drink_id: i32,
+    pub person_id: i32,
+    pub notes: TastingNotes,
+}
+
+impl Query for SetTastingNotes {
+    type Output = DrinkOutcome;
+
+    fn execute(&self, conn: Connection) -> Result<Self::Output> {
+        use super::schema::drink;
+        use super::schema::flavor_descriptor;
+        use super::schema::tasting_note;
+        use super::schema::tasting_note_descriptor;
+        use diesel::pg::upsert::excluded;
+
+        conn.transaction::<_, Error, _>(|| {
+            let found = drink::table
+                .select(drink::id)
+                .filter(drink::id.eq(self.drink_id))
+                .filter(drink::person_id.eq(self.person_id))
+                .for_update()
+                .first::<i32>(&conn)
+                .optional()?;
+
+            if found.is_none() {
+                return Ok(DrinkOutcome::NotFound);
+            }
+
+            let names = self
+                .notes
+                .descriptors
+                .iter()
+                .map(|name| name.to_lowercase())
+                .collect::<Vec<_>>();
+
+            let descriptors = flavor_descriptor::table
+                .filter(flavor_descriptor::name.eq_any(&names))
+                .load::<models::FlavorDescriptor>(&conn)?;
+
+            let unknown = names
+                .into_iter()
+                .filter(|name| !descriptors.iter().any(|found| &found.name == name))
+                .collect::<Vec<_>>();
+
+            if !unknown.is_empty() {
+                return Ok(DrinkOutcome::UnknownDescriptors(unknown));
+            }
+
+            diesel::insert_into(tasting_note::table)
+                .values(&models::NewTastingNote {
+                    drink_id: self.drink_id,
+                    appearance: self.notes.appearance,
+                    aroma: self.notes.aroma,
+                    flavor: self.notes.flavor,
+                    mouthfeel: self.notes.mouthfeel,
+                    overall: self.notes.overall,
+                })
+                .on_conflict(tasting_note::drink_id)
+                .do_update()
+                .set((
+                    tasting_note::appearance.eq(excluded(tasting_note::appearance)),
+                    tasting_note::aroma.eq(excluded(tasting_note::aroma)),
+                    tasting_note::flavor.eq(excluded(tasting_note::flavor)),
+                    tasting_note::mouthfeel.eq(excluded(tasting_note::mouthfeel)),
+                    tasting_note::overall.eq(excluded(tasting_note::overall)),
+                ))
+                .execute(&conn)?;
+
+            diesel::delete(
+                tasting_note_descriptor::table
+                    .filter(tasting_note_descriptor::drink_id.eq(self.drink_id)),
+            )
+            .execute(&conn)?;
+
+            let picked = descriptors
+                .iter()
+                .map(|descriptor| models::NewTastingNoteDescriptor {
+                    drink_id: self.drink_id,
+                    descriptor_id: descriptor.id,
+                })
+                .collect::<Vec<_>>();
+
+            if !picked.is_empty() {
+                diesel::insert_into(tasting_note_descriptor::table)
+                    .values(&picked)
+                    .execute(&conn)?;
+            }
+
+            Ok(DrinkOutcome::Saved(expanded_drink(&conn, self.drink_id)?))
+        })
+    }
+}
+
+/// Remove the tasting notes from one of a person's drinks.
+pub struct DeleteTastingNotes {
+    pub drink_id: i32,
+    pub person_id: i32,
+}
+
+impl Query for DeleteTastingNotes {
+    type Output = usize;
+
+    fn execute(&self, conn: Connection) -> Result<Self::Output> {
+        use super::schema::drink;
+        use super::schema::tasting_note;
+
+        let owned = drink::table
+            .select(drink::id)
+            .filter(drink::id.eq(self.drink_id))
+            .filter(drink::person_id.eq(self.person_id));
+
+        // Descriptors go along with the notes through the cascade
+        Ok(
+            diesel::delete(tasting_note::table.filter(tasting_note::drink_id.eq_any(owned)))
+                .execute(&conn)?,
+        )
+    }
+}
+
+/// List the flavors that tasting notes can pick out, alphabetically.
+pub struct GetFlavorDescriptors;
+
+impl Query for GetFlavorDescriptors {
+    type Output = Vec<models::FlavorDescriptor>;
+
+    fn execute(&self, conn: Connection) -> Result<Self::Output> {
+        use super::schema::flavor_descriptor::dsl::*;
+
+        Ok(flavor_descriptor
+            .order(name.asc())
+            .load::<models::FlavorDescriptor>(&conn)?)
+    }
+}
+
+/*************************************/
+/* Login and Registration            */
+/*************************************/
+
+pub struct LookupIdentiy {
+    pub kind: models::IdentityKind,
+    pub identifier: String,
+}
+
+impl Query for LookupIdentiy {
+    type Output = models::Identity;
+
+    fn execute(&self, conn: Connection) -> Result<Self::Output> {
+        use self::schema::identity::dsl::*;
+        use self::schema::person::dsl::*;
+
+        // Query to see if a matching Identity exists
+        let existing_identity = identity
+            .filter(kind.eq(self.kind.as_str()).and(identifier.eq(&self.identifier)))
+            .first::<models::Identity>(&conn)
+            .optional()?;
+
+        // If an Identity was found, return it
+        if let Some(existing_identity) = existing_identity {
+            info!(
+                "Found existing {} identity matching '{}', person {}.",
+                existing_identity.kind, existing_identity.identifier, existing_identity.person_id
+            );
+            return Ok(existing_identity);
+        }
+
+        // If here, then no identity was found
+        // Create a new person to go with that identity
+        let new_person = diesel::insert_into(person) /* lol */
+            .default_values() // currently no other values need to be inserted at the moment
+            .get_result::<models::Person>(&conn)?;
+
+        // Insert the new identity
+        let new_identity = diesel::insert_into(identity)
+            .values(&models::NewIdentity {
+                identifier: &self.identifier,
+                person_id: new_person.id,
+                kind: self.kind.as_str(),
+            })
+            .get_result::<models::Identity>(&conn)?;
+
+        info!(
+            "Created new {} identity matching '{}' for person {}.",
+            new_identity.kind, new_identity.identifier, new_identity.person_id
+        );
+
+        Ok(new_identity)
+    }
+}
+
+/*************************************/
+/* Linked Identities                 */
+/*************************************/
+
+/// List the identities that a person can log in with, oldest first.
+pub struct GetIdentities {
+    pub person_id: i32,
+}
+
+impl Query for GetIdentities {
+    type Output = Vec<models::Identity>;
+
+    fn execute(&self, conn: Connection) -> Result<Self::Output> {
+        use self::schema::identity::dsl::*;
+
+        Ok(identity
+            .filter(person_id.eq(self.person_id))
+            .order(created_at.asc())
+            .load::<models::Identity>(&conn)?)
+    }
+}
+
+/// What happened when a verified identity was attached to a person.
+pub enum AttachOutcome {
+    /// The identity was new, and now belongs to the person.
+    Attached(models::Identity),
+
+    /// The identity already belonged to the person.
+    AlreadyAttached(models::Identity),
+
+    /// The identity belongs to another person, and merging wasn't requested.
+    Conflict,
+
+    /// The identity belonged to another person, who was merged into this one.
+    Merged(MergeReport),
+}
+
+/// Attach a verified identity to a person.
+///
+/// If the identity already belongs to someone else, that person is merged into
+/// this one when `merge` is set. Verifying the identity proves that whoever is
+/// asking controls the other account.
+pub struct AttachIdentity {
+    pub person_id: i32,
+    pub kind: models::IdentityKind,
+    pub identifier: String,
+    pub merge: bool,
+}
+
+impl Query for AttachIdentity {
+    type Output = AttachOutcome;
+
+    fn execute(&self, conn: Connection) -> Result<Self::Output> {
+        use self::schema::identity::dsl::*;
+
+        conn.transaction::<_, Error, _>(|| {
+            let existing_identity = identity
+                .filter(kind.eq(self.kind.as_str()).and(identifier.eq(&self.identifier)))
+                .for_update()
+                .first::<models::Identity>(&conn)
+                .optional()?;
+
+            match existing_identity {
+                None => {
+                    let new_identity = diesel::insert_into(identity)
+                        .values(&models::NewIdentity {
+                            identifier: &self.identifier,
+                            person_id: self.person_id,
+                            kind: self.kind.as_str(),
+                        })
+                        .get_result::<models::Identity>(&conn)?;
+
+                    info!(
+                        "Attached {} identity '{}' to person {}.",
+                        new_identity.kind, new_identity.identifier, new_identity.person_id
+                    );
+
+                    Ok(AttachOutcome::Attached(new_identity))
+                }
+                Some(existing_identity) if existing_identity.person_id == self.person_id => {
+                    Ok(AttachOutcome::AlreadyAttached(existing_identity))
+                }
+                Some(_) if !self.merge => Ok(AttachOutcome::Conflict),
+                Some(existing_identity) => Ok(AttachOutcome::Merged(merge_persons(
+                    &conn,
+                    existing_identity.person_id,
+                    self.person_id,
+                )?)),
+            }
+        })
+    }
+}
+
+/// What happened when detaching an identity from a person.
+pub enum DetachOutcome {
+    Detached,
+
+    /// The person has no such identity.
+    NotFound,
+
+    /// The identity is the only way the person can log in, so it was kept.
+    LastIdentity,
+}
+
+/// Detach an identity from a person, as long as they have another one left.
+pub struct DetachIdentity {
+    pub person_id: i32,
+    pub kind: models::IdentityKind,
+    pub identifier: String,
+}
+
+impl Query for DetachIdentity {
+    type Output = DetachOutcome;
+
+    fn execute(&self, conn: Connection) -> Result<Self::Output> {
+        use self::schema::identity::dsl::*;
+
+        conn.transaction::<_, Error, _>(|| {
+            // Lock all of the person's identities so that two concurrent requests
+            // can't each detach one of the last two.
+            let identities = identity
+                .filter(person_id.eq(self.person_id))
+                .for_update()
+                .load::<models::Identity>(&conn)?;
+
+            let found = identities
+                .iter()
+                .any(|i| i.kind == self.kind.as_str() && i.identifier == self.identifier);
+
+            if !found {
+                return Ok(DetachOutcome::NotFound);
+            }
+
+            if identities.len() == 1 {
+                return Ok(DetachOutcome::LastIdentity);
+            }
+
+            diesel::delete(
+                identity.filter(
+                    kind.eq(self.kind.as_str())
+                        .and(identifier.eq(&self.identifier))
+                        .and(person_id.eq(self.person_id)),
+                ),
+            )
+            .execute(&conn)?;
+
+            info!(
+                "Detached {} identity '{}' from person {}.",
+                self.kind.as_str(),
+                self.identifier,
+                self.person_id
+            );
+
+            Ok(DetachOutcome::Detached)
+        })
+    }
+}
+
+/// A summary of everything that was moved when merging two people.
+#[derive(Serialize)]
+#[serde(rename = "merge")]
+pub struct MergeReport {
+    /// The person that was merged, and no longer exists.
+    pub from_person_id: i32,
+    pub into_person_id: i32,
+    pub drinks: usize,
+    pub identities: usize,
+    pub auth_events: usize,
+
+    /// Sessions and API tokens of the merged person are revoked rather than
+    /// moved, since phone numbers get recycled and whoever held them before
+    /// mustn't end up logged in as the surviving person.
+    pub revoked_sessions: usize,
+    pub revoked_api_tokens: usize,
+
+    /// The handle and role of the merged person, which the surviving person
+    /// doesn't inherit.
+    pub dropped_handle: Option<String>,
+    pub dropped_role: Option<String>,
+
+    /// The uploaded avatar of the merged person, left to remove from the store.
+    #[serde(skip)]
+    pub dropped_avatar_key: Option<String>,
+
+    /// Moved drinks of a beer that the surviving person also drank on the same
+    /// day. Both drinks are kept, since they may well be two separate pints.
+    pub possible_duplicate_drinks: Vec<i32>,
+}
+
+/// Move everything belonging to person `from` over to person `into`, revoke
+/// the logins of `from`, then delete it.
+///
+/// This must be called inside a transaction.
+fn merge_persons(conn: &PgConnection, from: i32, into: i32) -> Result<MergeReport> {
+    use self::schema::{api_token, auth_event, drink, identity, login_session, person};
+
+    let surviving_drinks = drink::table
+        .select((drink::beer_id, drink::drank_on))
+        .filter(drink::person_id.eq(into))
+        .load::<(i32, NaiveDate)>(conn)?;
+
+    let possible_duplicate_drinks = drink::table
+        .select((drink::id, drink::beer_id, drink::drank_on))
+        .filter(drink::person_id.eq(from))
+        .order(drink::id.asc())
+        .load::<(i32, i32, NaiveDate)>(conn)?
+        .into_iter()
+        .filter(|(_, beer_id, drank_on)| surviving_drinks.contains(&(*beer_id, *drank_on)))
+        .map(|(drink_id, _, _)| drink_id)
+        .collect();
+
+    let drinks = diesel::update(drink::table.filter(drink::person_id.eq(from)))
+        .set(drink::person_id.eq(into))
+        .execute(conn)?;
+
+    let identities = diesel::update(identity::table.filter(identity::person_id.eq(from)))
+        .set(identity::person_id.eq(into))
+        .execute(conn)?;
+
+    let revoked_sessions =
+        diesel::delete(login_session::table.filter(login_session::person_id.eq(from)))
+            .execute(conn)?;
+
+    let revoked_api_tokens =
+        diesel::delete(api_token::table.filter(api_token::person_id.eq(from))).execute(conn)?;
+
+    // The security log would otherwise be deleted along with the person
+    let auth_events = diesel::update(auth_event::table.filter(auth_event::person_id.eq(from)))
+        .set(auth_event::person_id.eq(into))
+        .execute(conn)?;
+
+    let dropped = diesel::delete(person::table.find(from)).get_result::<models::Person>(conn)?;
+    let dropped_role = Some(dropped.role).filter(|role| role != Role::Member.as_str());
+
+    info!(
+        "Merged person {} into person {}: {} drinks, {} identities, {} auth events; revoked {} \
+         sessions and {} API tokens.",
+        from, into, drinks, identities, auth_events, revoked_sessions, revoked_api_tokens
+    );
+
+    Ok(MergeReport {
+        from_person_id: from,
+        into_person_id: into,
+        drinks,
+        identities,
+        auth_events,
+        revoked_sessions,
+        revoked_api_tokens,
+        dropped_handle: dropped.handle,
+        dropped_role,
+        dropped_avatar_key: dropped.avatar_key,
+        possible_duplicate_drinks,
+    })
+}
+
+/// Rewrite phone identities saved before numbers were normalized in E.164
+/// format, merging people whose identities turn out to be the same phone.
+///
+/// Each identifier is parsed with `PhoneNumber::parse_legacy`, the same way as
+/// a number typed at login, and any that don't parse are left as they are. The
+/// oldest identity for each number wins. Returns how many were rewritten.
+pub struct NormalizePhoneIdentities;
+
+impl Query for NormalizePhoneIdentities {
+    type Output = usize;
+
+    fn execute(&self, conn: Connection) -> Result<Self::Output> {
+        use self::schema::identity::dsl::*;
+
+        let phone = models::IdentityKind::Phone.as_str();
+
+        conn.transaction::<_, Error, _>(|| {
+            let legacy = identity
+                .select(identifier)
+                .filter(kind.eq(phone))
+                .filter(diesel::dsl::not(identifier.like("+%")))
+                .order((created_at.asc(), person_id.asc()))
+                .for_update()
+                .load::<String>(&conn)?;
+
+            let mut normalized = 0;
+
+            for old_identifier in legacy {
+                let number = match PhoneNumber::parse_legacy(&old_identifier) {
+                    Some(number) => number.e164(),
+                    None => {
+                        warn!(
+                            "Leaving phone identity '{}' as it is; it isn't a valid number.",
+                            old_identifier
+                        );
+                        continue;
+                    }
+                };
+
+                // Earlier merges may have moved this identity to another person
+                let old = identity
+                    .filter(kind.eq(phone).and(identifier.eq(&old_identifier)))
+                    .first::<models::Identity>(&conn)?;
+
+                let existing = identity
+                    .filter(kind.eq(phone).and(identifier.eq(&number)))
+                    .for_update()
+                    .first::<models::Identity>(&conn)
+                    .optional()?;
+
+                let keep = match existing {
+                    Some(existing) => {
+                        let (keep, duplicate) = if (old.created_at, old.person_id)
+                            < (existing.created_at, existing.person_id)
+                        {
+                            (old, existing)
+                        } else {
+                            (existing, old)
+                        };
+
+                        diesel::delete(
+                            identity
+                                .filter(kind.eq(phone).and(identifier.eq(&duplicate.identifier))),
+                        )
+                        .execute(&conn)?;
+
+                        if duplicate.person_id != keep.person_id {
+                            merge_persons(&conn, duplicate.person_id, keep.person_id)?;
+                        }
+
+                        keep
+                    }
+                    None => old,
+                };
+
+                diesel::update(
+                    identity.filter(kind.eq(phone).and(identifier.eq(&keep.identifier))),
+                )
+                .set(identifier.eq(&number))
+                .execute(&conn)?;
+
+                normalized += 1;
+            }
+
+            Ok(normalized)
+        })
+    }
+}
+
+/*************************************/
+/* Rate Limiting                     */
+/*************************************/
+
+/// Count a hit against a rate limit key, opening a new window if none is open.
+pub struct HitThrottle {
+    pub key: String,
+    pub window: Duration,
+}
+
+impl Query for HitThrottle {
+    type Output = models::AuthThrottle;
+
+    fn execute(&self, conn: Connection) -> Result<Self::Output> {
+        use self::schema::auth_throttle::dsl::*;
+
+        let now = Utc::now();
+
+        conn.transaction::<_, Error, _>(|| {
+            // A closed window for the key is cleared, so that a new one opens
+            diesel::delete(auth_throttle.filter(key.eq(&self.key).and(resets_at.le(now))))
+                .execute(&conn)?;
+
+            Ok(diesel::insert_into(auth_throttle)
+                .values(&models::NewAuthThrottle {
+                    key: &self.key,
+                    hits: 1,
+                    resets_at: now + self.window,
+                })
+                .on_conflict(key)
+                .do_update()
+                .set(hits.eq(hits + 1))
+                .get_result::<models::AuthThrottle>(&conn)?)
+        })
+    }
+}
+
+/// Get the open rate limit window for a key.
+pub struct GetThrottle {
+    pub key: String,
+}
+
+impl Query for GetThrottle {
+    type Output = Option<models::AuthThrottle>;
+
+    fn execute(&self, conn: Connection) -> Result<Self::Output> {
+        use self::schema::auth_throttle::dsl::*;
+
+        Ok(auth_throttle
+            .filter(key.eq(&self.key).and(resets_at.gt(Utc::now())))
+            .first::<models::AuthThrottle>(&conn)
+            .optional()?)
+    }
+}
+
+/// Close the rate limit window for a key.
+pub struct ClearThrottle {
+    pub key: String,
+}
+
+impl Query for ClearThrottle {
+    type Output = usize;
+
+    fn execute(&self, conn: Connection) -> Result<Self::Output> {
+        use self::schema::auth_throttle::dsl::*;
+
+        Ok(diesel::delete(auth_throttle.filter(key.eq(&self.key))).execute(&conn)?)
+    }
+}
+
+/// Clear out every closed rate limit window.
+pub struct PurgeThrottles;
+
+impl Query for PurgeThrottles {
+    type Output = usize;
+
+    fn execute(&self, conn: Connection) -> Result<Self::Output> {
+        use self::schema::auth_throttle::dsl::*;
+
+        Ok(diesel::delete(auth_throttle.filter(resets_at.le(Utc::now()))).execute(&conn)?)
+    }
+}
+
+/*************************************/
+/* Email Login                       */
+/*************************************/
+
+/// Create a single-use login challenge for an email address.
+///
+/// Returns the plaintext token to be sent in the login link.
+pub struct CreateEmailChallenge {
+    pub email: String,
+    pub lifetime: Duration,
+}
+
+impl Query for CreateEmailChallenge {
+    type Output = String;
+
+    fn execute(&self, conn: Connection) -> Result<Self::Output> {
+        use self::schema::email_challenge::dsl::*;
+
+        let challenge_token = token::generate_hex(32);
+
+        diesel::insert_into(email_challenge)
+            .values(&models::NewEmailChallenge {
+                email: &self.email,
+                digest: &token::digest(&challenge_token),
+                expires_at: Utc::now() + self.lifetime,
+            })
+            .execute(&conn)?;
+
+        Ok(challenge_token)
+    }
+}
+
+/// Use up a login challenge, returning the email address it was sent to.
+///
+/// Returns `None` if the token is unknown, expired or has already been used.
+pub struct ConsumeEmailChallenge {
+    pub token: String,
+}
+
+impl Query for ConsumeEmailChallenge {
+    type Output = Option<String>;
+
+    fn execute(&self, conn: Connection) -> Result<Self::Output> {
+        use self::schema::email_challenge::dsl::*;
+
+        let now = Utc::now();
+
+        // Marking the challenge as consumed in the same statement that checks it
+        // ensures that a link can only ever be used once.
+        Ok(diesel::update(
+            email_challenge.filter(
+                digest
+                    .eq(token::digest(&self.token))
+                    .and(consumed_at.is_null())
+                    .and(expires_at.gt(now)),
+            ),
+        )
+        .set(consumed_at.eq(now))
+        .returning(email)
+        .get_result::<String>(&conn)
+        .optional()?)
+    }
+}
+
+/*************************************/
+/* OIDC Login                        */
+/*************************************/
+
+/// Remember the secrets for a login with an OpenID Connect provider until it is completed.
+pub struct CreateOidcLogin {
+    pub state: String,
+    pub nonce: String,
+    pub code_verifier: String,
+    pub lifetime: Duration,
+}
+
+impl Query for CreateOidcLogin {
+    type Output = ();
+
+    fn execute(&self, conn: Connection) -> Result<Self::Output> {
+        use self::schema::oidc_login::dsl::*;
+
+        let now = Utc::now();
+
+        // Abandoned logins are never consumed, so clear them out as we go
+        diesel::delete(oidc_login.filter(expires_at.le(now))).execute(&conn)?;
+
+        diesel::insert_into(oidc_login)
+            .values(&models::NewOidcLogin {
+                state: &token::digest(&self.state),
+                nonce: &self.nonce,
+                code_verifier: &self.code_verifier,
+                expires_at: now + self.lifetime,
+            })
+            .execute(&conn)?;
+
+        Ok(())
+    }
+}
+
+/// Use up a pending OIDC login, identified by the `state` the provider returned.
+///
+/// Returns `None` if the state is unknown, expired or has already been used.
+pub struct ConsumeOidcLogin {
+    pub state: String,
+}
+
+impl Query for ConsumeOidcLogin {
+    type Output = Option<models::OidcLogin>;
+
+    fn execute(&self, conn: Connection) -> Result<Self::Output> {
+        use self::schema::oidc_login::dsl::*;
+
+        Ok(diesel::delete(
+            oidc_login.filter(
+                state
+                    .eq(token::digest(&self.state))
+                    .and(expires_at.gt(Utc::now())),
+            ),
+        )
+        .get_result::<models::OidcLogin>(&conn)
+        .optional()?)
+    }
+}
+
+/*************************************/
+
+/*************************************/
+
+pub struct StartSession {
+    pub person_id: i32,
+    pub config: SessionConfig,
+    pub device_label: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+/// A newly started session.
+///
+/// This is the only time the plaintext session token exists outside of the client;
+/// it is serialized as `id` so existing clients keep working. It is taken out when
+/// the token is handed over in a cookie instead.
+#[derive(Serialize)]
+#[serde(rename = "session")]
+pub struct StartedSession {
+    #[serde(rename = "id", skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+    pub person_id: i32,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl Query for StartSession {
+    type Output = StartedSession;
+
+    fn execute(&self, conn: Connection) -> Result<Self::Output> {
+        use self::schema::login_session::dsl::*;
+
+        // Create a unique identifier for this session, only its digest is stored
+        let session_token = token::generate();
+
+        let new_session = models::NewSession {
+            id: &token::digest(&session_token),
+            person_id: self.person_id,
+            expires_at: Utc::now() + cmp::min(self.config.lifetime, self.config.max_lifetime),
+            device_label: self.device_label.as_ref().map(String::as_str),
+            user_agent: self.user_agent.as_ref().map(String::as_str),
+        };
+
+        let session = diesel::insert_into(login_session)
+            .values(&new_session)
+            .get_result::<models::Session>(&conn)?;
+
+        Ok(StartedSession {
+            token: Some(session_token),
+            person_id: session.person_id,
+            created_at: session.created_at,
+            expires_at: session.expires_at,
+        })
+    }
+}
+
+/********************************/
+/** Get Session                **/
+/********************************/
+
+pub struct GetSession {
+    pub session_id: String,
+}
+
+impl Query for GetSession {
+    type Output = models::Session;
+
+    fn execute(&self, conn: Connection) -> Result<Self::Output> {
+        use self::schema::login_session::dsl::*;
+
+        Ok(login_session
+            .filter(id.eq(token::digest(&self.session_id)))
+            .first::<models::Session>(&conn)?)
+    }
+}
+
+/********************************/
+/** Get Logged-in Person       **/
+/********************************/
+
+/// This is a `Message` for getting the current active user
+/// given the peron's `session_id`.
+///
+/// Expired sessions are rejected with `Error::SessionExpired`. The session's `last_used_at`
+/// is updated and, if sliding renewal is enabled, its expiry is pushed forward,
+/// up to its maximum lifetime.
+#[derive(Clone)]
+pub struct GetLoggedInPerson {
+    pub session_id: String,
+    pub config: SessionConfig,
+}
+
+impl GetLoggedInPerson {
+    pub fn from_session(session_id: String, config: SessionConfig) -> GetLoggedInPerson {
+        GetLoggedInPerson { session_id, config }
+    }
+}
+
+impl Query for GetLoggedInPerson {
+    type Output = models::Person;
+
+    fn execute(&self, conn: Connection) -> Result<Self::Output> {
+        use self::schema::login_session;
+        use self::schema::person;
+
+        let session_digest = token::digest(&self.session_id);
+
+        let (logged_in, (started_at, expires_at, last_used_at)) = person::table
+            .inner_join(login_session::table)
+            .filter(login_session::id.eq(&session_digest))
+            .select((
+                person::all_columns,
+                (
+                    login_session::created_at,
+                    login_session::expires_at,
+                    login_session::last_used_at,
+                ),
+            ))
+            .first::<(
+                models::Person,
+                (DateTime<Utc>, DateTime<Utc>, Option<DateTime<Utc>>),
+            )>(&conn)
+            .optional()?
+            .ok_or(Error::SessionNotFound)?;
+
+        let now = Utc::now();
+        let renewed = session_expiry(&self.config, started_at, expires_at, now)
+            .ok_or(Error::SessionExpired)?;
+
+        // Record activity on the session, but avoid writing to it on every single request
+        let stale = last_used_at
+            .map(|last_used_at| now - last_used_at >= Duration::minutes(1))
+            .unwrap_or(true);
+
+        if stale {
+            diesel::update(login_session::table.filter(login_session::id.eq(&session_digest)))
+                .set((
+                    login_session::last_used_at.eq(now),
+                    login_session::expires_at.eq(renewed),
+                ))
+                .execute(&conn)?;
+        }
+
+        Ok(logged_in)
+    }
+}
+
+/// When a session that is used at `now` should expire, or `None` if it already has.
+///
+/// No session outlives `max_lifetime` from when it started. With sliding renewal,
+/// using a session pushes its expiry out to `lifetime` from now, up to that limit.
+fn session_expiry(
+    config: &SessionConfig,
+    started_at: DateTime<Utc>,
+    expires_at: DateTime<Utc>,
+    now: DateTime<Utc>,
+) -> Option<DateTime<Utc>> {
+    let hard_limit = started_at + config.max_lifetime;
+
+    if expires_at <= now || hard_limit <= now {
+        return None;
+    }
+
+    Some(match config.sliding {
+        true => cmp::max(cmp::min(now + config.lifetime, hard_limit), expires_at),
+        false => expires_at,
+    })
+}
+
+/********************************/
+/** Session Management         **/
+/********************************/
+
+/// A login session as shown to the person that owns it.
+///
+/// The secret session token is never included; sessions are referred to by `public_id`.
+#[derive(Serialize)]
+#[serde(rename = "session")]
+pub struct SessionSummary {
+    pub id: i32,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub expires_at: DateTime<Utc>,
+    pub device_label: Option<String>,
+    pub user_agent: Option<String>,
+
+    /// Whether this is the session making the request.
+    pub current: bool,
+}
+
+/// Get all of a person's active sessions, most recently used first.
+pub struct GetSessions {
+    pub person_id: i32,
+    pub current_session_id: String,
+
+    /// Sessions started longer ago than this are no longer accepted, whatever
+    /// their expiry says.
+    pub max_lifetime: Duration,
+}
+
+impl Query for GetSessions {
+    type Output = Vec<SessionSummary>;
+
+    fn execute(&self, conn: Connection) -> Result<Self::Output> {
+        use self::schema::login_session::dsl::*;
+
+        let current_digest = token::digest(&self.current_session_id);
+
+        let now = Utc::now();
+
+        let mut sessions = login_session
+            .filter(person_id.eq(self.person_id))
+            .filter(expires_at.gt(now))
+            .filter(created_at.gt(now - self.max_lifetime))
+            .load::<models::Session>(&conn)?;
+
+        // Sessions that have never been used sort by when they were created
+        sessions.sort_by_key(|session| {
+            cmp::Reverse(session.last_used_at.unwrap_or(session.created_at))
+        });
+
+        Ok(sessions
+            .into_iter()
+            .map(|session| SessionSummary {
+                current: session.id == current_digest,
+                id: session.public_id,
+                created_at: session.created_at,
+                last_used_at: session.last_used_at,
+                expires_at: session.expires_at,
+                device_label: session.device_label,
+                user_agent: session.user_agent,
+            })
+            .collect())
+    }
+}
+
+/// End the session identified by its secret token.
+pub struct EndSession {
+    pub session_id: String,
+    pub person_id: i32,
+}
+
+impl Query for EndSession {
+    type Output = usize;
+
+    fn execute(&self, conn: Connection) -> Result<Self::Output> {
+        use self::schema::login_session::dsl::*;
+
+        Ok(diesel::delete(
+            login_session.filter(
+                id.eq(token::digest(&self.session_id))
+                    .and(person_id.eq(self.person_id)),
+            ),
+        )
+        .execute(&conn)?)
+    }
+}
+
+/// Revoke one of a person's sessions by its `public_id`.
+pub struct RevokeSession {
+    pub public_id: i32,
+    pub person_id: i32,
+}
+
+impl Query for RevokeSession {
+    type Output = usize;
+
+    fn execute(&self, conn: Connection) -> Result<Self::Output> {
+        use self::schema::login_session::dsl as session;
+
+        Ok(diesel::delete(
+            session::login_session.filter(
+                session::public_id
+                    .eq(self.public_id)
+                    .and(session::person_id.eq(self.person_id)),
+            ),
+        )
+        .execute(&conn)?)
+    }
+}
+
+/// Revoke all of a person's sessions except for the current one.
+pub struct RevokeOtherSessions {
+    pub person_id: i32,
+    pub current_session_id: String,
+}
+
+impl Query for RevokeOtherSessions {
+    type Output = usize;
+
+    fn execute(&self, conn: Connection) -> Result<Self::Output> {
+        use self::schema::login_session::dsl::*;
+
+        Ok(diesel::delete(
+            login_session.filter(
+                person_id
+                    .eq(self.person_id)
+                    .and(id.ne(token::digest(&self.current_session_id))),
+            ),
+        )
+        .execute(&conn)?)
+    }
+}
+
+/********************************/
+/** API Tokens                 **/
+/********************************/
+
+/// A newly minted API token.
+///
+/// This is the only time the plaintext token exists outside of the client.
+#[derive(Serialize)]
+#[serde(rename = "token")]
+pub struct CreatedApiToken {
+    pub id: i32,
+    pub name: String,
+    pub token: String,
+    pub scopes: Vec<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+pub struct CreateApiToken {
+    pub person_id: i32,
+    pub name: String,
+    pub scopes: Vec<Scope>,
+}
+
+impl Query for CreateApiToken {
+    type Output = CreatedApiToken;
+
+    fn execute(&self, conn: Connection) -> Result<Self::Output> {
+        use self::schema::api_token::dsl::*;
+
+        let new_token = format!("{}{}", API_TOKEN_PREFIX, token::generate());
+
+        let details = diesel::insert_into(api_token)
+            .values(&models::NewApiToken {
+                person_id: self.person_id,
+                name: &self.name,
+                digest: &token::digest(&new_token),
+                scopes: self.scopes.iter().map(Scope::as_str).collect(),
+            })
+            .get_result::<models::ApiToken>(&conn)?;
+
+        Ok(CreatedApiToken {
+            id: details.id,
+            name: details.name,
+            token: new_token,
+            scopes: details.scopes,
+            created_at: details.created_at,
+        })
+    }
+}
+
+pub struct GetApiTokens {
+    pub person_id: i32,
+}
+
+impl Query for GetApiTokens {
+    type Output = Vec<models::ApiToken>;
+
+    fn execute(&self, conn: Connection) -> Result<Self::Output> {
+        use self::schema::api_token::dsl::*;
+
+        Ok(api_token
+            .filter(person_id.eq(self.person_id))
+            .order(created_at.desc())
+            .load::<models::ApiToken>(&conn)?)
+    }
+}
+
+pub struct DeleteApiToken {
+    pub token_id: i32,
+    pub person_id: i32,
+}
+
+impl Query for DeleteApiToken {
+    type Output = usize;
+
+    fn execute(&self, conn: Connection) -> Result<Self::Output> {
+        use self::schema::api_token::dsl::*;
+
+        Ok(
+            diesel::delete(api_token.filter(id.eq(self.token_id).and(person_id.eq(self.person_id))))
+                .execute(&conn)?,
+        )
+    }
+}
+
+/// Get the person that owns an API token, provided that the token has been granted `scope`.
+pub struct GetApiTokenPerson {
+    pub token: String,
+    pub scope: Scope,
+}
+
+impl Query for GetApiTokenPerson {
+    type Output = models::Person;
+
+    fn execute(&self, conn: Connection) -> Result<Self::Output> {
+        use self::schema::api_token;
+        use self::schema::person;
+
+        let token_digest = token::digest(&self.token);
+
+        let (owner, (token_id, scopes, last_used_at)) = person::table
+            .inner_join(api_token::table)
+            .filter(api_token::digest.eq(&token_digest))
             .select((
-                drink::id,
-                drink::drank_on,
-                beer::name,
-                brewery::name,
-                drink::rating,
-                drink::comment,
+                person::all_columns,
+                (api_token::id, api_token::scopes, api_token::last_used_at),
             ))
-            .filter(drink::id.eq(&self.drink_id))
-            .first::<ExpandedDrink>(&conn)?)
+            .first::<(models::Person, (i32, Vec<String>, Option<DateTime<Utc>>))>(&conn)
+            .optional()?
+            .ok_or(Error::SessionNotFound)?;
+
+        if !scopes.iter().any(|granted| granted == self.scope.as_str()) {
+            return Err(Error::InsufficientScope);
+        }
+
+        // Record activity on the token, but avoid writing to it on every single request
+        let now = Utc::now();
+        let stale = last_used_at
+            .map(|last_used_at| now - last_used_at >= Duration::minutes(1))
+            .unwrap_or(true);
+
+        if stale {
+            diesel::update(api_token::table.filter(api_token::id.eq(token_id)))
+                .set(api_token::last_used_at.eq(now))
+                .execute(&conn)?;
+        }
+
+        Ok(owner)
+    }
+}
+
+/********************************/
+/** Personal Data              **/
+/********************************/
+
+/// Everything stored about a person.
+#[derive(Serialize)]
+#[serde(rename = "export")]
+pub struct PersonalData {
+    pub exported_at: DateTime<Utc>,
+    pub person: models::Person,
+    pub identities: Vec<models::Identity>,
+
+    /// Session metadata; the tokens themselves are never included.
+    pub sessions: Vec<models::Session>,
+    pub api_tokens: Vec<models::ApiToken>,
+    pub drinks: Vec<ExpandedDrink>,
+    pub auth_events: Vec<models::AuthEvent>,
+}
+
+/// Gather everything stored about a person, for them to take with them.
+pub struct GetPersonalData {
+    pub person_id: i32,
+}
+
+impl Query for GetPersonalData {
+    type Output = PersonalData;
+
+    fn execute(&self, conn: Connection) -> Result<Self::Output> {
+        use self::schema::{api_token, auth_event, identity, login_session, person};
+
+        // Read everything from the same snapshot
+        conn.build_transaction()
+            .read_only()
+            .repeatable_read()
+            .run(|| {
+                Ok(PersonalData {
+                    exported_at: Utc::now(),
+                    person: person::table
+                        .find(self.person_id)
+                        .first::<models::Person>(&conn)?,
+                    identities: identity::table
+                        .filter(identity::person_id.eq(self.person_id))
+                        .order(identity::created_at.asc())
+                        .load::<models::Identity>(&conn)?,
+                    sessions: login_session::table
+                        .filter(login_session::person_id.eq(self.person_id))
+                        .order(login_session::created_at.asc())
+                        .load::<models::Session>(&conn)?,
+                    api_tokens: api_token::table
+                        .filter(api_token::person_id.eq(self.person_id))
+                        .order(api_token::created_at.asc())
+                        .load::<models::ApiToken>(&conn)?,
+                    drinks: expanded_drinks(&conn, self.person_id)?,
+                    auth_events: auth_event::table
+                        .filter(auth_event::person_id.eq(self.person_id))
+                        .order(auth_event::id.asc())
+                        .load::<models::AuthEvent>(&conn)?,
+                })
+            })
+    }
+}
+
+/// Schedule a person's account for deletion, keeping the original request time
+/// if they had already asked.
+pub struct RequestDeletion {
+    pub person_id: i32,
+}
+
+impl Query for RequestDeletion {
+    type Output = models::Person;
+
+    fn execute(&self, conn: Connection) -> Result<Self::Output> {
+        use self::schema::person::dsl::*;
+
+        Ok(diesel::update(person.find(self.person_id))
+            .set(deletion_requested_at.eq(coalesce(deletion_requested_at, Utc::now()).nullable()))
+            .get_result::<models::Person>(&conn)?)
+    }
+}
+
+/// Cancel a pending account deletion.
+pub struct CancelDeletion {
+    pub person_id: i32,
+}
+
+impl Query for CancelDeletion {
+    type Output = models::Person;
+
+    fn execute(&self, conn: Connection) -> Result<Self::Output> {
+        use self::schema::person::dsl::*;
+
+        Ok(diesel::update(person.find(self.person_id))
+            .set(deletion_requested_at.eq(None::<DateTime<Utc>>))
+            .get_result::<models::Person>(&conn)?)
+    }
+}
+
+/// Permanently delete everyone whose deletion grace period is over.
+///
+/// Drinks, identities, sessions and API tokens go with them through `ON DELETE CASCADE`.
+pub struct PurgeDeletedPersons {
+    pub grace: Duration,
+}
+
+/// The accounts that were purged, and the photos and avatars left to remove
+/// from the blob store.
+pub struct PurgedPersons {
+    pub persons: usize,
+    pub photo_keys: Vec<String>,
+}
+
+impl Query for PurgeDeletedPersons {
+    type Output = PurgedPersons;
+
+    fn execute(&self, conn: Connection) -> Result<Self::Output> {
+        use self::schema::drink;
+        use self::schema::drink_photo;
+        use self::schema::person::dsl::*;
+
+        let cutoff = Utc::now() - self.grace;
+
+        conn.transaction::<_, Error, _>(|| {
+            let photos = drink_photo::table
+                .inner_join(drink::table.inner_join(person))
+                .select(drink_photo::all_columns)
+                .filter(deletion_requested_at.le(cutoff))
+                .load::<models::DrinkPhoto>(&conn)?;
+
+            let avatars = person
+                .select(avatar_key)
+                .filter(deletion_requested_at.le(cutoff))
+                .filter(avatar_key.is_not_null())
+                .load::<Option<String>>(&conn)?;
+
+            let persons =
+                diesel::delete(person.filter(deletion_requested_at.le(cutoff))).execute(&conn)?;
+
+            let mut keys = photo_keys(photos);
+            keys.extend(avatars.into_iter().flatten());
+
+            Ok(PurgedPersons {
+                persons,
+                photo_keys: keys,
+            })
+        })
     }
 }
 
-/*************************************/
-/** Delete Drink message            **/
-/*************************************/
+/********************************/
+/** Profiles                   **/
+/********************************/
+
+pub enum ProfileOutcome {
+    /// The updated person, and the key of an uploaded avatar that was replaced,
+    /// which should be removed from the blob store.
+    Updated(models::Person, Option<String>),
 
-pub struct DeleteDrink {
-    pub drink_id: i32,
+    /// Someone else already has the requested handle.
+    HandleTaken,
+}
+
+/// Change the current person's profile.
+pub struct UpdateProfile {
     pub person_id: i32,
+    pub changes: models::ProfileChanges,
 }
 
-impl Query for DeleteDrink {
-    type Output = usize;
+impl Query for UpdateProfile {
+    type Output = ProfileOutcome;
 
     fn execute(&self, conn: Connection) -> Result<Self::Output> {
-        use super::schema::drink::dsl::*;
-
-        Ok(
-            diesel::delete(drink.filter(id.eq(self.drink_id).and(person_id.eq(self.person_id))))
-                .execute(&conn)?,
-        )
+        use self::schema::person::dsl::*;
+        use diesel::result::{DatabaseErrorKind, Error as DieselError};
+
+        conn.transaction::<_, Error, _>(|| {
+            let replaced = match self.changes.avatar_key {
+                Some(_) => person
+                    .select(avatar_key)
+                    .find(self.person_id)
+                    .for_update()
+                    .first::<Option<String>>(&conn)?,
+                None => None,
+            };
+
+            let updated = diesel::update(person.find(self.person_id))
+                .set(&self.changes)
+                .get_result::<models::Person>(&conn);
+
+            match updated {
+                Ok(updated) => Ok(ProfileOutcome::Updated(updated, replaced)),
+                Err(DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => {
+                    Ok(ProfileOutcome::HandleTaken)
+                }
+                Err(e) => Err(e.into()),
+            }
+        })
     }
 }
 
-/*************************************/
-/*************************************/
-
-pub struct GetBreweryByName {
-    pub name: String,
+/// Point the current person's avatar at one that was uploaded to the blob store.
+///
+/// Returns the updated person, and the key of the uploaded avatar it replaced.
+pub struct SetAvatar {
+    pub person_id: i32,
+    pub avatar_key: String,
 }
 
-impl Query for GetBreweryByName {
-    type Output = Option<models::Brewery>;
+impl Query for SetAvatar {
+    type Output = (models::Person, Option<String>);
 
     fn execute(&self, conn: Connection) -> Result<Self::Output> {
-        use super::schema::brewery::dsl::*;
+        use self::schema::person::dsl::*;
 
-        Ok(brewery
-            .filter(lower(name).eq(&self.name.to_lowercase()))
-            .first::<models::Brewery>(&conn)
-            .optional()?)
+        conn.transaction::<_, Error, _>(|| {
+            let replaced = person
+                .select(avatar_key)
+                .find(self.person_id)
+                .for_update()
+                .first::<Option<String>>(&conn)?;
+
+            let updated = diesel::update(person.find(self.person_id))
+                .set((
+                    avatar_key.eq(&self.avatar_key),
+                    avatar_url.eq(photos::url(&self.avatar_key)),
+                ))
+                .get_result::<models::Person>(&conn)?;
+
+            Ok((updated, replaced))
+        })
     }
 }
 
-/*************************************/
-/*************************************/
-
-pub struct GetBeerByName {
-    pub name: String,
-    pub brewery_id: i32,
+/// Find a person by their handle, unless they are deleting their account.
+pub struct GetPersonByHandle {
+    pub handle: String,
 }
 
-impl Query for GetBeerByName {
-    type Output = Option<models::Beer>;
+impl Query for GetPersonByHandle {
+    type Output = Option<models::Person>;
 
     fn execute(&self, conn: Connection) -> Result<Self::Output> {
-        use super::schema::beer::dsl::*;
+        use self::schema::person::dsl::*;
 
-        Ok(beer
-            .filter(
-                lower(name)
-                    .eq(&self.name.to_lowercase())
-                    .and(brewery_id.eq(&self.brewery_id)),
-            )
-            .first::<models::Beer>(&conn)
+        Ok(person
+            .filter(handle.eq(&self.handle))
+            .filter(deletion_requested_at.is_null())
+            .first::<models::Person>(&conn)
             .optional()?)
     }
 }
 
-/*************************************/
-/*************************************/
+/********************************/
+/** Audit Log                  **/
+/********************************/
 
-pub struct CreateBrewery {
-    pub name: String,
+/// Record an authentication event.
+///
+/// Events about an identity that belongs to someone are attached to that person,
+/// so that they can see failed attempts to log in to their account.
+pub struct RecordAuthEvent {
+    pub kind: AuthEventKind,
+    pub person_id: Option<i32>,
+    pub identity: Option<(models::IdentityKind, String)>,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+    pub detail: Option<String>,
 }
 
-impl Query for CreateBrewery {
-    type Output = models::Brewery;
+impl Query for RecordAuthEvent {
+    type Output = ();
 
     fn execute(&self, conn: Connection) -> Result<Self::Output> {
-        use super::schema::brewery::dsl::*;
+        let owner = match (self.person_id, &self.identity) {
+            (Some(owner), _) => Some(owner),
+            (None, Some((identity_kind, ident))) => {
+                use self::schema::identity::dsl::*;
+
+                identity
+                    .filter(kind.eq(identity_kind.as_str()))
+                    .filter(identifier.eq(ident))
+                    .select(person_id)
+                    .first::<i32>(&conn)
+                    .optional()?
+            }
+            (None, None) => None,
+        };
 
-        let new_brewery = models::NewBrewery { name: &self.name };
+        diesel::insert_into(schema::auth_event::table)
+            .values(&models::NewAuthEvent {
+                kind: self.kind.as_str(),
+                person_id: owner,
+                identity_kind: self.identity.as_ref().map(|(kind, _)| kind.as_str()),
+                identifier: self.identity.as_ref().map(|(_, ident)| ident.as_str()),
+                ip_address: self.ip_address.as_ref().map(String::as_str),
+                user_agent: self.user_agent.as_ref().map(String::as_str),
+                detail: self.detail.as_ref().map(String::as_str),
+            })
+            .execute(&conn)?;
 
-        Ok(diesel::insert_into(brewery)
-            .values(new_brewery)
-            .get_result(&conn)?)
+        Ok(())
     }
 }
 
-/*************************************/
-/*************************************/
+/// List authentication events, newest first.
+pub struct GetAuthEvents {
+    pub person_id: Option<i32>,
+    pub identifier: Option<String>,
+    pub kind: Option<AuthEventKind>,
 
-pub struct CreateBeer {
-    pub name: String,
-    pub brewery_id: i32,
+    /// Only events older than this one, for paging through the log.
+    pub before: Option<i32>,
+    pub limit: i64,
 }
 
-impl Query for CreateBeer {
-    type Output = models::Beer;
+impl Query for GetAuthEvents {
+    type Output = Vec<models::AuthEvent>;
 
     fn execute(&self, conn: Connection) -> Result<Self::Output> {
-        use super::schema::beer::dsl::*;
+        use self::schema::auth_event::dsl::*;
 
-        let new_beer = models::NewBeer {
-            name: &self.name,
-            brewery_id: self.brewery_id,
-        };
+        let mut query = auth_event.order(id.desc()).limit(self.limit).into_boxed();
 
-        Ok(diesel::insert_into(beer)
-            .values(new_beer)
-            .get_result(&conn)?)
+        if let Some(wanted) = self.person_id {
+            query = query.filter(person_id.eq(wanted));
+        }
+
+        if let Some(ref wanted) = self.identifier {
+            query = query.filter(identifier.eq(wanted));
+        }
+
+        if let Some(wanted) = self.kind {
+            query = query.filter(kind.eq(wanted.as_str()));
+        }
+
+        if let Some(before) = self.before {
+            query = query.filter(id.lt(before));
+        }
+
+        Ok(query.load::<models::AuthEvent>(&conn)?)
     }
 }
 
-/*************************************/
-/* Login and Registration            */
-/*************************************/
+/********************************/
+/** Administration             **/
+/********************************/
 
-pub struct LookupIdentiy {
-    pub identifier: String,
+/// List people, optionally only those with a given role.
+pub struct GetPeople {
+    pub role: Option<Role>,
 }
 
-impl Query for LookupIdentiy {
-    type Output = models::Identity;
+impl Query for GetPeople {
+    type Output = Vec<models::Person>;
 
     fn execute(&self, conn: Connection) -> Result<Self::Output> {
-        use self::schema::identity::dsl::*;
         use self::schema::person::dsl::*;
 
-        // Query to see if a matching Identity exists
-        let existing_identity = identity
-            .filter(identifier.eq(&self.identifier))
-            .first::<models::Identity>(&conn)
-            .optional()?;
+        let mut query = person.order(id.asc()).into_boxed();
 
-        // If an Identity was found, return it
-        if let Some(existing_identity) = existing_identity {
-            info!(
-                "Found existing identity matching '{}', person {}.",
-                existing_identity.identifier, existing_identity.person_id
-            );
-            return Ok(existing_identity);
+        if let Some(ref wanted) = self.role {
+            query = query.filter(role.eq(wanted.as_str()));
         }
 
-        // If here, then no identity was found
-        // Create a new person to go with that identity
-        let new_person = diesel::insert_into(person) /* lol */
-            .default_values() // currently no other values need to be inserted at the moment
-            .get_result::<models::Person>(&conn)?;
+        Ok(query.load::<models::Person>(&conn)?)
+    }
+}
 
-        // Insert the new identity
-        let new_identity = diesel::insert_into(identity)
-            .values(&models::NewIdentity {
-                identifier: &self.identifier,
-                person_id: new_person.id,
-            })
-            .get_result::<models::Identity>(&conn)?;
+/// Change a person's role, returning `None` if there is no such person.
+pub struct SetRole {
+    pub person_id: i32,
+    pub role: Role,
+}
 
-        info!(
-            "Created new identity matching '{}' for person {}.",
-            new_identity.identifier, new_identity.person_id
-        );
+impl Query for SetRole {
+    type Output = Option<models::Person>;
 
-        Ok(new_identity)
+    fn execute(&self, conn: Connection) -> Result<Self::Output> {
+        use self::schema::person::dsl::*;
+
+        Ok(diesel::update(person.find(self.person_id))
+            .set(role.eq(self.role.as_str()))
+            .get_result::<models::Person>(&conn)
+            .optional()?)
     }
 }
 
-/*************************************/
+/// The result of moderating a beer or brewery.
+pub enum CatalogEdit<T> {
+    Done(T),
 
-/*************************************/
+    /// There's no beer or brewery with that id.
+    NotFound,
 
-pub struct StartSession {
-    pub person_id: i32,
+    /// Another entry already has that name; its id is given so the two can be merged.
+    Duplicate(i32),
 }
 
-impl Query for StartSession {
-    type Output = models::Session;
+/// Correct the name of a brewery.
+pub struct RenameBrewery {
+    pub brewery_id: i32,
+    pub name: String,
+}
+
+impl Query for RenameBrewery {
+    type Output = CatalogEdit<models::Brewery>;
 
     fn execute(&self, conn: Connection) -> Result<Self::Output> {
-        use self::schema::login_session::dsl::*;
+        use self::schema::brewery::dsl::*;
+
+        conn.transaction::<_, Error, _>(|| {
+            let find_duplicate = || {
+                brewery
+                    .select(id)
+                    .filter(
+                        lower(name)
+                            .eq(&self.name.to_lowercase())
+                            .and(id.ne(self.brewery_id)),
+                    )
+                    .first::<i32>(&conn)
+                    .optional()
+            };
+
+            if let Some(duplicate) = find_duplicate()? {
+                return Ok(CatalogEdit::Duplicate(duplicate));
+            }
+
+            let renamed = conn.transaction(|| {
+                diesel::update(brewery.find(self.brewery_id))
+                    .set(name.eq(&self.name))
+                    .get_result::<models::Brewery>(&conn)
+                    .optional()
+            });
+
+            catalog_renamed(renamed, find_duplicate)
+        })
+    }
+}
 
-        // Create a unique identifier for this session
-        let nonce = TextNonce::sized(64).unwrap();
+/// Correct the name of a beer.
+pub struct RenameBeer {
+    pub beer_id: i32,
+    pub name: String,
+}
 
-        let new_session = models::NewSession {
-            id: &nonce,
-            person_id: self.person_id,
-            expires_at: Utc::now() + Duration::weeks(2),
-        };
+impl Query for RenameBeer {
+    type Output = CatalogEdit<models::Beer>;
 
-        Ok(diesel::insert_into(login_session)
-            .values(&new_session)
-            .get_result::<models::Session>(&conn)?)
+    fn execute(&self, conn: Connection) -> Result<Self::Output> {
+        use self::schema::beer::dsl::*;
+
+        conn.transaction::<_, Error, _>(|| {
+            let found = beer
+                .select(brewery_id)
+                .find(self.beer_id)
+                .for_update()
+                .first::<i32>(&conn)
+                .optional()?;
+
+            let listed_under = match found {
+                Some(listed_under) => listed_under,
+                None => return Ok(CatalogEdit::NotFound),
+            };
+
+            // Beers only need unique names within their brewery
+            let find_duplicate = || {
+                beer.select(id)
+                    .filter(
+                        lower(name)
+                            .eq(&self.name.to_lowercase())
+                            .and(brewery_id.eq(listed_under))
+                            .and(id.ne(self.beer_id)),
+                    )
+                    .first::<i32>(&conn)
+                    .optional()
+            };
+
+            if let Some(duplicate) = find_duplicate()? {
+                return Ok(CatalogEdit::Duplicate(duplicate));
+            }
+
+            let renamed = conn.transaction(|| {
+                diesel::update(beer.find(self.beer_id))
+                    .set(name.eq(&self.name))
+                    .get_result::<models::Beer>(&conn)
+                    .optional()
+            });
+
+            catalog_renamed(renamed, find_duplicate)
+        })
     }
 }
 
-/********************************/
-/** Get Session                **/
-/********************************/
+/// The outcome of renaming a beer or brewery, from the result of the update.
+///
+/// Someone else can take the name between checking for a duplicate and
+/// renaming, which the unique index catches. The update runs in a nested
+/// transaction, so the outer one can still look up who took it.
+fn catalog_renamed<T, F>(
+    renamed: QueryResult<Option<T>>,
+    find_duplicate: F,
+) -> Result<CatalogEdit<T>>
+where
+    F: Fn() -> QueryResult<Option<i32>>,
+{
+    use diesel::result::{DatabaseErrorKind, Error as DieselError};
+
+    match renamed {
+        Ok(renamed) => Ok(renamed.map_or(CatalogEdit::NotFound, CatalogEdit::Done)),
+        Err(e @ DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => {
+            match find_duplicate()? {
+                Some(duplicate) => Ok(CatalogEdit::Duplicate(duplicate)),
+                None => Err(e.into()),
+            }
+        }
+        Err(e) => Err(e.into()),
+    }
+}
 
-pub struct GetSession {
-    pub session_id: String,
+/// Merge a duplicate brewery into another, moving its beers across.
+///
+/// A beer that the other brewery already lists under the same name is merged
+/// into that one. Returns the number of beers that were moved or merged.
+pub struct MergeBrewery {
+    pub from: i32,
+    pub into: i32,
 }
 
-impl Query for GetSession {
-    type Output = models::Session;
+impl Query for MergeBrewery {
+    type Output = CatalogEdit<usize>;
 
     fn execute(&self, conn: Connection) -> Result<Self::Output> {
-        use self::schema::login_session::dsl::*;
-
-        Ok(login_session
-            .filter(id.eq(&self.session_id))
-            .first::<models::Session>(&conn)?)
-    }
-}
+        use self::schema::{beer, brewery, drink};
+
+        conn.transaction::<_, Error, _>(|| {
+            let found = brewery::table
+                .filter(brewery::id.eq_any(vec![self.from, self.into]))
+                .count()
+                .get_result::<i64>(&conn)?;
+
+            if found != 2 {
+                return Ok(CatalogEdit::NotFound);
+            }
+
+            let listed = beer::table
+                .select((beer::id, lower(beer::name)))
+                .filter(beer::brewery_id.eq(self.into))
+                .load::<(i32, String)>(&conn)?
+                .into_iter()
+                .map(|(id, name)| (name, id))
+                .collect::<HashMap<_, _>>();
+
+            let moving = beer::table
+                .select((beer::id, lower(beer::name)))
+                .filter(beer::brewery_id.eq(self.from))
+                .load::<(i32, String)>(&conn)?;
+
+            for (beer_id, beer_name) in &moving {
+                match listed.get(beer_name) {
+                    Some(&into) => {
+                        diesel::update(drink::table.filter(drink::beer_id.eq(beer_id)))
+                            .set(drink::beer_id.eq(into))
+                            .execute(&conn)?;
+                        diesel::delete(beer::table.find(beer_id)).execute(&conn)?;
+                    }
+                    None => {
+                        diesel::update(beer::table.find(beer_id))
+                            .set(beer::brewery_id.eq(self.into))
+                            .execute(&conn)?;
+                    }
+                }
+            }
+
+            let moved = moving.len();
+
+            diesel::delete(brewery::table.find(self.from)).execute(&conn)?;
 
-/********************************/
-/** Get Logged-in Person       **/
-/********************************/
+            info!(
+                "Merged brewery {} into brewery {}, moving {} beers.",
+                self.from, self.into, moved
+            );
 
-/// This is a `Message` for getting the current active user
-/// given the peron's `session_id`.
-#[derive(Clone)]
-pub struct GetLoggedInPerson {
-    pub session_id: String,
+            Ok(CatalogEdit::Done(moved))
+        })
+    }
 }
 
-impl GetLoggedInPerson {
-    pub fn from_session(session_id: String) -> GetLoggedInPerson {
-        GetLoggedInPerson { session_id }
-    }
+/// Merge a duplicate beer into another, moving everyone's drinks across.
+///
+/// Returns the number of drinks that were moved.
+pub struct MergeBeer {
+    pub from: i32,
+    pub into: i32,
 }
 
-impl Query for GetLoggedInPerson {
-    type Output = models::Person;
+impl Query for MergeBeer {
+    type Output = CatalogEdit<usize>;
 
     fn execute(&self, conn: Connection) -> Result<Self::Output> {
-        use self::schema::login_session::dsl::id as sid;
-        use self::schema::login_session::dsl::login_session;
-        use self::schema::person::dsl::*;
+        use self::schema::{beer, drink};
 
-        Ok(person
-            .inner_join(login_session)
-            .filter(sid.eq(&self.session_id))
-            .select((id, created_at, updated_at))
-            .first::<models::Person>(&conn)?)
+        conn.transaction::<_, Error, _>(|| {
+            let found = beer::table
+                .filter(beer::id.eq_any(vec![self.from, self.into]))
+                .count()
+                .get_result::<i64>(&conn)?;
+
+            if found != 2 {
+                return Ok(CatalogEdit::NotFound);
+            }
+
+            let moved = diesel::update(drink::table.filter(drink::beer_id.eq(self.from)))
+                .set(drink::beer_id.eq(self.into))
+                .execute(&conn)?;
+
+            diesel::delete(beer::table.find(self.from)).execute(&conn)?;
+
+            info!(
+                "Merged beer {} into beer {}, moving {} drinks.",
+                self.from, self.into, moved
+            );
+
+            Ok(CatalogEdit::Done(moved))
+        })
     }
 }
 
@@ -531,7 +3369,77 @@ fn tsquery_string(text: &str) -> String {
 
 #[cfg(test)]
 mod tests {
-    use super::tsquery_string;
+    use super::{find_or_create_beer, merge_persons, photo_access, plan_import, tsquery_string};
+    use super::{import, models, schema, AuthEventKind, Error, Rating};
+    use super::{session_expiry, GetBreweryByName, GetDrink, SessionConfig, UpdateDrink};
+    use super::{DrinkCursor, DrinkOutcome, DrinkSort, DrinkSortKey, PhotoAccess};
+    use chrono::{DateTime, Duration, NaiveDate, Utc};
+    use diesel::prelude::*;
+
+    /// Connect to the database in `TEST_DATABASE_URL`, which must have every
+    /// migration applied. Tests that need it are ignored unless run with
+    /// `cargo test -- --ignored`, and roll back everything they do.
+    fn test_connection() -> PgConnection {
+        let url = std::env::var("TEST_DATABASE_URL").expect("TEST_DATABASE_URL must be set!");
+
+        PgConnection::establish(&url).expect("Failed to connect to the test database!")
+    }
+
+    fn new_person(conn: &PgConnection) -> models::Person {
+        diesel::insert_into(schema::person::table)
+            .default_values()
+            .get_result(conn)
+            .unwrap()
+    }
+
+    fn new_drink(conn: &PgConnection, person_id: i32, beer_id: i32) -> i32 {
+        use super::schema::drink;
+
+        diesel::insert_into(drink::table)
+            .values((
+                drink::person_id.eq(person_id),
+                drink::drank_on.eq(NaiveDate::from_ymd(2019, 1, 2)),
+                drink::beer_id.eq(beer_id),
+                drink::rating_quarters.eq(16),
+            ))
+            .returning(drink::id)
+            .get_result(conn)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_session_expiry() {
+        let started_at = "2019-01-01T12:00:00Z".parse::<DateTime<Utc>>().unwrap();
+        let expires_at = started_at + Duration::days(14);
+        let config = |sliding| SessionConfig {
+            lifetime: Duration::days(14),
+            sliding,
+            max_lifetime: Duration::days(30),
+            cookie: None,
+        };
+        let expiry = |sliding, expires_at, days| {
+            session_expiry(
+                &config(sliding),
+                started_at,
+                expires_at,
+                started_at + Duration::days(days),
+            )
+        };
+
+        assert_eq!(Some(expires_at), expiry(false, expires_at, 10));
+        assert_eq!(None, expiry(false, expires_at, 14));
+
+        // Renewal pushes the expiry out from now, but never past the maximum lifetime
+        assert_eq!(
+            Some(started_at + Duration::days(24)),
+            expiry(true, expires_at, 10)
+        );
+        assert_eq!(
+            Some(started_at + Duration::days(30)),
+            expiry(true, started_at + Duration::days(28), 25)
+        );
+        assert_eq!(None, expiry(true, started_at + Duration::days(40), 30));
+    }
 
     #[test]
     fn test_tsquery_string() {
@@ -547,4 +3455,324 @@ mod tests {
         assert_eq!("test-:*", tsquery_string("test--"));
         assert_eq!("test-:*", tsquery_string("test-?-"));
     }
+
+    #[test]
+    fn test_drink_sort() {
+        assert_eq!(
+            Ok(DrinkSort {
+                key: DrinkSortKey::Rating,
+                descending: true,
+            }),
+            "-rating".parse()
+        );
+        assert_eq!("drank_on", DrinkSort::default().to_string());
+        assert!("-comment".parse::<DrinkSort>().is_err());
+    }
+
+    #[test]
+    fn test_drink_cursor() {
+        let cursor = DrinkCursor {
+            sort: "-drank_on".parse().unwrap(),
+            drank_on: NaiveDate::from_ymd(2019, 1, 2),
+            rating: "3.75".parse().unwrap(),
+            id: 17,
+        };
+
+        assert_eq!(Ok(cursor), cursor.to_string().parse());
+        assert!("not a cursor".parse::<DrinkCursor>().is_err());
+    }
+
+    #[test]
+    #[ignore]
+    fn test_beer_names_are_unique_per_brewery() {
+        let conn = test_connection();
+
+        conn.test_transaction::<_, Error, _>(|| {
+            let pale_ale = find_or_create_beer(&conn, "Test Brewery A", "Test Pale Ale")?;
+
+            assert_eq!(
+                pale_ale.id,
+                find_or_create_beer(&conn, "test brewery a", "TEST PALE ALE")?.id
+            );
+
+            let elsewhere = find_or_create_beer(&conn, "Test Brewery B", "Test Pale Ale")?;
+            assert_ne!(pale_ale.id, elsewhere.id);
+            assert_ne!(pale_ale.brewery_id, elsewhere.brewery_id);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    #[ignore]
+    fn test_plan_import() {
+        let conn = test_connection();
+
+        conn.test_transaction::<_, Error, _>(|| {
+            let listed = find_or_create_beer(&conn, "Test Brewery A", "Test Pale Ale")?;
+
+            let row = |line, brewery: &str, beer: &str| import::ImportRow {
+                line,
+                drank_on: NaiveDate::from_ymd(2019, 1, 2),
+                beer: beer.into(),
+                brewery: brewery.into(),
+                rating: "4".parse().unwrap(),
+                comment: None,
+            };
+            let rows = vec![
+                row(2, "test brewery a", "TEST PALE ALE"),
+                row(3, "Test Brewery A", "Test Stout"),
+                row(4, "Test Brewery B", "Test Pale Ale"),
+            ];
+
+            let mut report = import::ImportReport {
+                dry_run: true,
+                imported: 0,
+                breweries: Vec::new(),
+                beers: Vec::new(),
+                errors: Vec::new(),
+            };
+            plan_import(&conn, &rows, &mut report)?;
+
+            assert_eq!(
+                vec![Some(listed.brewery_id), None],
+                report.breweries.iter().map(|b| b.id).collect::<Vec<_>>()
+            );
+            assert_eq!(
+                vec![Some(listed.id), None, None],
+                report.beers.iter().map(|b| b.id).collect::<Vec<_>>()
+            );
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    #[ignore]
+    fn test_photo_access() {
+        use super::schema::{drink, drink_photo, person};
+
+        let conn = test_connection();
+
+        conn.test_transaction::<_, Error, _>(|| {
+            let (drinker, viewer) = (new_person(&conn), new_person(&conn));
+            let beer = find_or_create_beer(&conn, "Test Brewery", "Test Pale Ale")?;
+
+            let drink_id = new_drink(&conn, drinker.id, beer.id);
+
+            diesel::insert_into(drink_photo::table)
+                .values(&models::NewDrinkPhoto {
+                    drink_id,
+                    blob_key: "test-photo.jpg",
+                    thumbnail_key: "test-photo-thumb.jpg",
+                    width: 640,
+                    height: 480,
+                })
+                .execute(&conn)?;
+
+            diesel::update(person::table.find(viewer.id))
+                .set(person::avatar_key.eq("test-avatar.jpg"))
+                .execute(&conn)?;
+
+            let access = |key, viewer_id| photo_access(&conn, key, viewer_id).unwrap();
+
+            assert_eq!(
+                PhotoAccess::Owner,
+                access("test-photo.jpg", Some(drinker.id))
+            );
+            assert_eq!(
+                PhotoAccess::Hidden,
+                access("test-photo-thumb.jpg", Some(viewer.id))
+            );
+            assert_eq!(PhotoAccess::Hidden, access("test-photo.jpg", None));
+            assert_eq!(PhotoAccess::Public, access("test-avatar.jpg", None));
+            assert_eq!(PhotoAccess::Hidden, access("missing.jpg", Some(drinker.id)));
+
+            diesel::update(drink::table.find(drink_id))
+                .set(drink::shared.eq(true))
+                .execute(&conn)?;
+
+            assert_eq!(PhotoAccess::Public, access("test-photo-thumb.jpg", None));
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    #[ignore]
+    fn test_drink_visibility() {
+        use super::schema::{drink, person};
+
+        let conn = test_connection();
+
+        conn.test_transaction::<_, Error, _>(|| {
+            let (drinker, viewer) = (new_person(&conn), new_person(&conn));
+            let beer = find_or_create_beer(&conn, "Test Brewery", "Test Pale Ale")?;
+            let drink_id = new_drink(&conn, drinker.id, beer.id);
+
+            let visible = |viewer_id| {
+                GetDrink {
+                    drink_id,
+                    viewer_id,
+                }
+                .find(&conn)
+                .unwrap()
+                .map(|drink| drink.id)
+            };
+
+            assert_eq!(Some(drink_id), visible(drinker.id));
+            assert_eq!(None, visible(viewer.id));
+
+            diesel::update(drink::table.find(drink_id))
+                .set(drink::shared.eq(true))
+                .execute(&conn)?;
+
+            assert_eq!(Some(drink_id), visible(viewer.id));
+
+            // Shared drinks are hidden while their drinker is deleting their account
+            diesel::update(person::table.find(drinker.id))
+                .set(person::deletion_requested_at.eq(diesel::dsl::now))
+                .execute(&conn)?;
+
+            assert_eq!(None, visible(viewer.id));
+            assert_eq!(Some(drink_id), visible(drinker.id));
+
+            let missing = GetDrink {
+                drink_id: drink_id + 1,
+                viewer_id: drinker.id,
+            };
+            assert!(missing.find(&conn)?.is_none());
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    #[ignore]
+    fn test_only_owners_update_drinks() {
+        let conn = test_connection();
+
+        conn.test_transaction::<_, Error, _>(|| {
+            let (drinker, other) = (new_person(&conn), new_person(&conn));
+            let beer = find_or_create_beer(&conn, "Test Brewery", "Test Pale Ale")?;
+            let drink_id = new_drink(&conn, drinker.id, beer.id);
+
+            let update = |person_id, brewery_name: &str| UpdateDrink {
+                drink_id,
+                person_id,
+                beer: Some((brewery_name.to_string(), "Test Stout".to_string())),
+                changes: models::DrinkChanges {
+                    rating: Rating::from_quarters(4),
+                    ..Default::default()
+                },
+            };
+
+            match update(other.id, "Other Brewery").update(&conn)? {
+                DrinkOutcome::NotFound => {}
+                _ => panic!("Someone else's drink was updated!"),
+            }
+
+            // Nothing was added to the catalog for a drink that isn't theirs
+            let other_brewery = GetBreweryByName {
+                name: "Other Brewery".into(),
+            };
+            assert!(other_brewery.find(&conn)?.is_none());
+
+            match update(drinker.id, "Test Brewery").update(&conn)? {
+                DrinkOutcome::Saved(drink) => {
+                    assert_eq!(Rating::from_quarters(4), Some(drink.rating));
+                    assert_eq!("Test Stout", drink.name);
+                }
+                _ => panic!("The drinker's drink wasn't updated!"),
+            }
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    #[ignore]
+    fn test_merge_keeps_auth_events() {
+        use super::schema::auth_event;
+
+        let conn = test_connection();
+
+        conn.test_transaction::<_, Error, _>(|| {
+            let (from, into) = (new_person(&conn), new_person(&conn));
+
+            diesel::insert_into(auth_event::table)
+                .values(&models::NewAuthEvent {
+                    kind: AuthEventKind::SessionStarted.as_str(),
+                    person_id: Some(from.id),
+                    identity_kind: None,
+                    identifier: None,
+                    ip_address: None,
+                    user_agent: None,
+                    detail: None,
+                })
+                .execute(&conn)?;
+
+            let report = merge_persons(&conn, from.id, into.id)?;
+            assert_eq!(1, report.auth_events);
+
+            let events = auth_event::table
+                .filter(auth_event::person_id.eq(into.id))
+                .count()
+                .get_result::<i64>(&conn)?;
+            assert_eq!(1, events);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    #[ignore]
+    fn test_merge_revokes_logins() {
+        use super::schema::{api_token, login_session};
+
+        let conn = test_connection();
+
+        conn.test_transaction::<_, Error, _>(|| {
+            let (from, into) = (new_person(&conn), new_person(&conn));
+
+            for (person_id, session_id) in &[(from.id, "from-session"), (into.id, "into-session")] {
+                diesel::insert_into(login_session::table)
+                    .values(&models::NewSession {
+                        id: session_id,
+                        person_id: *person_id,
+                        expires_at: Utc::now() + Duration::days(1),
+                        device_label: None,
+                        user_agent: None,
+                    })
+                    .execute(&conn)?;
+            }
+
+            diesel::insert_into(api_token::table)
+                .values(&models::NewApiToken {
+                    person_id: from.id,
+                    name: "Old phone",
+                    digest: "from-token",
+                    scopes: vec!["drinks:read"],
+                })
+                .execute(&conn)?;
+
+            let report = merge_persons(&conn, from.id, into.id)?;
+            assert_eq!(1, report.revoked_sessions);
+            assert_eq!(1, report.revoked_api_tokens);
+
+            let sessions = login_session::table
+                .filter(login_session::person_id.eq(into.id))
+                .count()
+                .get_result::<i64>(&conn)?;
+            assert_eq!(1, sessions);
+
+            let api_tokens = api_token::table
+                .filter(api_token::person_id.eq(into.id))
+                .count()
+                .get_result::<i64>(&conn)?;
+            assert_eq!(0, api_tokens);
+
+            Ok(())
+        });
+    }
 }
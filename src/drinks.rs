@@ -0,0 +1,426 @@
+//! Ratings, pour details and tasting notes of the drinks people log.
+//!
+//! Drinks are rated in quarter stars with `Rating`. The names of a drink's
+//! beer and brewery, its comment, and its optional details such as its serving, volume,
+//! price, ABV and venue, are checked by functions of the same name, as are
+//! tasting note scores and descriptors. Each returns the value to store, with
+//! prices in cents and times in UTC, or a message saying what was wrong with
+//! what was submitted.
+
+use std::fmt;
+use std::io::Write;
+use std::str::FromStr;
+
+use chrono::{DateTime, NaiveDate, Utc};
+use chrono_tz::Tz;
+use diesel::deserialize::{self, FromSql};
+use diesel::pg::Pg;
+use diesel::serialize::{self, Output, ToSql};
+use diesel::sql_types::SmallInt;
+use serde::de::{self, Deserialize, Deserializer, Visitor};
+use serde::ser::{Serialize, Serializer};
+
+/// A rating from 0 to 5 stars in quarter star steps, stored as a count of quarters.
+///
+/// Ratings are given and shown as a number of stars such as `3.75`, and can be
+/// submitted as either a number or a string.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, AsExpression, FromSqlRow)]
+#[sql_type = "SmallInt"]
+pub struct Rating(i16);
+
+impl Rating {
+    pub const MAX_QUARTERS: i16 = 20;
+
+    pub fn from_quarters(quarters: i16) -> Option<Rating> {
+        if quarters >= 0 && quarters <= Rating::MAX_QUARTERS {
+            Some(Rating(quarters))
+        } else {
+            None
+        }
+    }
+
+    pub fn from_stars(stars: f64) -> Result<Rating, String> {
+        let quarters = stars * 4.0;
+
+        if quarters.fract() == 0.0 && quarters >= 0.0 && quarters <= Rating::MAX_QUARTERS as f64 {
+            Ok(Rating(quarters as i16))
+        } else {
+            Err("Ratings must be from 0 to 5 stars, in steps of a quarter star".into())
+        }
+    }
+
+    pub fn quarters(&self) -> i16 {
+        self.0
+    }
+
+    pub fn stars(&self) -> f64 {
+        self.0 as f64 / 4.0
+    }
+}
+
+impl fmt::Display for Rating {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let stars = self.0 / 4;
+
+        match self.0 % 4 {
+            1 => write!(f, "{}.25", stars),
+            2 => write!(f, "{}.5", stars),
+            3 => write!(f, "{}.75", stars),
+            _ => write!(f, "{}", stars),
+        }
+    }
+}
+
+impl FromStr for Rating {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Rating, String> {
+        f64::from_str(s.trim())
+            .map_err(|_| format!("Invalid rating '{}'", s))
+            .and_then(Rating::from_stars)
+    }
+}
+
+impl Serialize for Rating {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_f64(self.stars())
+    }
+}
+
+impl<'de> Deserialize<'de> for Rating {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Rating, D::Error> {
+        struct RatingVisitor;
+
+        impl<'de> Visitor<'de> for RatingVisitor {
+            type Value = Rating;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a number of stars from 0 to 5, in quarter steps")
+            }
+
+            fn visit_i64<E: de::Error>(self, v: i64) -> Result<Rating, E> {
+                Rating::from_stars(v as f64).map_err(E::custom)
+            }
+
+            fn visit_u64<E: de::Error>(self, v: u64) -> Result<Rating, E> {
+                Rating::from_stars(v as f64).map_err(E::custom)
+            }
+
+            fn visit_f64<E: de::Error>(self, v: f64) -> Result<Rating, E> {
+                Rating::from_stars(v).map_err(E::custom)
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<Rating, E> {
+                Rating::from_str(v).map_err(E::custom)
+            }
+        }
+
+        deserializer.deserialize_any(RatingVisitor)
+    }
+}
+
+impl ToSql<SmallInt, Pg> for Rating {
+    fn to_sql<W: Write>(&self, out: &mut Output<W, Pg>) -> serialize::Result {
+        ToSql::<SmallInt, Pg>::to_sql(&self.0, out)
+    }
+}
+
+impl FromSql<SmallInt, Pg> for Rating {
+    fn from_sql(bytes: Option<&[u8]>) -> deserialize::Result<Rating> {
+        let quarters = <i16 as FromSql<SmallInt, Pg>>::from_sql(bytes)?;
+
+        Rating::from_quarters(quarters)
+            .ok_or_else(|| format!("Rating of {} quarter stars is out of range", quarters).into())
+    }
+}
+
+/// How a beer was served.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Serving {
+    Draft,
+    Can,
+    Bottle,
+    Cask,
+}
+
+impl Serving {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Serving::Draft => "draft",
+            Serving::Can => "can",
+            Serving::Bottle => "bottle",
+            Serving::Cask => "cask",
+        }
+    }
+}
+
+impl fmt::Display for Serving {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Serving {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Serving, String> {
+        match s.trim().to_lowercase().as_str() {
+            "draft" | "draught" | "tap" => Ok(Serving::Draft),
+            "can" => Ok(Serving::Can),
+            "bottle" => Ok(Serving::Bottle),
+            "cask" => Ok(Serving::Cask),
+            _ => Err(format!(
+                "Unknown serving '{}', expected draft, can, bottle or cask",
+                s
+            )),
+        }
+    }
+}
+
+/// The most characters in the name of a beer or brewery.
+pub const MAX_NAME_CHARS: usize = 100;
+
+pub fn beer(beer: &str) -> Result<String, String> {
+    catalog_name(beer, "Beer")
+}
+
+pub fn brewery(brewery: &str) -> Result<String, String> {
+    catalog_name(brewery, "Brewery")
+}
+
+fn catalog_name(name: &str, what: &str) -> Result<String, String> {
+    let name = name.trim();
+
+    if name.is_empty() {
+        Err(format!("{} names can't be empty", what))
+    } else if name.chars().count() > MAX_NAME_CHARS {
+        Err(format!(
+            "{} names can be at most {} characters",
+            what, MAX_NAME_CHARS
+        ))
+    } else {
+        Ok(name.to_string())
+    }
+}
+
+/// The most characters in a comment about a drink.
+pub const MAX_COMMENT_CHARS: usize = 500;
+
+pub fn comment(comment: &str) -> Result<String, String> {
+    let comment = comment.trim();
+
+    if comment.chars().count() <= MAX_COMMENT_CHARS {
+        Ok(comment.to_string())
+    } else {
+        Err(format!(
+            "Comments can be at most {} characters",
+            MAX_COMMENT_CHARS
+        ))
+    }
+}
+
+pub fn serving(serving: &str) -> Result<String, String> {
+    Serving::from_str(serving).map(|serving| serving.to_string())
+}
+
+/// A pour size in millilitres.
+pub fn volume_ml(volume: &str) -> Result<i32, String> {
+    match i32::from_str(volume.trim()) {
+        Ok(volume) if volume > 0 && volume <= 10_000 => Ok(volume),
+        _ => Err("Volumes must be a whole number of millilitres".into()),
+    }
+}
+
+/// A price such as `6.50`, stored in cents.
+pub fn price_cents(price: &str) -> Result<i32, String> {
+    let invalid = || "Prices must be a number with at most two decimal places".to_string();
+    let mut parts = price.trim().splitn(2, '.');
+
+    let whole = parts
+        .next()
+        .filter(|whole| !whole.is_empty() && whole.chars().all(|c| c.is_ascii_digit()))
+        .and_then(|whole| i32::from_str(whole).ok())
+        .ok_or_else(invalid)?;
+
+    let cents = match parts.next() {
+        None => 0,
+        Some(cents) if cents.chars().all(|c| c.is_ascii_digit()) => match cents.len() {
+            1 => i32::from_str(cents).map_err(|_| invalid())? * 10,
+            2 => i32::from_str(cents).map_err(|_| invalid())?,
+            _ => return Err(invalid()),
+        },
+        Some(_) => return Err(invalid()),
+    };
+
+    whole
+        .checked_mul(100)
+        .and_then(|whole| whole.checked_add(cents))
+        .ok_or_else(invalid)
+}
+
+/// Alcohol by volume as a percentage, which may be given with a `%` sign.
+pub fn abv(abv: &str) -> Result<f32, String> {
+    match f32::from_str(abv.trim().trim_end_matches('%').trim()) {
+        Ok(abv) if abv >= 0.0 && abv <= 100.0 => Ok(abv),
+        _ => Err("ABV must be a percentage between 0 and 100".into()),
+    }
+}
+
+pub fn venue(venue: &str) -> Result<String, String> {
+    let venue = venue.trim();
+
+    if venue.chars().count() <= 200 {
+        Ok(venue.to_string())
+    } else {
+        Err("Venues can be at most 200 characters".into())
+    }
+}
+
+/// A moment in RFC 3339 format, such as `2019-01-02T19:30:00-06:00`.
+pub fn drank_at(drank_at: &str) -> Result<DateTime<Utc>, String> {
+    DateTime::parse_from_rfc3339(drank_at.trim())
+        .map(|drank_at| drank_at.with_timezone(&Utc))
+        .map_err(|_| "Times must be in RFC 3339 format, such as 2019-01-02T19:30:00Z".into())
+}
+
+/// The date a drink was had on, as seen in the drinker's own timezone.
+pub fn local_date(drank_at: DateTime<Utc>, timezone: &str) -> NaiveDate {
+    let timezone = Tz::from_str(timezone).unwrap_or(Tz::UTC);
+
+    drank_at.with_timezone(&timezone).naive_local().date()
+}
+
+/// The most each tasting note score can be, following the BJCP score sheet.
+pub const MAX_APPEARANCE: i16 = 3;
+pub const MAX_AROMA: i16 = 12;
+pub const MAX_FLAVOR: i16 = 20;
+pub const MAX_MOUTHFEEL: i16 = 5;
+pub const MAX_OVERALL: i16 = 10;
+
+/// A tasting note score, which is a whole number from 0 up to `max`.
+pub fn score(score: &str, max: i16, what: &str) -> Result<i16, String> {
+    match i16::from_str(score.trim()) {
+        Ok(score) if score >= 0 && score <= max => Ok(score),
+        _ => Err(format!(
+            "{} scores must be a whole number from 0 to {}",
+            what, max
+        )),
+    }
+}
+
+/// A comma separated list of flavor descriptors, such as `citrus, roasty`.
+///
+/// Names are lowercased and repeats are dropped; whether they are known
+/// descriptors is left to the database.
+pub fn descriptors(descriptors: &str) -> Vec<String> {
+    let mut names = Vec::<String>::new();
+
+    for name in descriptors
+        .split(',')
+        .map(|name| name.trim().to_lowercase())
+    {
+        if !name.is_empty() && !names.contains(&name) {
+            names.push(name);
+        }
+    }
+
+    names
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rating() {
+        assert_eq!(
+            Ok(15),
+            Rating::from_str("3.75").map(|rating| rating.quarters())
+        );
+        assert_eq!("3.75", Rating::from_str(" 3.75 ").unwrap().to_string());
+        assert_eq!("4", Rating::from_str("4").unwrap().to_string());
+        assert_eq!("0.5", Rating::from_quarters(2).unwrap().to_string());
+        assert!(Rating::from_str("3.3").is_err());
+        assert!(Rating::from_str("5.25").is_err());
+        assert!(Rating::from_str("-1").is_err());
+        assert!(Rating::from_quarters(21).is_none());
+    }
+
+    #[test]
+    fn test_rating_serde() {
+        let rating = Rating::from_quarters(14).unwrap();
+
+        assert_eq!(rating, serde_json::from_str("3.5").unwrap());
+        assert_eq!(rating, serde_json::from_str("\"3.5\"").unwrap());
+        assert_eq!("3.5", serde_json::to_string(&rating).unwrap());
+        assert!(serde_json::from_str::<Rating>("7").is_err());
+    }
+
+    #[test]
+    fn test_catalog_names() {
+        assert_eq!(Ok("Pale Ale".into()), beer(" Pale Ale "));
+        assert_eq!(Ok("Sierra Nevada".into()), brewery("Sierra Nevada"));
+        assert_eq!(Err("Beer names can't be empty".into()), beer("  "));
+        assert!(brewery(&"a".repeat(MAX_NAME_CHARS + 1)).is_err());
+    }
+
+    #[test]
+    fn test_comment() {
+        assert_eq!(Ok("Crisp".into()), comment(" Crisp \n"));
+        assert!(comment(&"a".repeat(MAX_COMMENT_CHARS)).is_ok());
+        assert!(comment(&"a".repeat(MAX_COMMENT_CHARS + 1)).is_err());
+    }
+
+    #[test]
+    fn test_serving() {
+        assert_eq!(Ok("draft".into()), serving(" Draught "));
+        assert_eq!(Ok("cask".into()), serving("cask"));
+        assert!(serving("growler").is_err());
+    }
+
+    #[test]
+    fn test_price_cents() {
+        assert_eq!(Ok(650), price_cents("6.50"));
+        assert_eq!(Ok(650), price_cents("6.5"));
+        assert_eq!(Ok(600), price_cents(" 6 "));
+        assert!(price_cents("6.505").is_err());
+        assert!(price_cents("-6").is_err());
+        assert!(price_cents(".50").is_err());
+        assert!(price_cents("six").is_err());
+    }
+
+    #[test]
+    fn test_abv() {
+        assert_eq!(Ok(5.5), abv("5.5%"));
+        assert!(abv("101").is_err());
+    }
+
+    #[test]
+    fn test_local_date() {
+        let drank_at = drank_at("2019-01-03T02:30:00Z").unwrap();
+
+        assert_eq!(
+            NaiveDate::from_ymd(2019, 1, 2),
+            local_date(drank_at, "America/Chicago")
+        );
+        assert_eq!(NaiveDate::from_ymd(2019, 1, 3), local_date(drank_at, "UTC"));
+    }
+
+    #[test]
+    fn test_score() {
+        assert_eq!(Ok(12), score(" 12 ", MAX_AROMA, "Aroma"));
+        assert_eq!(Ok(0), score("0", MAX_APPEARANCE, "Appearance"));
+        assert!(score("13", MAX_AROMA, "Aroma").is_err());
+        assert!(score("2.5", MAX_MOUTHFEEL, "Mouthfeel").is_err());
+        assert!(score("-1", MAX_OVERALL, "Overall").is_err());
+    }
+
+    #[test]
+    fn test_descriptors() {
+        assert_eq!(
+            vec!["citrus".to_string(), "dark fruit".into()],
+            descriptors(" Citrus, dark fruit,,citrus ")
+        );
+        assert!(descriptors(" ").is_empty());
+    }
+}
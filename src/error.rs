@@ -1,6 +1,6 @@
+use crate::api::{ApiResponse, ResponseStatus};
 use actix_web::error::ResponseError;
 use actix_web::Error as ActixError;
-use authy::AuthyError;
 use diesel::r2d2;
 use diesel::result::Error as DieselError;
 use futures::channel::oneshot::Canceled as FutureCanceled;
@@ -9,14 +9,46 @@ use std::error::Error as StdError;
 
 pub type Result<T> = ::std::result::Result<T, Error>;
 
+/// The realm named in `WWW-Authenticate` challenges.
+const REALM: &str = "Mug Club";
+
 #[derive(Debug, Display)]
 pub enum Error {
     ActixError,
 
-    AuthyError(AuthyError),
+    VerificationError(String),
+
+    MailError(String),
+
+    ExportError(String),
+
+    OidcError(String),
+
+    StorageError(String),
 
+    /// No session or API token was supplied.
+    SessionMissing,
+
+    /// The `Authorization` header couldn't be parsed.
+    MalformedToken,
+
+    /// The session or API token isn't one we know of.
     SessionNotFound,
 
+    SessionExpired,
+
+    SessionRequired,
+
+    InsufficientScope,
+
+    InsufficientRole,
+
+    /// A cookie session made an unsafe request without the matching CSRF token.
+    CsrfFailed,
+
+    /// Too many attempts; the client should retry after this many seconds.
+    RateLimited(i64),
+
     DieselError(DieselError),
 
     PoolError(r2d2::PoolError),
@@ -28,16 +60,67 @@ impl std::error::Error for Error {
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
         match self {
             Self::ActixError => None,
-            Self::AuthyError(e) => Some(e),
+            Self::VerificationError(_) => None,
+            Self::MailError(_) => None,
+            Self::ExportError(_) => None,
+            Self::OidcError(_) => None,
+            Self::StorageError(_) => None,
             Self::DieselError(e) => Some(e),
             Self::PoolError(e) => Some(e),
             Self::FutureCanceled(e) => Some(e),
+            Self::SessionMissing => None,
+            Self::MalformedToken => None,
             Self::SessionNotFound => None,
+            Self::SessionExpired => None,
+            Self::SessionRequired => None,
+            Self::InsufficientScope => None,
+            Self::InsufficientRole => None,
+            Self::CsrfFailed => None,
+            Self::RateLimited(_) => None,
         }
     }
 }
 impl ResponseError for Error {
     fn error_response(&self) -> actix_web::web::HttpResponse {
+        // Authentication failures are reported to API clients in the usual envelope
+        let message = match self {
+            Self::SessionMissing => Some("Missing session token".into()),
+            Self::MalformedToken => {
+                Some("Malformed Authorization header, expected 'Bearer <token>'".into())
+            }
+            Self::SessionNotFound => Some("Invalid session".into()),
+            Self::SessionExpired => Some("Session expired".into()),
+            Self::SessionRequired => Some("This requires a login session, not an API token".into()),
+            Self::InsufficientScope => {
+                Some("This API token does not have the required scope".into())
+            }
+            Self::InsufficientRole => Some("You don't have permission to do that".into()),
+            Self::CsrfFailed => Some("Missing or invalid CSRF token".into()),
+            Self::RateLimited(seconds) => Some(format!(
+                "Too many attempts, try again in {} seconds",
+                seconds
+            )),
+            _ => None,
+        };
+
+        if let Some(message) = message {
+            let response = ApiResponse::<()>::from(None)
+                .with_status(ResponseStatus::Fail)
+                .add_message(message);
+
+            let mut builder = actix_web::dev::HttpResponseBuilder::new(self.status_code());
+
+            if let Self::RateLimited(seconds) = self {
+                builder.set_header(actix_web::http::header::RETRY_AFTER, seconds.to_string());
+            }
+
+            if let Some(challenge) = self.challenge() {
+                builder.set_header(actix_web::http::header::WWW_AUTHENTICATE, challenge);
+            }
+
+            return builder.json(response);
+        }
+
         actix_web::dev::HttpResponseBuilder::new(self.status_code())
             .set_header(
                 actix_web::http::header::CONTENT_TYPE,
@@ -50,7 +133,39 @@ impl ResponseError for Error {
             ))
     }
     fn status_code(&self) -> actix_web::http::StatusCode {
-        actix_web::http::StatusCode::INTERNAL_SERVER_ERROR
+        use actix_web::http::StatusCode;
+
+        match self {
+            Self::SessionMissing
+            | Self::MalformedToken
+            | Self::SessionNotFound
+            | Self::SessionExpired => StatusCode::UNAUTHORIZED,
+            Self::SessionRequired
+            | Self::InsufficientScope
+            | Self::InsufficientRole
+            | Self::CsrfFailed => StatusCode::FORBIDDEN,
+            Self::RateLimited(_) => StatusCode::TOO_MANY_REQUESTS,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl Error {
+    /// The `WWW-Authenticate` challenge for an authentication failure, as described in RFC 6750.
+    fn challenge(&self) -> Option<String> {
+        let (error, description) = match self {
+            Self::SessionMissing => return Some(format!("Bearer realm=\"{}\"", REALM)),
+            Self::MalformedToken => ("invalid_request", "The Authorization header is malformed"),
+            Self::SessionNotFound => ("invalid_token", "The token is not recognized"),
+            Self::SessionExpired => ("invalid_token", "The token has expired"),
+            Self::InsufficientScope => ("insufficient_scope", "The token lacks the required scope"),
+            _ => return None,
+        };
+
+        Some(format!(
+            "Bearer realm=\"{}\", error=\"{}\", error_description=\"{}\"",
+            REALM, error, description
+        ))
     }
 }
 
@@ -72,14 +187,40 @@ impl From<FutureCanceled> for Error {
     }
 }
 
-impl From<AuthyError> for Error {
-    fn from(e: AuthyError) -> Error {
-        Error::AuthyError(e)
-    }
-}
-
 impl From<ActixError> for Error {
     fn from(e: ActixError) -> Error {
         Error::ActixError
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::header::WWW_AUTHENTICATE;
+    use actix_web::http::StatusCode;
+
+    #[test]
+    fn test_authentication_challenges() {
+        let challenge = |e: Error| {
+            let response = e.error_response();
+
+            assert_eq!(StatusCode::UNAUTHORIZED, response.status());
+
+            response
+                .headers()
+                .get(WWW_AUTHENTICATE)
+                .unwrap()
+                .to_str()
+                .unwrap()
+                .to_string()
+        };
+
+        assert_eq!(
+            "Bearer realm=\"Mug Club\"",
+            challenge(Error::SessionMissing)
+        );
+        assert!(challenge(Error::MalformedToken).contains("error=\"invalid_request\""));
+        assert!(challenge(Error::SessionNotFound).contains("error=\"invalid_token\""));
+        assert!(challenge(Error::SessionExpired).contains("has expired"));
+    }
+}
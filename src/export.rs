@@ -0,0 +1,328 @@
+//! Personal data exports.
+//!
+//! The JSON export is just `PersonalData` in the usual response envelope; this
+//! module builds the CSV export, which is a zip archive with one CSV file per
+//! kind of record so that it opens directly in a spreadsheet.
+
+use std::io::{Cursor, Write};
+
+use chrono::{DateTime, Utc};
+use zip::write::{FileOptions, ZipWriter};
+
+use crate::db::{PersonalData, TastingNotes};
+use crate::error::{Error, Result};
+
+/// Build a zip archive of CSV files from a person's data.
+pub fn csv_archive(data: &PersonalData) -> Result<Vec<u8>> {
+    let mut archive = ZipWriter::new(Cursor::new(Vec::new()));
+
+    let person = &data.person;
+    add_csv(
+        &mut archive,
+        "person.csv",
+        &[
+            "id",
+            "role",
+            "created_at",
+            "updated_at",
+            "deletion_requested_at",
+            "display_name",
+            "handle",
+            "bio",
+            "avatar_url",
+            "timezone",
+            "units",
+        ],
+        vec![vec![
+            person.id.to_string(),
+            person.role.clone(),
+            timestamp(&person.created_at),
+            timestamp(&person.updated_at),
+            optional_timestamp(&person.deletion_requested_at),
+            person.display_name.clone().unwrap_or_default(),
+            person.handle.clone().unwrap_or_default(),
+            person.bio.clone().unwrap_or_default(),
+            person.avatar_url.clone().unwrap_or_default(),
+            person.timezone.clone(),
+            person.units.clone(),
+        ]],
+    )?;
+
+    add_csv(
+        &mut archive,
+        "identities.csv",
+        &["kind", "identifier", "created_at"],
+        data.identities
+            .iter()
+            .map(|identity| {
+                vec![
+                    identity.kind.clone(),
+                    identity.identifier.clone(),
+                    timestamp(&identity.created_at),
+                ]
+            })
+            .collect(),
+    )?;
+
+    add_csv(
+        &mut archive,
+        "sessions.csv",
+        &[
+            "id",
+            "created_at",
+            "last_used_at",
+            "expires_at",
+            "device_label",
+            "user_agent",
+        ],
+        data.sessions
+            .iter()
+            .map(|session| {
+                vec![
+                    session.public_id.to_string(),
+                    timestamp(&session.created_at),
+                    optional_timestamp(&session.last_used_at),
+                    timestamp(&session.expires_at),
+                    session.device_label.clone().unwrap_or_default(),
+                    session.user_agent.clone().unwrap_or_default(),
+                ]
+            })
+            .collect(),
+    )?;
+
+    add_csv(
+        &mut archive,
+        "api_tokens.csv",
+        &["id", "name", "scopes", "created_at", "last_used_at"],
+        data.api_tokens
+            .iter()
+            .map(|token| {
+                vec![
+                    token.id.to_string(),
+                    token.name.clone(),
+                    token.scopes.join(" "),
+                    timestamp(&token.created_at),
+                    optional_timestamp(&token.last_used_at),
+                ]
+            })
+            .collect(),
+    )?;
+
+    add_csv(
+        &mut archive,
+        "drinks.csv",
+        &[
+            "id",
+            "drank_on",
+            "drank_at",
+            "beer",
+            "brewery",
+            "rating",
+            "comment",
+            "shared",
+            "serving",
+            "volume_ml",
+            "price_cents",
+            "abv",
+            "venue",
+            "appearance",
+            "aroma",
+            "flavor",
+            "mouthfeel",
+            "overall",
+            "descriptors",
+        ],
+        data.drinks
+            .iter()
+            .map(|drink| {
+                vec![
+                    drink.id.to_string(),
+                    drink.drank_on.to_string(),
+                    optional_timestamp(&drink.drank_at),
+                    drink.name.clone(),
+                    drink.brewery.clone(),
+                    drink.rating.to_string(),
+                    drink.comment.clone().unwrap_or_default(),
+                    drink.shared.to_string(),
+                    drink.serving.clone().unwrap_or_default(),
+                    optional(&drink.volume_ml),
+                    optional(&drink.price_cents),
+                    optional(&drink.abv),
+                    drink.venue.clone().unwrap_or_default(),
+                ]
+                .into_iter()
+                .chain(tasting_columns(&drink.tasting))
+                .collect()
+            })
+            .collect(),
+    )?;
+
+    add_csv(
+        &mut archive,
+        "auth_events.csv",
+        &[
+            "created_at",
+            "kind",
+            "identity_kind",
+            "identifier",
+            "ip_address",
+            "user_agent",
+            "detail",
+        ],
+        data.auth_events
+            .iter()
+            .map(|event| {
+                vec![
+                    timestamp(&event.created_at),
+                    event.kind.clone(),
+                    event.identity_kind.clone().unwrap_or_default(),
+                    event.identifier.clone().unwrap_or_default(),
+                    event.ip_address.clone().unwrap_or_default(),
+                    event.user_agent.clone().unwrap_or_default(),
+                    event.detail.clone().unwrap_or_default(),
+                ]
+            })
+            .collect(),
+    )?;
+
+    let archive = archive.finish().map_err(export_error)?;
+
+    Ok(archive.into_inner())
+}
+
+fn add_csv(
+    archive: &mut ZipWriter<Cursor<Vec<u8>>>,
+    name: &str,
+    headers: &[&str],
+    rows: Vec<Vec<String>>,
+) -> Result<()> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+
+    writer.write_record(headers).map_err(export_error)?;
+
+    for row in rows {
+        writer.write_record(&row).map_err(export_error)?;
+    }
+
+    let contents = writer.into_inner().map_err(export_error)?;
+
+    archive
+        .start_file(name, FileOptions::default())
+        .map_err(export_error)?;
+    archive.write_all(&contents).map_err(export_error)?;
+
+    Ok(())
+}
+
+fn timestamp(time: &DateTime<Utc>) -> String {
+    time.to_rfc3339()
+}
+
+fn optional_timestamp(time: &Option<DateTime<Utc>>) -> String {
+    time.as_ref().map(timestamp).unwrap_or_default()
+}
+
+fn optional<T: ToString>(value: &Option<T>) -> String {
+    value.as_ref().map(T::to_string).unwrap_or_default()
+}
+
+/// The tasting note columns of a drink, which are all empty if it has no notes.
+fn tasting_columns(tasting: &Option<TastingNotes>) -> Vec<String> {
+    match tasting {
+        Some(tasting) => vec![
+            optional(&tasting.appearance),
+            optional(&tasting.aroma),
+            optional(&tasting.flavor),
+            optional(&tasting.mouthfeel),
+            optional(&tasting.overall),
+            tasting.descriptors.join(", "),
+        ],
+        None => vec![String::new(); 6],
+    }
+}
+
+fn export_error<E: std::fmt::Display>(e: E) -> Error {
+    Error::ExportError(e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::ExpandedDrink;
+    use crate::drinks::Rating;
+    use crate::models::Person;
+    use chrono::NaiveDate;
+    use std::io::Read;
+
+    #[test]
+    fn test_csv_archive() {
+        let now = Utc::now();
+        let data = PersonalData {
+            exported_at: now,
+            person: Person {
+                id: 1,
+                created_at: now,
+                updated_at: now,
+                role: "member".into(),
+                deletion_requested_at: None,
+                display_name: None,
+                handle: None,
+                bio: None,
+                avatar_url: None,
+                timezone: "UTC".into(),
+                units: "metric".into(),
+                avatar_key: None,
+            },
+            identities: vec![],
+            sessions: vec![],
+            api_tokens: vec![],
+            drinks: vec![ExpandedDrink {
+                id: 7,
+                drank_on: NaiveDate::from_ymd(2019, 1, 2),
+                beer_id: 3,
+                name: "Pale Ale".into(),
+                brewery_id: 2,
+                brewery: "Sierra Nevada".into(),
+                rating: Rating::from_quarters(16).unwrap(),
+                comment: Some("Crisp, \"hoppy\"".into()),
+                shared: false,
+                drank_at: None,
+                serving: Some("draft".into()),
+                volume_ml: Some(473),
+                price_cents: None,
+                abv: Some(5.6),
+                venue: None,
+                tasting: Some(TastingNotes {
+                    appearance: Some(3),
+                    aroma: Some(10),
+                    flavor: None,
+                    mouthfeel: Some(4),
+                    overall: Some(8),
+                    descriptors: vec!["citrus".into(), "piney".into()],
+                }),
+                photos: vec![],
+            }],
+            auth_events: vec![],
+        };
+
+        let archive = csv_archive(&data).unwrap();
+        let mut archive = zip::ZipArchive::new(Cursor::new(archive)).unwrap();
+
+        assert_eq!(6, archive.len());
+
+        let mut drinks = String::new();
+        archive
+            .by_name("drinks.csv")
+            .unwrap()
+            .read_to_string(&mut drinks)
+            .unwrap();
+
+        assert_eq!(
+            "id,drank_on,drank_at,beer,brewery,rating,comment,shared,serving,volume_ml,price_cents,abv,venue,\
+             appearance,aroma,flavor,mouthfeel,overall,descriptors\n\
+             7,2019-01-02,,Pale Ale,Sierra Nevada,4,\"Crisp, \"\"hoppy\"\"\",false,draft,473,,5.6,,\
+             3,10,,4,8,\"citrus, piney\"\n",
+            drinks
+        );
+    }
+}
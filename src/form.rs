@@ -0,0 +1,53 @@
+//! Reading optional fields out of submitted forms.
+//!
+//! Fields that are left out of a form are left alone. When editing, a field
+//! that is given but empty clears the stored value instead.
+
+/// Check a submitted value for an optional field, where an empty value clears it.
+pub fn clearable<T, F>(value: &Option<String>, check: F) -> Result<Option<Option<T>>, String>
+where
+    F: Fn(&str) -> Result<T, String>,
+{
+    match value {
+        None => Ok(None),
+        Some(value) if value.trim().is_empty() => Ok(Some(None)),
+        Some(value) => check(value).map(|value| Some(Some(value))),
+    }
+}
+
+/// Check a submitted value for an optional field that is being set from scratch,
+/// where nothing is cleared and so an empty value is the same as none at all.
+pub fn optional<T, F>(value: &Option<String>, check: F) -> Result<Option<T>, String>
+where
+    F: Fn(&str) -> Result<T, String>,
+{
+    clearable(value, check).map(Option::flatten)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::profile::{avatar_url, bio};
+
+    #[test]
+    fn test_clearable() {
+        assert_eq!(Ok(None), clearable(&None, bio));
+        assert_eq!(Ok(Some(None)), clearable(&Some("  ".into()), bio));
+        assert_eq!(
+            Ok(Some(Some("Likes stouts".into()))),
+            clearable(&Some(" Likes stouts ".into()), bio)
+        );
+        assert!(clearable(&Some("ftp://example.com/me.png".into()), avatar_url).is_err());
+    }
+
+    #[test]
+    fn test_optional() {
+        assert_eq!(Ok(None), optional(&None, bio));
+        assert_eq!(Ok(None), optional(&Some("  ".into()), bio));
+        assert_eq!(
+            Ok(Some("Likes stouts".into())),
+            optional(&Some(" Likes stouts ".into()), bio)
+        );
+        assert!(optional(&Some("ftp://example.com/me.png".into()), avatar_url).is_err());
+    }
+}
@@ -0,0 +1,293 @@
+//! Bulk imports of drink logs kept elsewhere, such as in a spreadsheet.
+//!
+//! An import is a CSV file with a header row. A `ColumnMapping` says which
+//! columns hold the date, beer, brewery, rating and comment of each drink; the
+//! defaults match the `drinks.csv` of a personal data export, so an export can
+//! be imported again as it is.
+
+use std::str::FromStr;
+
+use chrono::NaiveDate;
+use csv::{ReaderBuilder, StringRecord, Trim};
+
+use crate::drinks::{self, Rating};
+use crate::form::optional;
+
+/// The most rows a single import may have.
+pub const MAX_ROWS: usize = 10_000;
+
+/// The largest file accepted, in bytes.
+pub const MAX_BYTES: usize = 5 * 1024 * 1024;
+
+/// The names of the columns that hold each part of a drink, matched case-insensitively.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ColumnMapping {
+    pub date: String,
+    pub beer: String,
+    pub brewery: String,
+    pub rating: String,
+
+    /// Comments are optional; without a mapping, a `comment` column is used if there is one.
+    pub comment: Option<String>,
+
+    /// How dates are written, in `strftime` format.
+    pub date_format: String,
+}
+
+impl Default for ColumnMapping {
+    fn default() -> ColumnMapping {
+        ColumnMapping {
+            date: "drank_on".into(),
+            beer: "beer".into(),
+            brewery: "brewery".into(),
+            rating: "rating".into(),
+            comment: None,
+            date_format: "%Y-%m-%d".into(),
+        }
+    }
+}
+
+/// A drink read from an import.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ImportRow {
+    /// The line of the file the drink was on, counting the header as line 1.
+    pub line: u64,
+    pub drank_on: NaiveDate,
+    pub beer: String,
+    pub brewery: String,
+    pub rating: Rating,
+    pub comment: Option<String>,
+}
+
+/// A problem with one row of an import.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct RowError {
+    /// The line of the file, counting the header as line 1.
+    pub row: u64,
+    pub message: String,
+}
+
+/// The rows of an import that could be read, and the problems with the rest.
+pub struct Parsed {
+    pub rows: Vec<ImportRow>,
+    pub errors: Vec<RowError>,
+}
+
+/// What an import did, or would do on a dry run.
+#[derive(Serialize)]
+#[serde(rename = "import")]
+pub struct ImportReport {
+    pub dry_run: bool,
+
+    /// How many drinks were logged, which is none on a dry run or if any row has a problem.
+    pub imported: usize,
+    pub breweries: Vec<CatalogMatch>,
+    pub beers: Vec<CatalogMatch>,
+    pub errors: Vec<RowError>,
+}
+
+/// A brewery or beer named in an import, and whether it is already in the catalog.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct CatalogMatch {
+    pub name: String,
+
+    /// The brewery of a beer.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub brewery: Option<String>,
+
+    /// The catalog id, which is unknown for new entries until the import is committed.
+    pub id: Option<i32>,
+
+    /// Whether the entry is new to the catalog, and was or would be added.
+    pub created: bool,
+}
+
+/// Read the drinks in an import, collecting a problem for each row that can't be read.
+///
+/// Fails with a message for the person if the file as a whole can't be used,
+/// such as when a mapped column is missing.
+pub fn parse(csv: &[u8], mapping: &ColumnMapping) -> Result<Parsed, String> {
+    let mut reader = ReaderBuilder::new()
+        .flexible(true)
+        .trim(Trim::All)
+        .from_reader(csv);
+
+    let headers = reader
+        .headers()
+        .map_err(|e| format!("Unable to read the CSV header: {}", e))?
+        .clone();
+
+    let column = |name: &str| {
+        headers
+            .iter()
+            .position(|header| header.eq_ignore_ascii_case(name.trim()))
+            .ok_or_else(|| format!("There is no '{}' column", name))
+    };
+
+    let columns = Columns {
+        date: column(&mapping.date)?,
+        beer: column(&mapping.beer)?,
+        brewery: column(&mapping.brewery)?,
+        rating: column(&mapping.rating)?,
+        comment: match mapping.comment {
+            Some(ref comment) => Some(column(comment)?),
+            None => column("comment").ok(),
+        },
+    };
+
+    let mut parsed = Parsed {
+        rows: Vec::new(),
+        errors: Vec::new(),
+    };
+
+    for (index, record) in reader.records().enumerate() {
+        if index >= MAX_ROWS {
+            return Err(format!("Imports can have at most {} rows", MAX_ROWS));
+        }
+
+        // Line 1 is the header, so the first row is on line 2
+        let fallback = index as u64 + 2;
+        let read = record
+            .map_err(|e| (e.position().map_or(fallback, |p| p.line()), e.to_string()))
+            .and_then(|record| {
+                let line = record.position().map_or(fallback, |p| p.line());
+
+                row(line, &record, &columns, &mapping.date_format).map_err(|e| (line, e))
+            });
+
+        match read {
+            Ok(row) => parsed.rows.push(row),
+            Err((row, message)) => parsed.errors.push(RowError { row, message }),
+        }
+    }
+
+    Ok(parsed)
+}
+
+/// Where each part of a drink is in a row.
+struct Columns {
+    date: usize,
+    beer: usize,
+    brewery: usize,
+    rating: usize,
+    comment: Option<usize>,
+}
+
+fn row(
+    line: u64,
+    record: &StringRecord,
+    columns: &Columns,
+    date_format: &str,
+) -> Result<ImportRow, String> {
+    let field = |index: usize| record.get(index).unwrap_or("");
+    let required = |index: usize, what: &str| match field(index) {
+        "" => Err(format!("The {} is missing", what)),
+        value => Ok(value.to_string()),
+    };
+
+    let date = required(columns.date, "date")?;
+    let drank_on = NaiveDate::parse_from_str(&date, date_format)
+        .map_err(|_| format!("Invalid date '{}'", date))?;
+
+    Ok(ImportRow {
+        line,
+        drank_on,
+        beer: drinks::beer(&required(columns.beer, "beer")?)?,
+        brewery: drinks::brewery(&required(columns.brewery, "brewery")?)?,
+        rating: Rating::from_str(&required(columns.rating, "rating")?)?,
+        comment: optional(
+            &columns.comment.map(field).map(String::from),
+            drinks::comment,
+        )?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_export() {
+        let csv = "id,drank_on,drank_at,beer,brewery,rating,comment\n\
+                   7,2019-01-02,,Pale Ale,Sierra Nevada,4,\"Crisp, hoppy\"\n\
+                   8,2019-01-03,,Stout,Guinness,3.75,\n";
+
+        let parsed = parse(csv.as_bytes(), &ColumnMapping::default()).unwrap();
+
+        assert!(parsed.errors.is_empty());
+        assert_eq!(
+            vec![
+                ImportRow {
+                    line: 2,
+                    drank_on: NaiveDate::from_ymd(2019, 1, 2),
+                    beer: "Pale Ale".into(),
+                    brewery: "Sierra Nevada".into(),
+                    rating: Rating::from_quarters(16).unwrap(),
+                    comment: Some("Crisp, hoppy".into()),
+                },
+                ImportRow {
+                    line: 3,
+                    drank_on: NaiveDate::from_ymd(2019, 1, 3),
+                    beer: "Stout".into(),
+                    brewery: "Guinness".into(),
+                    rating: Rating::from_quarters(15).unwrap(),
+                    comment: None,
+                },
+            ],
+            parsed.rows
+        );
+    }
+
+    #[test]
+    fn test_parse_mapping() {
+        let mapping = ColumnMapping {
+            date: "When".into(),
+            beer: "Beer Name".into(),
+            brewery: "Brewer".into(),
+            rating: "Stars".into(),
+            comment: Some("Notes".into()),
+            date_format: "%m/%d/%Y".into(),
+        };
+        let csv = format!(
+            "when,beer name,brewer,stars,notes\n\
+             01/02/2019, Pale Ale ,Sierra Nevada,4,Crisp\n\
+             2019-01-03,Stout,Guinness,3.75,\n\
+             01/04/2019,,Guinness,3,\n\
+             01/05/2019,Porter,Fuller's,6,\n\
+             01/06/2019,Porter,{},4,\n",
+            "Fuller's".repeat(20)
+        );
+
+        let parsed = parse(csv.as_bytes(), &mapping).unwrap();
+
+        assert_eq!(1, parsed.rows.len());
+        assert_eq!("Pale Ale", parsed.rows[0].beer);
+        assert_eq!(
+            vec![3, 4, 5, 6],
+            parsed.errors.iter().map(|e| e.row).collect::<Vec<_>>()
+        );
+        assert_eq!("Invalid date '2019-01-03'", parsed.errors[0].message);
+        assert_eq!("The beer is missing", parsed.errors[1].message);
+        assert_eq!(
+            "Brewery names can be at most 100 characters",
+            parsed.errors[3].message
+        );
+    }
+
+    #[test]
+    fn test_parse_missing_column() {
+        let mapping = ColumnMapping {
+            comment: Some("notes".into()),
+            ..ColumnMapping::default()
+        };
+
+        assert_eq!(
+            Err("There is no 'notes' column".into()),
+            parse(b"drank_on,beer,brewery,rating\n", &mapping).map(|parsed| parsed.rows)
+        );
+        assert_eq!(
+            Err("There is no 'rating' column".into()),
+            parse(b"drank_on,beer,brewery\n", &ColumnMapping::default()).map(|parsed| parsed.rows)
+        );
+    }
+}
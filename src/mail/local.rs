@@ -0,0 +1,57 @@
+use std::fs;
+use std::path::PathBuf;
+
+use chrono::Utc;
+
+use super::{Email, Mailer};
+use crate::error::{Error, Result};
+
+/// Writes every message to the log instead of sending it.
+pub struct LogMailer;
+
+impl Mailer for LogMailer {
+    fn send(&self, email: &Email) -> Result<()> {
+        info!(
+            "Email to '{}', subject '{}':\n{}",
+            email.to, email.subject, email.body
+        );
+
+        Ok(())
+    }
+}
+
+/// Writes every message to its own file in a directory instead of sending it.
+pub struct FileMailer {
+    directory: PathBuf,
+}
+
+impl FileMailer {
+    pub fn new<P: Into<PathBuf>>(directory: P) -> FileMailer {
+        FileMailer {
+            directory: directory.into(),
+        }
+    }
+}
+
+impl Mailer for FileMailer {
+    fn send(&self, email: &Email) -> Result<()> {
+        let path = self.directory.join(format!(
+            "{}-{}.txt",
+            Utc::now().format("%Y%m%dT%H%M%S%.f"),
+            email.to.replace(|c: char| !c.is_alphanumeric(), "_")
+        ));
+
+        fs::write(
+            &path,
+            format!(
+                "To: {}\nSubject: {}\n\n{}\n",
+                email.to, email.subject, email.body
+            ),
+        )
+        .map_err(|e| Error::MailError(e.to_string()))?;
+
+        info!("Wrote email to '{}' to {}", email.to, path.display());
+
+        Ok(())
+    }
+}
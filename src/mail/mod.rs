@@ -0,0 +1,109 @@
+//! Outgoing email.
+//!
+//! Like phone verification, delivery is pluggable through the `MAILER` variable
+//! so that email login can be used locally without a mail server.
+
+use std::sync::Arc;
+
+use actix_web::web;
+use futures::future::Future;
+use futures::prelude::*;
+
+use crate::error::{Error, Result};
+
+mod local;
+mod smtp;
+
+pub use self::local::{FileMailer, LogMailer};
+pub use self::smtp::SmtpMailer;
+
+/// A plain text email message.
+pub struct Email {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+pub trait Mailer: Send + Sync {
+    /// Deliver an email, blocking until it has been handed off.
+    fn send(&self, email: &Email) -> Result<()>;
+}
+
+/// The shared mailer handle stored as application data.
+pub type Provider = Arc<dyn Mailer>;
+
+/// Build the mailer selected by the `MAILER` variable.
+///
+/// - `log` (the default) writes messages to the log.
+/// - `file` writes each message to a file in `MAIL_DIRECTORY`.
+/// - `smtp` sends messages from `MAIL_FROM` through `SMTP_HOST`, using
+///   `SMTP_USERNAME` and `SMTP_PASSWORD`.
+pub fn from_env() -> Provider {
+    match std::env::var("MAILER").unwrap_or("log".into()).as_str() {
+        "log" => Arc::new(LogMailer),
+        "file" => Arc::new(FileMailer::new(
+            std::env::var("MAIL_DIRECTORY").expect("MAIL_DIRECTORY must be set!"),
+        )),
+        "smtp" => Arc::new(SmtpMailer::new(
+            std::env::var("SMTP_HOST").expect("SMTP_HOST must be set!"),
+            std::env::var("SMTP_USERNAME").expect("SMTP_USERNAME must be set!"),
+            std::env::var("SMTP_PASSWORD").expect("SMTP_PASSWORD must be set!"),
+            std::env::var("MAIL_FROM").expect("MAIL_FROM must be set!"),
+        )),
+        other => panic!("Unknown mailer '{}'!", other),
+    }
+}
+
+/// Run `Mailer::send` on the blocking thread pool.
+pub fn send(mailer: &Provider, email: Email) -> impl Future<Output = Result<()>> {
+    use actix_web::error::BlockingError;
+    use futures::channel::oneshot::Canceled;
+
+    let mailer = mailer.clone();
+
+    web::block(move || mailer.send(&email)).map(|res| match res {
+        Ok(r) => Ok(r),
+        Err(BlockingError::Error(e)) => Err(e),
+        Err(BlockingError::Canceled) => Err(Error::from(Canceled)),
+    })
+}
+
+/// Validate an email address and normalize it for use as an identifier.
+///
+/// Addresses are trimmed and lowercased. Only the basic shape is checked;
+/// whether the address really works is proven by the person clicking a link.
+pub fn normalize_address(address: &str) -> Option<String> {
+    lazy_static! {
+        static ref ADDRESS: regex::Regex =
+            regex::Regex::new(r"^[^@\s]+@[^@\s\.]+(\.[^@\s\.]+)+$").unwrap();
+    }
+
+    let address = address.trim().to_lowercase();
+
+    match address.len() <= 254 && ADDRESS.is_match(&address) {
+        true => Some(address),
+        false => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::normalize_address;
+
+    #[test]
+    fn test_normalize_address() {
+        assert_eq!(
+            Some("someone@example.com".to_string()),
+            normalize_address(" SomeOne@Example.com ")
+        );
+        assert_eq!(
+            Some("some.one+beer@mail.example.co.uk".to_string()),
+            normalize_address("some.one+beer@mail.example.co.uk")
+        );
+        assert_eq!(None, normalize_address("someone"));
+        assert_eq!(None, normalize_address("someone@localhost"));
+        assert_eq!(None, normalize_address("some one@example.com"));
+        assert_eq!(None, normalize_address("someone@@example.com"));
+        assert_eq!(None, normalize_address("someone@example..com"));
+    }
+}
@@ -0,0 +1,51 @@
+use lettre::smtp::authentication::Credentials;
+use lettre::{SmtpClient, Transport};
+use lettre_email::EmailBuilder;
+
+use super::{Email, Mailer};
+use crate::error::{Error, Result};
+
+/// Sends messages through an SMTP submission server over TLS.
+pub struct SmtpMailer {
+    host: String,
+    username: String,
+    password: String,
+    from: String,
+}
+
+impl SmtpMailer {
+    pub fn new(host: String, username: String, password: String, from: String) -> SmtpMailer {
+        SmtpMailer {
+            host,
+            username,
+            password,
+            from,
+        }
+    }
+}
+
+impl Mailer for SmtpMailer {
+    fn send(&self, email: &Email) -> Result<()> {
+        let message = EmailBuilder::new()
+            .to(email.to.as_str())
+            .from(self.from.as_str())
+            .subject(email.subject.as_str())
+            .text(email.body.as_str())
+            .build()
+            .map_err(|e| Error::MailError(e.to_string()))?;
+
+        let mut transport = SmtpClient::new_simple(&self.host)
+            .map_err(|e| Error::MailError(e.to_string()))?
+            .credentials(Credentials::new(
+                self.username.clone(),
+                self.password.clone(),
+            ))
+            .transport();
+
+        transport
+            .send(message.into())
+            .map_err(|e| Error::MailError(e.to_string()))?;
+
+        Ok(())
+    }
+}
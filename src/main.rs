@@ -23,39 +23,74 @@ extern crate log;
 extern crate regex;
 #[macro_use]
 extern crate lazy_static;
+extern crate lettre;
+extern crate lettre_email;
+extern crate rand;
+extern crate sha2;
 extern crate textnonce;
 
 mod api;
+mod audit;
+mod auth;
+mod config;
+mod cookies;
 mod db;
+mod drinks;
 mod error;
+mod export;
+mod form;
+mod import;
+mod mail;
 mod models;
+mod oidc;
+mod phone;
+mod photos;
+mod profile;
 mod schema;
-
-use self::api::{ApiResponse, ResponseStatus};
+mod throttle;
+mod token;
+mod verification;
+
+use self::api::{ApiResponse, Pagination, ResponseStatus};
+use self::audit::{AuditLog, AuthEventKind, Event};
+use self::auth::{
+    Bartender, ClubAdmin, DrinksRead, DrinksWrite, RequirePerson, Role, Scoped, SiteAdmin,
+};
+use self::config::{DeletionConfig, EmailLoginConfig, SessionConfig};
 use self::db::{
-    BeerSearchResult, BrewerySearchResult, Connection, CreateBeer, CreateBrewery, CreateDrink,
-    DeleteDrink, ExpandedDrink, GetBeerByName, GetBreweryByName, GetDrink, GetDrinks,
-    LookupIdentiy, Pool, SearchBeerByName, SearchBreweryByName, StartSession,
+    AddDrinkPhoto, AttachIdentity, AttachOutcome, BeerSearchResult, BrewerySearchResult,
+    CancelDeletion, CatalogEdit, Connection, ConsumeEmailChallenge, ConsumeOidcLogin,
+    CreateApiToken, CreateEmailChallenge, CreateOidcLogin, DeleteApiToken, DeleteDrink,
+    DeleteDrinkPhoto, DeleteTastingNotes, DetachIdentity, DetachOutcome, DrinkCursor, DrinkDetails,
+    DrinkFilter, DrinkOutcome, DrinkSort, EndSession, ExpandedDrink, GetApiTokens, GetAuthEvents,
+    GetDrink, GetDrinks, GetFlavorDescriptors, GetIdentities, GetPeople, GetPersonByHandle,
+    GetPersonalData, GetPhotoAccess, GetSessions, ImportDrinks, LogDrink, LookupIdentiy, MergeBeer,
+    MergeBrewery, NormalizePhoneIdentities, PhotoAccess, Pool, ProfileOutcome, PurgeDeletedPersons,
+    RenameBeer, RenameBrewery, RequestDeletion, RevokeOtherSessions, RevokeSession,
+    SearchBeerByName, SearchBreweryByName, SessionSummary, SetAvatar, SetRole, SetTastingNotes,
+    StartSession, StartedSession, TastingNotes, UpdateDrink, UpdateProfile,
 };
-use self::error::Error;
+use self::drinks::Rating;
+use self::form::{clearable, optional};
+use self::import::ColumnMapping;
+use self::models::IdentityKind;
+use self::phone::PhoneNumber;
+use self::throttle::Throttle;
+use self::verification::CheckOutcome;
 
 use std::convert::From;
 use std::str::FromStr;
 
 use actix_cors::Cors;
+use actix_multipart::Multipart;
 use actix_web::middleware::Logger;
 use actix_web::*;
 use actix_web::{App, HttpRequest, HttpServer, Responder};
-use actix_web::error::BlockingError;
-use authy::AuthyError;
 use chrono::naive::NaiveDate;
+use chrono::Utc;
 use diesel::prelude::*;
 use diesel::r2d2::ConnectionManager;
-use futures::future::Either;
-use futures::Future;
 use futures::prelude::*;
-use futures::channel::oneshot::Canceled;
-use regex::Regex;
 
 type ActixResult<T> = std::result::Result<T, actix_web::error::Error>;
 
@@ -83,29 +118,154 @@ async fn p404() -> impl Responder {
     HttpResponse::Ok().json(ApiResponse::error(TestResponse("404 Not Found".into())))
 }
 
+#[derive(Deserialize)]
+struct DrinksForm {
+    /// Only drinks had on or after this date (yyyy-mm-dd).
+    from: Option<NaiveDate>,
+
+    /// Only drinks had on or before this date (yyyy-mm-dd).
+    to: Option<NaiveDate>,
+    brewery_id: Option<i32>,
+    beer_id: Option<i32>,
+    min_rating: Option<Rating>,
+    max_rating: Option<Rating>,
+    has_comment: Option<bool>,
+    has_tasting_notes: Option<bool>,
+
+    /// Only drinks with at least this overall impression in their tasting notes.
+    min_overall: Option<i16>,
+
+    /// Only drinks whose tasting notes pick out all of these comma separated flavors.
+    descriptors: Option<String>,
+
+    /// `drank_on` or `rating`, prefixed with `-` for descending order.
+    sort: Option<String>,
+
+    /// The `next` cursor from the previous page.
+    after: Option<String>,
+    limit: Option<i64>,
+}
+
+/// Route handler for listing the current person's drinks, a page at a time
+///
+/// Requires a valid session token, or an API token with the `drinks:read` scope,
+/// in the `Authorization` header.
+///
+/// Drinks are listed oldest first unless another `sort` is given. Every drink is
+/// listed unless a `limit` or an `after` cursor is given, in which case they are
+/// listed a page at a time: the response's `pagination.next` is a cursor to pass
+/// as `after` for the following page, and is `null` once there are no more drinks.
 async fn get_drinks(
     pool: web::Data<Pool>,
-    person: models::Person,
+    person: Scoped<DrinksRead>,
+    form: web::Query<DrinksForm>,
 ) -> ActixResult<HttpResponse> {
     #[derive(Serialize)]
     #[serde(rename = "drinks")]
     struct Drinks(Vec<ExpandedDrink>);
 
-    db::execute(
-        &pool,
-        GetDrinks {
-            person_id: person.id,
+    let form = form.into_inner();
+
+    // Clients from before pagination expect every drink at once
+    let limit = match (form.limit, &form.after) {
+        (None, None) => None,
+        (limit, _) => Some(limit.unwrap_or(100).max(1).min(500)),
+    };
+
+    let query = match drinks_query(person.id, form, limit) {
+        Ok(query) => query,
+        Err(message) => {
+            let response = ApiResponse::<()>::from(None)
+                .with_status(ResponseStatus::Fail)
+                .add_message(message);
+
+            return Ok(HttpResponse::BadRequest().json(response));
+        }
+    };
+
+    db::execute(&pool, query)
+        .then(move |res| async move {
+            match res {
+                Ok(page) => {
+                    let response = ApiResponse::success(Drinks(page.drinks));
+
+                    Ok(HttpResponse::Ok().json(match limit {
+                        Some(limit) => response.with_pagination(Pagination {
+                            next: page.next.map(|next| next.to_string()),
+                            limit,
+                        }),
+                        None => response,
+                    }))
+                }
+                Err(e) => {
+                    error!(
+                        "Unable to list drinks for person {}! Error: {}",
+                        person.id, e
+                    );
+
+                    let unexpected_error = ApiResponse::<()>::from(None)
+                        .with_status(ResponseStatus::Error)
+                        .add_message("An unexpected error occurred".into());
+
+                    Ok(HttpResponse::InternalServerError().json(unexpected_error))
+                }
+            }
+        })
+        .await
+}
+
+/// Check the submitted sort and cursor, describing the first problem found.
+fn drinks_query(
+    person_id: i32,
+    form: DrinksForm,
+    limit: Option<i64>,
+) -> std::result::Result<GetDrinks, String> {
+    let sort = match form.sort {
+        Some(ref sort) => DrinkSort::from_str(sort)?,
+        None => DrinkSort::default(),
+    };
+
+    let after = form
+        .after
+        .as_ref()
+        .map(|after| DrinkCursor::from_str(after))
+        .transpose()?;
+
+    if after.map_or(false, |after| after.sort != sort) {
+        return Err("That cursor is for a different sort order".into());
+    }
+
+    Ok(GetDrinks {
+        person_id,
+        filter: DrinkFilter {
+            from: form.from,
+            to: form.to,
+            brewery_id: form.brewery_id,
+            beer_id: form.beer_id,
+            min_rating: form.min_rating,
+            max_rating: form.max_rating,
+            has_comment: form.has_comment,
+            has_tasting_notes: form.has_tasting_notes,
+            min_overall: form.min_overall,
+            descriptors: form
+                .descriptors
+                .as_ref()
+                .map(|descriptors| drinks::descriptors(descriptors))
+                .unwrap_or_default(),
         },
-    )
-    .and_then(|drinks| async move { Ok(HttpResponse::Ok().json(ApiResponse::success(Drinks(drinks)))) })
-    .or_else(|_| async move { Ok(HttpResponse::InternalServerError().into()) })
-    .await
+        sort,
+        after,
+        limit,
+    })
 }
 
 #[derive(Deserialize)]
 struct DrinkForm {
     /// Date on which the drink was had.
-    drank_on: NaiveDate,
+    drank_on: Option<NaiveDate>,
+
+    /// When exactly the drink was had.
+    drank_at: Option<String>,
 
     /// The name of the beer.
     beer: String,
@@ -114,115 +274,143 @@ struct DrinkForm {
     brewery: String,
 
     /// Rating of the beer.
-    rating: i16,
+    rating: Rating,
 
     /// A comment/opinion about the beer.
     comment: Option<String>,
+
+    /// Whether other people may look at the drink.
+    shared: Option<bool>,
+
+    /// How the beer was served.
+    serving: Option<String>,
+    volume_ml: Option<String>,
+    price: Option<String>,
+    abv: Option<String>,
+    venue: Option<String>,
 }
 
 /// Route handler for creating new drink records
 ///
-/// Requires a valid session token in the `Authorization` header.
+/// Requires a valid session token, or an API token with the `drinks:write` scope,
+/// in the `Authorization` header.
 ///
 /// Expects the following POST data:
 ///
 /// - `drank_on`: The date on which the drink was had (yyyy-mm-dd).
-/// - `beer`: The name of the beer
-/// - `brewery`: The name of the brewery
-/// - `rating`: The rating of the beer, 0 - 5
-/// - `comment`: An optional comment about the beer
+/// - `beer`: The name of the beer, of at most 100 characters
+/// - `brewery`: The name of the brewery, of at most 100 characters
+/// - `rating`: The rating of the beer, 0 - 5 in steps of 0.25
+/// - `comment`: An optional comment about the beer, of at most 500 characters
+/// - `shared`: Whether other people may look at the drink, defaults to `false`
+///
+/// And these optional details:
+///
+/// - `drank_at`: When exactly the drink was had, in RFC 3339 format. If `drank_on`
+///   is left out, it is taken from this in the person's timezone.
+/// - `serving`: `draft`, `can`, `bottle` or `cask`
+/// - `volume_ml`: The pour size in millilitres
+/// - `price`: The price paid, such as `6.50`
+/// - `abv`: Alcohol by volume, as a percentage
+/// - `venue`: Where the drink was had
 ///
 /// If no records correspond to the `beer` or `brewery` names, new records will be created.
+/// The drink and any new records are saved in a single transaction.
 async fn new_drink(
     pool: web::Data<Pool>,
-    person: models::Person,
+    person: Scoped<DrinksWrite>,
     details: web::Form<DrinkForm>,
 ) -> ActixResult<HttpResponse> {
-    // Save these for later
-    let beer_name = details.beer.clone();
+    let details = details.into_inner();
+    let checked = drink_details(&details).and_then(|checked| {
+        let drank_on = details
+            .drank_on
+            .or_else(|| {
+                checked
+                    .drank_at
+                    .map(|drank_at| drinks::local_date(drank_at, &person.timezone))
+            })
+            .ok_or_else(|| "Either drank_on or drank_at is required".to_string())?;
 
-    /*********************************************/
-    /*  Closures for database operations         */
-    /*********************************************/
+        let brewery = drinks::brewery(&details.brewery)?;
+        let beer = drinks::beer(&details.beer)?;
+        let comment = optional(&details.comment, drinks::comment)?;
 
-    // This closure will create a new brewery record with the given `name`.
-    let create_brewery = |pool: &Pool, name: String| {
-        db::execute(pool, CreateBrewery { name: name })
-    };
+        Ok((brewery, beer, comment, drank_on, checked))
+    });
 
-    // This closure will create a new beer record, given a `name` and its `brewery_id`.
-    let create_beer = |pool: &Pool, name: String, brewery_id: i32| {
-        db::execute(pool, CreateBeer { name, brewery_id })
-    };
+    let (brewery, beer, comment, drank_on, checked) = match checked {
+        Ok(checked) => checked,
+        Err(message) => {
+            let response = ApiResponse::<()>::from(None)
+                .with_status(ResponseStatus::Fail)
+                .add_message(message);
 
-    // This closure will lookup a brewery given its `name` and,
-    // if no matching record is found, will insert a new one.
-    let get_brewery = |pool: &Pool, name: String| {
-        let pool_clone = pool.clone();
-        db::execute(pool, GetBreweryByName { name: name.clone() })
-            .and_then(move |res| match res {
-                Some(brewery) => Either::Left(futures::future::ready(Ok(brewery))),
-                None => Either::Right(create_brewery(&pool_clone, name)),
-            })
+            return Ok(HttpResponse::BadRequest().json(response));
+        }
     };
 
-    // This closure will lookup a beer given its `name` and `brewery_id` and,
-    // will insert a new one if no record is found.
-    let get_beer = move |pool: &Pool, name: String, brewery_id: i32| {
-        let pool_clone = pool.clone();
-        db::execute(
-            pool,
-            GetBeerByName {
-                name: name.clone(),
-                brewery_id: brewery_id,
-            },
-        )
-        .and_then(move |res| match res {
-            Some(beer) => Either::Left(futures::future::ready(Ok(beer))),
-            None => Either::Right(create_beer(&pool_clone, name, brewery_id)),
-        })
-    };
+    db::execute(
+        &pool,
+        LogDrink {
+            person_id: person.id,
+            drank_on,
+            brewery,
+            beer,
+            rating: details.rating,
+            comment,
+            shared: details.shared.unwrap_or(false),
+            details: checked,
+        },
+    )
+    .then(move |res| async move {
+        match res {
+            Ok(outcome) => Ok(drink_saved(outcome)),
+            Err(e) => {
+                error!("Unable to log drink for person {}! Error: {}", person.id, e);
 
-    // This will insert a new Drink record
-    let record_drink = |pool: &Pool, drink: CreateDrink| {
-        db::execute(pool, drink)
-    };
+                let unexpected_error = ApiResponse::<()>::from(None)
+                    .with_status(ResponseStatus::Error)
+                    .add_message("An unexpected error occurred".into());
 
-    // Get an ExpandedDrink record by ID
-    let get_drink = |pool: &Pool, drink_id: i32| {
-        db::execute(pool, GetDrink { drink_id })
-    };
+                Ok(HttpResponse::InternalServerError().json(unexpected_error))
+            }
+        }
+    })
+    .await
+}
 
-    /*********************************************/
-    /* Begin actual function execution           */
-    /*********************************************/
+/// Check the optional details of a new drink, describing the first problem found.
+fn drink_details(form: &DrinkForm) -> std::result::Result<DrinkDetails, String> {
+    Ok(DrinkDetails {
+        drank_at: optional(&form.drank_at, drinks::drank_at)?,
+        serving: optional(&form.serving, drinks::serving)?,
+        volume_ml: optional(&form.volume_ml, drinks::volume_ml)?,
+        price_cents: optional(&form.price, drinks::price_cents)?,
+        abv: optional(&form.abv, drinks::abv)?,
+        venue: optional(&form.venue, drinks::venue)?,
+    })
+}
 
-    let pool_clone_1 = pool.clone();
-    let pool_clone_2 = pool.clone();
-
-    // Look up the given brewery, and create a new record if one is not found
-    get_brewery(&pool, details.brewery.clone())
-        // Then lookup the beer by name, and create a new record if it is not found.
-        .and_then(move |brewery| get_beer(&pool, beer_name, brewery.id))
-        // Finally, insert a record of the individual drink
-        .and_then(move |beer| {
-            let drink = CreateDrink {
-                person_id: person.id,
-                drank_on: details.drank_on,
-                beer_id: beer.id,
-                rating: details.rating,
-                comment: details.comment.clone(),
-            };
+/// Respond with a drink that was just logged or edited, or why it couldn't be.
+fn drink_saved(outcome: DrinkOutcome) -> HttpResponse {
+    match outcome {
+        DrinkOutcome::Saved(drink) => HttpResponse::Ok().json(ApiResponse::success(drink)),
+        DrinkOutcome::NotFound => {
+            let not_found = ApiResponse::<()>::from(None)
+                .with_status(ResponseStatus::Fail)
+                .add_message("Could not find that drink".into());
 
-            record_drink(&pool_clone_1, drink)
-        })
-        .and_then(move |drink| get_drink(&pool_clone_2, drink.id))
-        // Format the result for output
-        .then(|res| async move { match res {
-            Ok(drink) => Ok(HttpResponse::Ok().json(ApiResponse::success(drink))),
-            Err(_) => Ok(HttpResponse::InternalServerError().into()),
-        }})
-        .await
+            HttpResponse::NotFound().json(not_found)
+        }
+        DrinkOutcome::UnknownDescriptors(names) => {
+            let response = ApiResponse::<()>::from(None)
+                .with_status(ResponseStatus::Fail)
+                .add_message(format!("Unknown flavor descriptors: {}", names.join(", ")));
+
+            HttpResponse::BadRequest().json(response)
+        }
+    }
 }
 
 #[derive(Deserialize)]
@@ -230,354 +418,2772 @@ struct DrinkIdForm {
     id: i32,
 }
 
-async fn delete_drink(
-    person: models::Person,
+/// Route handler for looking at a single drink
+///
+/// Requires a valid session token, or an API token with the `drinks:read` scope,
+/// in the `Authorization` header. Only the person who had the drink can see it,
+/// unless they have shared it.
+async fn get_drink(
+    person: Scoped<DrinksRead>,
     info: web::Path<DrinkIdForm>,
     pool: web::Data<Pool>,
 ) -> ActixResult<HttpResponse> {
     db::execute(
         &pool,
-        DeleteDrink {
+        GetDrink {
             drink_id: info.id,
-            person_id: person.id,
+            viewer_id: person.id,
         },
     )
-    .then(move |res| async move { match res {
-        Ok(0) => {
-            let not_found = ApiResponse::<()>::from(None)
-                .with_status(ResponseStatus::Fail)
-                .add_message("Could not find that drink".into());
+    .then(move |res| async move {
+        match res {
+            Ok(Some(drink)) => Ok(HttpResponse::Ok().json(ApiResponse::success(drink))),
+            Ok(None) => {
+                let not_found = ApiResponse::<()>::from(None)
+                    .with_status(ResponseStatus::Fail)
+                    .add_message("Could not find that drink".into());
+
+                Ok(HttpResponse::NotFound().json(not_found))
+            }
+            Err(e) => {
+                error!("Unable to get drink for person {}! Error: {}", person.id, e);
 
-            Ok(HttpResponse::NotFound().json(not_found))
+                let unexpected_error = ApiResponse::<()>::from(None)
+                    .with_status(ResponseStatus::Error)
+                    .add_message("An unexpected error occurred".into());
+
+                Ok(HttpResponse::InternalServerError().json(unexpected_error))
+            }
         }
-        Ok(1) => {
-            let deleted = ApiResponse::<()>::from(None)
-                .with_status(ResponseStatus::Success)
-                .add_message("Deleted".into());
+    })
+    .await
+}
+
+#[derive(Deserialize)]
+struct DrinkEditForm {
+    drank_on: Option<NaiveDate>,
+    beer: Option<String>,
+    brewery: Option<String>,
+    rating: Option<Rating>,
+    comment: Option<String>,
+    shared: Option<bool>,
+    drank_at: Option<String>,
+    serving: Option<String>,
+    volume_ml: Option<String>,
+    price: Option<String>,
+    abv: Option<String>,
+    venue: Option<String>,
+}
 
-            Ok(HttpResponse::Ok().json(deleted))
+/// Route handler for editing one of the current person's drinks
+///
+/// Requires a valid session token, or an API token with the `drinks:write` scope,
+/// in the `Authorization` header.
+///
+/// Accepts the same fields as `new_drink`, but only the submitted ones are
+/// changed. `beer` and `brewery` must be given together, and an empty value
+/// clears an optional field. Changing `drank_at` without giving `drank_on`
+/// moves the drink to the date it falls on in the person's timezone.
+async fn update_drink(
+    person: Scoped<DrinksWrite>,
+    info: web::Path<DrinkIdForm>,
+    form: web::Form<DrinkEditForm>,
+    pool: web::Data<Pool>,
+) -> ActixResult<HttpResponse> {
+    let beer = match (&form.brewery, &form.beer) {
+        (Some(brewery), Some(beer)) => {
+            drinks::brewery(brewery).and_then(|brewery| Ok(Some((brewery, drinks::beer(beer)?))))
         }
-        Ok(n) => {
-            error!("Person {} somehow deleted {} drinks!", person.id, n);
+        (None, None) => Ok(None),
+        _ => Err("The beer and brewery must be changed together".to_string()),
+    };
 
-            let unexpected_error = ApiResponse::<()>::from(None)
-                .with_status(ResponseStatus::Error)
-                .add_message("An unexpected error occurred".into());
+    let beer = match beer {
+        Ok(beer) => beer,
+        Err(message) => {
+            let response = ApiResponse::<()>::from(None)
+                .with_status(ResponseStatus::Fail)
+                .add_message(message);
 
-            Ok(HttpResponse::InternalServerError().json(unexpected_error))
+            return Ok(HttpResponse::BadRequest().json(response));
         }
-        Err(e) => {
-            error!(
-                "Unable to delete drink for person {}! Error: {}",
-                person.id, e
-            );
+    };
 
-            let unexpected_error = ApiResponse::<()>::from(None)
-                .with_status(ResponseStatus::Error)
-                .add_message("An unexpected error occurred".into());
+    let changes = match drink_changes(&form, &person.timezone) {
+        Ok(changes) => changes,
+        Err(message) => {
+            let response = ApiResponse::<()>::from(None)
+                .with_status(ResponseStatus::Fail)
+                .add_message(message);
+
+            return Ok(HttpResponse::BadRequest().json(response));
+        }
+    };
+
+    db::execute(
+        &pool,
+        UpdateDrink {
+            drink_id: info.id,
+            person_id: person.id,
+            beer,
+            changes,
+        },
+    )
+    .then(move |res| async move {
+        match res {
+            Ok(outcome) => Ok(drink_saved(outcome)),
+            Err(e) => {
+                error!(
+                    "Unable to update drink for person {}! Error: {}",
+                    person.id, e
+                );
+
+                let unexpected_error = ApiResponse::<()>::from(None)
+                    .with_status(ResponseStatus::Error)
+                    .add_message("An unexpected error occurred".into());
 
-            Ok(HttpResponse::InternalServerError().json(unexpected_error))
+                Ok(HttpResponse::InternalServerError().json(unexpected_error))
+            }
         }
-    }})
+    })
     .await
 }
 
-#[derive(Deserialize)]
-struct AuthForm {
-    country_code: u16,
-    phone_number: String,
-    code: Option<String>,
+/// Check the submitted changes to a drink, describing the first problem found.
+fn drink_changes(
+    form: &DrinkEditForm,
+    timezone: &str,
+) -> std::result::Result<models::DrinkChanges, String> {
+    let drank_at = clearable(&form.drank_at, drinks::drank_at)?;
+    let drank_on = form.drank_on.or_else(|| {
+        drank_at
+            .flatten()
+            .map(|drank_at| drinks::local_date(drank_at, timezone))
+    });
+
+    Ok(models::DrinkChanges {
+        drank_on,
+        beer_id: None,
+        rating: form.rating,
+        comment: clearable(&form.comment, drinks::comment)?,
+        shared: form.shared,
+        drank_at,
+        serving: clearable(&form.serving, drinks::serving)?,
+        volume_ml: clearable(&form.volume_ml, drinks::volume_ml)?,
+        price_cents: clearable(&form.price, drinks::price_cents)?,
+        abv: clearable(&form.abv, drinks::abv)?,
+        venue: clearable(&form.venue, drinks::venue)?,
+    })
 }
 
-async fn begin_auth(form: web::Form<AuthForm>) -> ActixResult<HttpResponse> {
-    use authy::api::phone;
-
-    lazy_static! {
-        // See: https://github.com/authy/authy-form-helpers/blob/be2081cd44041ba61173658c100471c8ff7302b9/src/form.authy.js#L693
-        static ref RE: Regex =
-            Regex::new(r"^([0-9][0-9][0-9])\W*([0-9][0-9]{2})\W*([0-9]{0,5})$").unwrap();
-    }
+async fn delete_drink(
+    person: Scoped<DrinksWrite>,
+    info: web::Path<DrinkIdForm>,
+    pool: web::Data<Pool>,
+    store: web::Data<photos::Store>,
+) -> ActixResult<HttpResponse> {
+    db::execute(
+        &pool,
+        DeleteDrink {
+            drink_id: info.id,
+            person_id: person.id,
+        },
+    )
+    .then(move |res| async move {
+        match res {
+            Ok(None) => {
+                let not_found = ApiResponse::<()>::from(None)
+                    .with_status(ResponseStatus::Fail)
+                    .add_message("Could not find that drink".into());
 
-    // Check to make sure that the identity submitted appears to be a phone number
-    if !RE.is_match(&form.phone_number) {
-        info!(
-            "Received invalid phone number '{}' '{}'!",
-            form.country_code, form.phone_number
-        );
+                Ok(HttpResponse::NotFound().json(not_found))
+            }
+            Ok(Some(photo_keys)) => {
+                photos::remove(&store, photo_keys).await;
 
-        let response = ApiResponse::<()>::from(None)
-            .with_status(ResponseStatus::Fail)
-            .add_message("Invalid phone number".into());
+                let deleted = ApiResponse::<()>::from(None)
+                    .with_status(ResponseStatus::Success)
+                    .add_message("Deleted".into());
 
-        return Ok(HttpResponse::BadRequest().json(response));
-    }
+                Ok(HttpResponse::Ok().json(deleted))
+            }
+            Err(e) => {
+                error!(
+                    "Unable to delete drink for person {}! Error: {}",
+                    person.id, e
+                );
 
-    let client = authy::Client::new(
-        "https://api.authy.com",
-        &std::env::var("AUTHY_API_KEY").expect("An authy API key is required!"),
-    );
+                let unexpected_error = ApiResponse::<()>::from(None)
+                    .with_status(ResponseStatus::Error)
+                    .add_message("An unexpected error occurred".into());
 
-        web::block(move || {
-            phone::start(
-                &client,
-                phone::ContactType::SMS,
-                form.country_code,
-                &form.phone_number,
-                Some(6),
-                None,
-            ).map_err(|e| Error::from(e))
-        })
-        .map(|f| {
-            match f {
-                Ok(f) => Ok(f),
-                Err(BlockingError::Error(e)) => Err(Error::from(e)),
-                Err(BlockingError::Canceled) => Err(Error::from(Canceled)),
+                Ok(HttpResponse::InternalServerError().json(unexpected_error))
             }
-        })
-        .and_then(|(status, _start)| async move {
-            let response = ApiResponse::<()>::from(None).add_message(status.message);
+        }
+    })
+    .await
+}
 
-            Ok(HttpResponse::Ok().json(response))
-        })
-        .or_else(|e| async move {
-            error!("Failed to start phone number verification! Error: {}", e);
+#[derive(Deserialize)]
+struct ImportForm {
+    date: Option<String>,
+    beer: Option<String>,
+    brewery: Option<String>,
+    rating: Option<String>,
+    comment: Option<String>,
+    date_format: Option<String>,
+
+    /// Report what the import would do without saving anything.
+    #[serde(default)]
+    dry_run: bool,
+}
+
+/// Route handler for importing many drinks at once from a CSV file
+///
+/// Requires a valid session token, or an API token with the `drinks:write` scope,
+/// in the `Authorization` header.
+///
+/// Expects the CSV file, with a header row, as the request body. The query string
+/// says which columns to read and whether to save anything:
+///
+/// - `date`, `beer`, `brewery`, `rating`: The column holding each part of a drink,
+///   which default to `drank_on`, `beer`, `brewery` and `rating`
+/// - `comment`: The column holding comments, if there is one besides `comment`
+/// - `date_format`: How dates are written, in `strftime` format; `%Y-%m-%d` by default
+/// - `dry_run`: `true` to report which beers and breweries would be matched or
+///   added to the catalog, without saving anything
+///
+/// Drinks are only saved if every row can be. Otherwise the response fails with
+/// the problem in each row that couldn't be, by line number.
+async fn import_drinks(
+    person: Scoped<DrinksWrite>,
+    form: web::Query<ImportForm>,
+    body: web::Bytes,
+    pool: web::Data<Pool>,
+) -> ActixResult<HttpResponse> {
+    let form = form.into_inner();
+    let defaults = ColumnMapping::default();
+    let mapping = ColumnMapping {
+        date: form.date.unwrap_or(defaults.date),
+        beer: form.beer.unwrap_or(defaults.beer),
+        brewery: form.brewery.unwrap_or(defaults.brewery),
+        rating: form.rating.unwrap_or(defaults.rating),
+        comment: form.comment,
+        date_format: form.date_format.unwrap_or(defaults.date_format),
+    };
 
+    let parsed = match import::parse(&body, &mapping) {
+        Ok(parsed) => parsed,
+        Err(message) => {
             let response = ApiResponse::<()>::from(None)
-                .with_status(ResponseStatus::Error)
-                .add_message("That phone number didn't work :(".into());
+                .with_status(ResponseStatus::Fail)
+                .add_message(message);
 
-            Ok(HttpResponse::BadRequest().json(response))
-        })
-        .await
+            return Ok(HttpResponse::BadRequest().json(response));
+        }
+    };
+
+    db::execute(
+        &pool,
+        ImportDrinks {
+            person_id: person.id,
+            rows: parsed.rows,
+            errors: parsed.errors,
+            dry_run: form.dry_run,
+        },
+    )
+    .then(move |res| async move {
+        match res {
+            Ok(report) if report.errors.is_empty() => {
+                Ok(HttpResponse::Ok().json(ApiResponse::success(report)))
+            }
+            Ok(report) => {
+                let response = ApiResponse::from(Some(report))
+                    .with_status(ResponseStatus::Fail)
+                    .add_message("Some rows can't be imported, so nothing was saved".into());
+
+                Ok(HttpResponse::BadRequest().json(response))
+            }
+            Err(e) => {
+                error!(
+                    "Unable to import drinks for person {}! Error: {}",
+                    person.id, e
+                );
+
+                let unexpected_error = ApiResponse::<()>::from(None)
+                    .with_status(ResponseStatus::Error)
+                    .add_message("An unexpected error occurred".into());
+
+                Ok(HttpResponse::InternalServerError().json(unexpected_error))
+            }
+        }
+    })
+    .await
 }
 
-async fn complete_auth(
-    form: web::Form<AuthForm>,
+/// Route handler for attaching a photo to one of the current person's drinks
+///
+/// Requires a valid session token, or an API token with the `drinks:write` scope,
+/// in the `Authorization` header.
+///
+/// Expects a `multipart/form-data` body with a JPEG, PNG, GIF or WebP image of at
+/// most 10 MiB in its `photo` field. The image is re-encoded as JPEG without its
+/// EXIF metadata, and a thumbnail is made. Responds with the drink, which lists
+/// the URLs of all of its photos.
+async fn upload_photo(
+    person: Scoped<DrinksWrite>,
+    info: web::Path<DrinkIdForm>,
+    mut payload: Multipart,
     pool: web::Data<Pool>,
+    store: web::Data<photos::Store>,
 ) -> ActixResult<HttpResponse> {
-    use authy::api::phone;
+    let upload = match read_upload(&mut payload, "photo").await {
+        Ok(upload) => upload,
+        Err(response) => return Ok(response),
+    };
 
-    let pool_clone = pool.clone();
+    let processed = match upload {
+        Some(bytes) => photos::process_upload(bytes, photos::process).await,
+        None => Ok(Err("A photo is required".to_string())),
+    };
 
-    /*********************************************/
-    /*  Closures for database operations         */
-    /*********************************************/
+    let processed = match processed {
+        Ok(Ok(processed)) => processed,
+        Ok(Err(message)) => {
+            let response = ApiResponse::<()>::from(None)
+                .with_status(ResponseStatus::Fail)
+                .add_message(message);
 
-    let lookup_idenity = |pool: &Pool, country_code: u16, phone_number: String| {
-        db::execute(
-            pool,
-            LookupIdentiy {
-                identifier: format!("{}{}", country_code, phone_number),
-            },
-        )
+            return Ok(HttpResponse::BadRequest().json(response));
+        }
+        Err(e) => return Ok(photo_error(person.id, e)),
     };
 
-    let start_session = |pool: &Pool, person_id: i32| {
-        db::execute(pool, StartSession { person_id })
-    };
+    let (blob_key, thumbnail_key) = photos::new_keys();
 
-    /*********************************************/
-    /*  Begin request handling logic             */
-    /*********************************************/
+    let blobs = vec![
+        (blob_key.clone(), processed.photo),
+        (thumbnail_key.clone(), processed.thumbnail),
+    ];
 
-    lazy_static! {
-        // See: https://github.com/authy/authy-form-helpers/blob/be2081cd44041ba61173658c100471c8ff7302b9/src/form.authy.js#L693
-        static ref RE: Regex =
-            Regex::new(r"^([0-9][0-9][0-9])\W*([0-9][0-9]{2})\W*([0-9]{0,5})$").unwrap();
+    if let Err(e) = photos::put(&store, blobs).await {
+        return Ok(photo_error(person.id, e));
     }
 
-    // Make sure some kind of verification code was submitted
-    if form.code.is_none() {
-        info!("Verification code was submitted!");
+    let outcome = db::execute(
+        &pool,
+        AddDrinkPhoto {
+            drink_id: info.id,
+            person_id: person.id,
+            blob_key: blob_key.clone(),
+            thumbnail_key: thumbnail_key.clone(),
+            width: processed.width,
+            height: processed.height,
+        },
+    )
+    .await;
 
-        let response = ApiResponse::<()>::from(None)
-            .with_status(ResponseStatus::Fail)
-            .add_message("Missing verification code!".into());
+    // Don't leave the photo behind in the store if it wasn't attached
+    if !matches!(outcome, Ok(DrinkOutcome::Saved(_))) {
+        photos::remove(&store, vec![blob_key, thumbnail_key]).await;
+    }
 
-        return Ok(HttpResponse::BadRequest().json(response));
+    match outcome {
+        Ok(outcome) => Ok(drink_saved(outcome)),
+        Err(e) => Ok(photo_error(person.id, e)),
     }
+}
 
-    // Check to make sure that the identity submitted appears to be a phone number
-    if !RE.is_match(&form.phone_number) {
-        info!(
-            "Received invalid phone number '{}' '{}'!",
-            form.country_code, form.phone_number
-        );
+fn photo_error(person_id: i32, e: error::Error) -> HttpResponse {
+    error!(
+        "Unable to save a photo for person {}! Error: {}",
+        person_id, e
+    );
+
+    let unexpected_error = ApiResponse::<()>::from(None)
+        .with_status(ResponseStatus::Error)
+        .add_message("An unexpected error occurred".into());
+
+    HttpResponse::InternalServerError().json(unexpected_error)
+}
 
+/// Read the image in the `name` field of a `multipart/form-data` upload,
+/// skipping over any other fields.
+///
+/// Returns the response to send if the upload can't be read or is too large.
+async fn read_upload(
+    payload: &mut Multipart,
+    name: &str,
+) -> std::result::Result<Option<Vec<u8>>, HttpResponse> {
+    let unreadable = |e: actix_multipart::MultipartError| {
         let response = ApiResponse::<()>::from(None)
             .with_status(ResponseStatus::Fail)
-            .add_message("Invalid phone number!".into());
+            .add_message(format!("Unable to read the upload: {}", e));
 
-        return Ok(HttpResponse::BadRequest().json(response));
+        HttpResponse::BadRequest().json(response)
+    };
+
+    let mut upload = None;
+
+    while let Some(field) = payload.next().await {
+        let mut field = field.map_err(unreadable)?;
+        let field_name = field
+            .content_disposition()
+            .and_then(|disposition| disposition.get_name().map(String::from));
+
+        if field_name.as_ref().map(String::as_str) != Some(name) {
+            continue;
+        }
+
+        let mut bytes = Vec::new();
+
+        while let Some(chunk) = field.next().await {
+            let chunk = chunk.map_err(unreadable)?;
+
+            if bytes.len() + chunk.len() > photos::MAX_UPLOAD_BYTES {
+                let response = ApiResponse::<()>::from(None)
+                    .with_status(ResponseStatus::Fail)
+                    .add_message("Images can be at most 10 MiB".into());
+
+                return Err(HttpResponse::PayloadTooLarge().json(response));
+            }
+
+            bytes.extend_from_slice(&chunk);
+        }
+
+        upload = Some(bytes);
+    }
+
+    Ok(upload)
+}
+
+#[derive(Deserialize)]
+struct DrinkPhotoForm {
+    id: i32,
+    photo_id: i32,
+}
+
+/// Route handler for removing a photo from one of the current person's drinks
+///
+/// Requires a valid session token, or an API token with the `drinks:write` scope,
+/// in the `Authorization` header.
+async fn delete_photo(
+    person: Scoped<DrinksWrite>,
+    info: web::Path<DrinkPhotoForm>,
+    pool: web::Data<Pool>,
+    store: web::Data<photos::Store>,
+) -> ActixResult<HttpResponse> {
+    db::execute(
+        &pool,
+        DeleteDrinkPhoto {
+            drink_id: info.id,
+            photo_id: info.photo_id,
+            person_id: person.id,
+        },
+    )
+    .then(move |res| async move {
+        match res {
+            Ok(Some(photo_keys)) => {
+                photos::remove(&store, photo_keys).await;
+
+                let deleted = ApiResponse::<()>::from(None)
+                    .with_status(ResponseStatus::Success)
+                    .add_message("Deleted".into());
+
+                Ok(HttpResponse::Ok().json(deleted))
+            }
+            Ok(None) => {
+                let not_found = ApiResponse::<()>::from(None)
+                    .with_status(ResponseStatus::Fail)
+                    .add_message("Could not find that photo".into());
+
+                Ok(HttpResponse::NotFound().json(not_found))
+            }
+            Err(e) => {
+                error!(
+                    "Unable to delete a photo for person {}! Error: {}",
+                    person.id, e
+                );
+
+                let unexpected_error = ApiResponse::<()>::from(None)
+                    .with_status(ResponseStatus::Error)
+                    .add_message("An unexpected error occurred".into());
+
+                Ok(HttpResponse::InternalServerError().json(unexpected_error))
+            }
+        }
+    })
+    .await
+}
+
+#[derive(Deserialize)]
+struct PhotoKeyForm {
+    key: String,
+}
+
+/// Route handler for fetching a photo, thumbnail or avatar from the blob store
+///
+/// Avatars and the photos of shared drinks need no authentication, so that they
+/// can be shown with a plain `<img>` tag. The photos of other drinks need a valid
+/// session token, or an API token with the `drinks:read` scope, for the person
+/// who had the drink; anyone else is told there's no such photo.
+async fn get_photo(
+    viewer: Option<Scoped<DrinksRead>>,
+    info: web::Path<PhotoKeyForm>,
+    pool: web::Data<Pool>,
+    store: web::Data<photos::Store>,
+) -> ActixResult<HttpResponse> {
+    let key = info.into_inner().key;
+    let access = db::execute(
+        &pool,
+        GetPhotoAccess {
+            key: key.clone(),
+            viewer_id: viewer.map(|viewer| viewer.id),
+        },
+    )
+    .await;
+
+    // Drinks can stop being shared, so nothing is cached for long
+    let cache_control = match access {
+        Ok(PhotoAccess::Public) => "public, max-age=3600",
+        Ok(PhotoAccess::Owner) => "private, max-age=3600",
+        Ok(PhotoAccess::Hidden) => return Ok(HttpResponse::NotFound().finish()),
+        Err(e) => return Ok(photo_fetch_error(&key, e)),
+    };
+
+    match photos::get(&store, key.clone()).await {
+        Ok(Some(bytes)) => Ok(HttpResponse::Ok()
+            .content_type("image/jpeg")
+            .set_header(actix_web::http::header::CACHE_CONTROL, cache_control)
+            .body(bytes)),
+        Ok(None) => Ok(HttpResponse::NotFound().finish()),
+        Err(e) => Ok(photo_fetch_error(&key, e)),
+    }
+}
+
+fn photo_fetch_error(key: &str, e: error::Error) -> HttpResponse {
+    error!("Unable to fetch photo '{}'! Error: {}", key, e);
+
+    let unexpected_error = ApiResponse::<()>::from(None)
+        .with_status(ResponseStatus::Error)
+        .add_message("An unexpected error occurred".into());
+
+    HttpResponse::InternalServerError().json(unexpected_error)
+}
+
+#[derive(Deserialize)]
+struct TastingNotesForm {
+    appearance: Option<String>,
+    aroma: Option<String>,
+    flavor: Option<String>,
+    mouthfeel: Option<String>,
+    overall: Option<String>,
+
+    /// Comma separated flavor descriptors, such as `citrus, roasty`.
+    descriptors: Option<String>,
+}
+
+/// Route handler for giving one of the current person's drinks tasting notes
+///
+/// Requires a valid session token, or an API token with the `drinks:write` scope,
+/// in the `Authorization` header.
+///
+/// Replaces any notes the drink already has. Every field is optional:
+///
+/// - `appearance`: Scored from 0 to 3
+/// - `aroma`: Scored from 0 to 12
+/// - `flavor`: Scored from 0 to 20
+/// - `mouthfeel`: Scored from 0 to 5
+/// - `overall`: The overall impression, scored from 0 to 10
+/// - `descriptors`: Flavors picked out in the drink, from `GET /drink/descriptors`
+async fn set_tasting_notes(
+    person: Scoped<DrinksWrite>,
+    info: web::Path<DrinkIdForm>,
+    form: web::Form<TastingNotesForm>,
+    pool: web::Data<Pool>,
+) -> ActixResult<HttpResponse> {
+    let notes = match tasting_notes(&form) {
+        Ok(notes) => notes,
+        Err(message) => {
+            let response = ApiResponse::<()>::from(None)
+                .with_status(ResponseStatus::Fail)
+                .add_message(message);
+
+            return Ok(HttpResponse::BadRequest().json(response));
+        }
+    };
+
+    db::execute(
+        &pool,
+        SetTastingNotes {
+            drink_id: info.id,
+            person_id: person.id,
+            notes,
+        },
+    )
+    .then(move |res| async move {
+        match res {
+            Ok(outcome) => Ok(drink_saved(outcome)),
+            Err(e) => {
+                error!(
+                    "Unable to save tasting notes for person {}! Error: {}",
+                    person.id, e
+                );
+
+                let unexpected_error = ApiResponse::<()>::from(None)
+                    .with_status(ResponseStatus::Error)
+                    .add_message("An unexpected error occurred".into());
+
+                Ok(HttpResponse::InternalServerError().json(unexpected_error))
+            }
+        }
+    })
+    .await
+}
+
+/// Check submitted tasting notes, describing the first problem found.
+fn tasting_notes(form: &TastingNotesForm) -> std::result::Result<TastingNotes, String> {
+    Ok(TastingNotes {
+        appearance: optional(&form.appearance, |appearance| {
+            drinks::score(appearance, drinks::MAX_APPEARANCE, "Appearance")
+        })?,
+        aroma: optional(&form.aroma, |aroma| {
+            drinks::score(aroma, drinks::MAX_AROMA, "Aroma")
+        })?,
+        flavor: optional(&form.flavor, |flavor| {
+            drinks::score(flavor, drinks::MAX_FLAVOR, "Flavor")
+        })?,
+        mouthfeel: optional(&form.mouthfeel, |mouthfeel| {
+            drinks::score(mouthfeel, drinks::MAX_MOUTHFEEL, "Mouthfeel")
+        })?,
+        overall: optional(&form.overall, |overall| {
+            drinks::score(overall, drinks::MAX_OVERALL, "Overall impression")
+        })?,
+        descriptors: form
+            .descriptors
+            .as_ref()
+            .map(|descriptors| drinks::descriptors(descriptors))
+            .unwrap_or_default(),
+    })
+}
+
+/// Route handler for removing the tasting notes from one of the current person's drinks
+///
+/// Requires a valid session token, or an API token with the `drinks:write` scope,
+/// in the `Authorization` header.
+async fn delete_tasting_notes(
+    person: Scoped<DrinksWrite>,
+    info: web::Path<DrinkIdForm>,
+    pool: web::Data<Pool>,
+) -> ActixResult<HttpResponse> {
+    db::execute(
+        &pool,
+        DeleteTastingNotes {
+            drink_id: info.id,
+            person_id: person.id,
+        },
+    )
+    .then(move |res| async move {
+        match res {
+            Ok(0) => {
+                let not_found = ApiResponse::<()>::from(None)
+                    .with_status(ResponseStatus::Fail)
+                    .add_message("Could not find tasting notes for that drink".into());
+
+                Ok(HttpResponse::NotFound().json(not_found))
+            }
+            Ok(_) => {
+                let deleted = ApiResponse::<()>::from(None)
+                    .with_status(ResponseStatus::Success)
+                    .add_message("Deleted".into());
+
+                Ok(HttpResponse::Ok().json(deleted))
+            }
+            Err(e) => {
+                error!(
+                    "Unable to delete tasting notes for person {}! Error: {}",
+                    person.id, e
+                );
+
+                let unexpected_error = ApiResponse::<()>::from(None)
+                    .with_status(ResponseStatus::Error)
+                    .add_message("An unexpected error occurred".into());
+
+                Ok(HttpResponse::InternalServerError().json(unexpected_error))
+            }
+        }
+    })
+    .await
+}
+
+/// Route handler for listing the flavors that tasting notes can pick out
+///
+/// Requires a valid session token, or an API token with the `drinks:read` scope,
+/// in the `Authorization` header.
+async fn get_flavor_descriptors(
+    _person: Scoped<DrinksRead>,
+    pool: web::Data<Pool>,
+) -> ActixResult<HttpResponse> {
+    #[derive(Serialize)]
+    #[serde(rename = "flavor_descriptors")]
+    struct Descriptors(Vec<models::FlavorDescriptor>);
+
+    db::execute(&pool, GetFlavorDescriptors)
+        .then(|res| async move {
+            match res {
+                Ok(descriptors) => {
+                    Ok(HttpResponse::Ok().json(ApiResponse::success(Descriptors(descriptors))))
+                }
+                Err(e) => {
+                    error!("Unable to list flavor descriptors! Error: {}", e);
+
+                    let unexpected_error = ApiResponse::<()>::from(None)
+                        .with_status(ResponseStatus::Error)
+                        .add_message("An unexpected error occurred".into());
+
+                    Ok(HttpResponse::InternalServerError().json(unexpected_error))
+                }
+            }
+        })
+        .await
+}
+
+#[derive(Deserialize)]
+struct AuthForm {
+    country_code: u16,
+    phone_number: String,
+    code: Option<String>,
+
+    /// An optional, person-chosen name for the device the session is started on.
+    device: Option<String>,
+
+    /// Keep the session in a cookie instead of returning its token, for browsers.
+    #[serde(default)]
+    cookie: bool,
+}
+
+async fn begin_auth(
+    req: HttpRequest,
+    form: web::Form<AuthForm>,
+    verifier: web::Data<verification::Provider>,
+    throttle: web::Data<Throttle>,
+) -> ActixResult<HttpResponse> {
+    // Check to make sure that the identity submitted is a valid phone number
+    let phone = match PhoneNumber::parse(form.country_code, &form.phone_number) {
+        Ok(phone) => phone,
+        Err(e) => {
+            info!(
+                "Received invalid phone number '{}' '{}'! {}",
+                form.country_code, form.phone_number, e
+            );
+
+            let response = ApiResponse::<()>::from(None)
+                .with_status(ResponseStatus::Fail)
+                .add_message(format!("Invalid phone number: {}", e));
+
+            return Ok(HttpResponse::BadRequest().json(response));
+        }
+    };
+
+    // Limit how often codes are sent, so this can't be used to flood a phone with messages
+    let config = throttle.config();
+    throttle
+        .attempt(vec![
+            (format!("start:phone:{}", phone), config.start_per_identity),
+            (format!("start:ip:{}", throttle.client_ip(&req)), config.start_per_ip),
+        ])
+        .await?;
+
+    let started =
+        Event::new(AuthEventKind::VerificationStarted).identity(IdentityKind::Phone, phone.e164());
+
+    verification::start(
+        &verifier,
+        phone.country_code(),
+        phone.national_number().to_string(),
+    )
+        .and_then(|message| async move {
+            audit::record(&req, started);
+
+            let response = ApiResponse::<()>::from(None).add_message(message);
+
+            Ok(HttpResponse::Ok().json(response))
+        })
+        .or_else(|e| async move {
+            error!("Failed to start phone number verification! Error: {}", e);
+
+            let response = ApiResponse::<()>::from(None)
+                .with_status(ResponseStatus::Error)
+                .add_message("That phone number didn't work :(".into());
+
+            Ok(HttpResponse::BadRequest().json(response))
+        })
+        .await
+}
+
+async fn complete_auth(
+    req: HttpRequest,
+    form: web::Form<AuthForm>,
+    pool: web::Data<Pool>,
+    verifier: web::Data<verification::Provider>,
+    throttle: web::Data<Throttle>,
+    session_config: web::Data<SessionConfig>,
+) -> ActixResult<HttpResponse> {
+    if form.cookie && session_config.cookie.is_none() {
+        return Ok(cookie_sessions_disabled());
     }
 
+    let use_cookie = form.cookie;
+    let response_config = session_config.clone();
+    let pool_clone = pool.clone();
+
     /*********************************************/
-    /*  Verify the phone number and code         */
+    /*  Closures for database operations         */
     /*********************************************/
 
-    let client = authy::Client::new(
-        "https://api.authy.com",
-        &std::env::var("AUTHY_API_KEY").expect("An authy API key is required!"),
-    );
+    let lookup_idenity = |pool: &Pool, phone: &PhoneNumber| {
+        db::execute(
+            pool,
+            LookupIdentiy {
+                kind: IdentityKind::Phone,
+                identifier: phone.e164(),
+            },
+        )
+    };
 
-    let verification_code = form.code.clone().unwrap_or("wtf".into());
-    let verification_code_clone_1 = verification_code.clone();
-    let verification_code_clone_2 = verification_code.clone();
-
-    // We're going to move `form` into a closure, so copy these fields
-    // as they're needed in a different spot.
-    let full_number = (form.country_code, form.phone_number.clone());
-    let full_number_clone_1 = full_number.clone();
-    let full_number_clone_2 = full_number.clone();
-
-    web::block(move || {
-        phone::check(
-            &client,
-            form.country_code,
-            &form.phone_number,
-            &verification_code,
+    let (device_label, user_agent) = device_details(&req, form.device.as_ref());
+
+    let start_session = move |pool: &Pool, person_id: i32| {
+        db::execute(
+            pool,
+            StartSession {
+                person_id,
+                config: session_config.get_ref().clone(),
+                device_label,
+                user_agent,
+            },
         )
-        .map_err(|e| Error::from(e))
-    })
-    .map(|f| {
-        match f {
-            Ok(f) => Ok(f),
-            Err(BlockingError::Error(e)) => Err(Error::from(e)),
-            Err(BlockingError::Canceled) => Err(Error::from(Canceled)),
+    };
+
+    /*********************************************/
+    /*  Begin request handling logic             */
+    /*********************************************/
+
+    // Make sure some kind of verification code was submitted
+    let verification_code = match form.code {
+        Some(ref code) => code.clone(),
+        None => {
+            info!("Verification code was not submitted!");
+
+            let response = ApiResponse::<()>::from(None)
+                .with_status(ResponseStatus::Fail)
+                .add_message("Missing verification code!".into());
+
+            return Ok(HttpResponse::BadRequest().json(response));
         }
-    })
-    .and_then(|verify_status| async move {
-        // If the verification code was invalid, return an error
-        if !verify_status.success {
-            warn!(
-                "Invalid verification code, '{}', submitted for '{}' '{}'!",
-                verification_code_clone_1, full_number_clone_1.0, full_number_clone_1.1
+    };
+
+    // Check to make sure that the identity submitted is a valid phone number
+    let phone = match PhoneNumber::parse(form.country_code, &form.phone_number) {
+        Ok(phone) => phone,
+        Err(e) => {
+            info!(
+                "Received invalid phone number '{}' '{}'! {}",
+                form.country_code, form.phone_number, e
             );
 
             let response = ApiResponse::<()>::from(None)
                 .with_status(ResponseStatus::Fail)
-                .add_message("Invalid verification code".into());
+                .add_message(format!("Invalid phone number: {}", e));
+
+            return Ok(HttpResponse::BadRequest().json(response));
+        }
+    };
+
+    /*********************************************/
+    /*  Verify the phone number and code         */
+    /*********************************************/
+
+    if let Err(response) =
+        verify_phone_code(&req, &verifier, &throttle, &phone, verification_code).await
+    {
+        return Ok(response);
+    }
 
-            return Ok(Err(
-                HttpResponse::Forbidden().json(response),
-            ));
+    /*********************************************/
+    /*  Verified, find identity, start session   */
+    /*********************************************/
+
+    lookup_idenity(&pool, &phone)
+        .and_then(move |ident| start_session(&pool_clone, ident.person_id))
+        .then(move |res| async move {
+            match res {
+                Ok(session) => {
+                    info!(
+                        "Successfully verified identity for person {}",
+                        session.person_id
+                    );
+
+                    Ok(session_started(&req, session, use_cookie, &response_config))
+                }
+                Err(e) => {
+                    error!("Failed to start session! Error: {}", e);
+
+                    let response = ApiResponse::<()>::from(None)
+                        .with_status(ResponseStatus::Error)
+                        .add_message("Internal server error".into());
+
+                    Ok(HttpResponse::InternalServerError().json(response))
+                }
+            }
+        })
+        .await
+}
+
+/// Check a phone verification code with the verification provider.
+///
+/// Attempts are rate limited, and a phone number is locked out after too many
+/// wrong codes. Returns the response to send if the code wasn't accepted.
+async fn verify_phone_code(
+    req: &HttpRequest,
+    verifier: &verification::Provider,
+    throttle: &Throttle,
+    phone: &PhoneNumber,
+    code: String,
+) -> std::result::Result<(), HttpResponse> {
+    let config = throttle.config();
+    let lockout_key = format!("failed:phone:{}", phone);
+
+    let throttled = throttle
+        .check_lockout(lockout_key.clone())
+        .and_then(|_| {
+            throttle.attempt(vec![
+                (format!("verify:phone:{}", phone), config.verify_per_identity),
+                (format!("verify:ip:{}", throttle.client_ip(req)), config.verify_per_ip),
+            ])
+        })
+        .await;
+
+    let event = |kind| Event::new(kind).identity(IdentityKind::Phone, phone.e164());
+
+    if let Err(e) = throttled {
+        if let error::Error::RateLimited(_) = e {
+            audit::record(
+                req,
+                event(AuthEventKind::VerificationFailed).detail("Rate limited".into()),
+            );
         }
 
+        return Err(e.error_response());
+    }
+
+    let outcome = verification::check(
+        verifier,
+        phone.country_code(),
+        phone.national_number().to_string(),
+        code.clone(),
+    )
+    .await;
+
+    match outcome {
         // Verification was correct
-        info!(
-            "Phone number {} {} verified!",
-            full_number_clone_1.0, full_number_clone_1.1
-        );
+        Ok(CheckOutcome::Verified) => {
+            info!("Phone number {} verified!", phone);
+
+            audit::record(req, event(AuthEventKind::VerificationSucceeded));
+
+            if let Err(e) = throttle.clear(lockout_key).await {
+                warn!("Unable to clear failed codes for '{}'! Error: {}", phone, e);
+            }
+
+            Ok(())
+        }
+        // If the verification code was invalid, return an error
+        Ok(CheckOutcome::InvalidCode) => {
+            warn!(
+                "Invalid verification code, '{}', submitted for '{}'!",
+                code, phone
+            );
+
+            audit::record(
+                req,
+                event(AuthEventKind::VerificationFailed).detail("Invalid code".into()),
+            );
+
+            match throttle.record_failure(lockout_key).await {
+                Ok(true) => audit::record(req, event(AuthEventKind::LockedOut)),
+                Ok(false) => {}
+                Err(e) => error!("Unable to record failed code for '{}'! Error: {}", phone, e),
+            }
+
+            let response = ApiResponse::<()>::from(None)
+                .with_status(ResponseStatus::Fail)
+                .add_message("Invalid verification code".into());
+
+            Err(HttpResponse::Forbidden().json(response))
+        }
+        // If the provider refused to verify the code for some other reason
+        Ok(CheckOutcome::Rejected(reason)) => {
+            warn!(
+                "Unexpected verification response, '{}', submitted for '{}'! Reason: {}",
+                code, phone, reason
+            );
+
+            audit::record(req, event(AuthEventKind::VerificationFailed).detail(reason));
+
+            let response = ApiResponse::<()>::from(None)
+                .with_status(ResponseStatus::Fail)
+                .add_message("Unable to verify the code".into());
+
+            Err(HttpResponse::Forbidden().json(response))
+        }
+        // Something awful happened
+        Err(e) => {
+            error!(
+                "Unable to verify code, '{}', submitted for '{}'! Error: {}",
+                code, phone, e
+            );
+
+            let response = ApiResponse::<()>::from(None)
+                .with_status(ResponseStatus::Error)
+                .add_message("Internal server error".into());
+
+            Err(HttpResponse::InternalServerError().json(response))
+        }
+    }
+}
+
+/// Get the device label and user agent to record with a new session.
+fn device_details(req: &HttpRequest, device: Option<&String>) -> (Option<String>, Option<String>) {
+    let device_label = device
+        .map(|device| device.trim().chars().take(128).collect::<String>())
+        .filter(|device| !device.is_empty());
+
+    let user_agent = req
+        .headers()
+        .get(http::header::USER_AGENT)
+        .and_then(|user_agent| user_agent.to_str().ok())
+        .map(|user_agent| user_agent.chars().take(512).collect::<String>());
+
+    (device_label, user_agent)
+}
+
+#[derive(Deserialize)]
+struct EmailAuthForm {
+    email: String,
+}
+
+/// Route handler for starting an email login.
+///
+/// Sends a single-use login link to the given address.
+async fn begin_email_auth(
+    req: HttpRequest,
+    form: web::Form<EmailAuthForm>,
+    pool: web::Data<Pool>,
+    mailer: web::Data<mail::Provider>,
+    throttle: web::Data<Throttle>,
+    email_config: web::Data<EmailLoginConfig>,
+) -> ActixResult<HttpResponse> {
+    let address = match mail::normalize_address(&form.email) {
+        Some(address) => address,
+        None => {
+            info!("Received invalid email address '{}'!", form.email);
+
+            let response = ApiResponse::<()>::from(None)
+                .with_status(ResponseStatus::Fail)
+                .add_message("Invalid email address".into());
+
+            return Ok(HttpResponse::BadRequest().json(response));
+        }
+    };
+
+    let config = throttle.config();
+    throttle
+        .attempt(vec![
+            (format!("start:email:{}", address), config.start_per_identity),
+            (format!("start:ip:{}", throttle.client_ip(&req)), config.start_per_ip),
+        ])
+        .await?;
+
+    let challenge = db::execute(
+        &pool,
+        CreateEmailChallenge {
+            email: address.clone(),
+            lifetime: email_config.lifetime,
+        },
+    )
+    .await;
+
+    let login_token = match challenge {
+        Ok(login_token) => login_token,
+        Err(e) => {
+            error!("Failed to create email challenge! Error: {}", e);
+
+            let response = ApiResponse::<()>::from(None)
+                .with_status(ResponseStatus::Error)
+                .add_message("Internal server error".into());
+
+            return Ok(HttpResponse::InternalServerError().json(response));
+        }
+    };
+
+    let email = mail::Email {
+        to: address.clone(),
+        subject: "Your Mug Club login link".into(),
+        body: format!(
+            "Follow this link to log in to Mug Club:\n\n{}?token={}\n\n\
+             The link expires in {} minutes and can only be used once. \
+             If you didn't ask to log in, you can ignore this email.",
+            email_config.link_url,
+            login_token,
+            email_config.lifetime.num_minutes()
+        ),
+    };
+
+    let started = Event::new(AuthEventKind::VerificationStarted)
+        .identity(IdentityKind::Email, address.clone());
+
+    mail::send(&mailer, email)
+        .and_then(|_| async move {
+            audit::record(&req, started);
+
+            let response =
+                ApiResponse::<()>::from(None).add_message("Check your email for a login link".into());
+
+            Ok(HttpResponse::Ok().json(response))
+        })
+        .or_else(move |e| async move {
+            error!("Failed to send login email to '{}'! Error: {}", address, e);
+
+            let response = ApiResponse::<()>::from(None)
+                .with_status(ResponseStatus::Error)
+                .add_message("That email address didn't work :(".into());
+
+            Ok(HttpResponse::InternalServerError().json(response))
+        })
+        .await
+}
+
+#[derive(Deserialize)]
+struct EmailVerifyForm {
+    /// The token from the login link.
+    token: String,
+
+    /// An optional, person-chosen name for the device the session is started on.
+    device: Option<String>,
+
+    /// Keep the session in a cookie instead of returning its token, for browsers.
+    #[serde(default)]
+    cookie: bool,
+}
+
+/// Route handler for completing an email login.
+///
+/// Uses up the token from a login link and starts a session.
+async fn complete_email_auth(
+    req: HttpRequest,
+    form: web::Form<EmailVerifyForm>,
+    pool: web::Data<Pool>,
+    session_config: web::Data<SessionConfig>,
+) -> ActixResult<HttpResponse> {
+    if form.cookie && session_config.cookie.is_none() {
+        return Ok(cookie_sessions_disabled());
+    }
+
+    let use_cookie = form.cookie;
+    let response_config = session_config.clone();
+    let (device_label, user_agent) = device_details(&req, form.device.as_ref());
+
+    let address = match consume_email_token(&req, &pool, form.token.clone()).await {
+        Ok(address) => address,
+        Err(response) => return Ok(response),
+    };
+
+    let pool_clone = pool.clone();
+
+    db::execute(
+        &pool,
+        LookupIdentiy {
+            kind: IdentityKind::Email,
+            identifier: address,
+        },
+    )
+    .and_then(move |ident| {
+        db::execute(
+            &pool_clone,
+            StartSession {
+                person_id: ident.person_id,
+                config: session_config.get_ref().clone(),
+                device_label,
+                user_agent,
+            },
+        )
+    })
+    .then(move |res| async move {
+        match res {
+            Ok(session) => {
+                info!(
+                    "Successfully verified identity for person {}",
+                    session.person_id
+                );
+
+                Ok(session_started(&req, session, use_cookie, &response_config))
+            }
+            Err(e) => {
+                error!("Failed to start session! Error: {}", e);
+
+                let response = ApiResponse::<()>::from(None)
+                    .with_status(ResponseStatus::Error)
+                    .add_message("Internal server error".into());
+
+                Ok(HttpResponse::InternalServerError().json(response))
+            }
+        }
+    })
+    .await
+}
+
+/// Use up the token from an emailed login link.
+///
+/// Returns the verified address, or the response to send if the token wasn't accepted.
+async fn consume_email_token(
+    req: &HttpRequest,
+    pool: &Pool,
+    token: String,
+) -> std::result::Result<String, HttpResponse> {
+    match db::execute(pool, ConsumeEmailChallenge { token }).await {
+        Ok(Some(address)) => {
+            info!("Email address {} verified!", address);
+
+            audit::record(
+                req,
+                Event::new(AuthEventKind::VerificationSucceeded)
+                    .identity(IdentityKind::Email, address.clone()),
+            );
+
+            Ok(address)
+        }
+        Ok(None) => {
+            warn!("Invalid or expired email login token submitted!");
+
+            audit::record(
+                req,
+                Event::new(AuthEventKind::VerificationFailed)
+                    .detail("Invalid or expired login link".into()),
+            );
+
+            let response = ApiResponse::<()>::from(None)
+                .with_status(ResponseStatus::Fail)
+                .add_message("Invalid or expired login link".into());
+
+            Err(HttpResponse::Forbidden().json(response))
+        }
+        Err(e) => {
+            error!("Failed to check email challenge! Error: {}", e);
+
+            let response = ApiResponse::<()>::from(None)
+                .with_status(ResponseStatus::Error)
+                .add_message("Internal server error".into());
+
+            Err(HttpResponse::InternalServerError().json(response))
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename = "authorization")]
+struct OidcAuthorization {
+    /// The provider page to send the person to.
+    url: String,
+}
+
+/// Route handler for starting a login with the OpenID Connect provider.
+///
+/// Returns the provider URL to send the person to. The provider sends them back to
+/// the configured redirect URI with the `code` and `state` for `POST /auth/oidc/verify`.
+async fn begin_oidc_auth(
+    req: HttpRequest,
+    pool: web::Data<Pool>,
+    oidc: web::Data<oidc::Provider>,
+    throttle: web::Data<Throttle>,
+) -> ActixResult<HttpResponse> {
+    let client = match oidc.get_ref() {
+        Some(client) => client,
+        None => return Ok(oidc_disabled()),
+    };
+
+    throttle
+        .attempt(vec![(
+            format!("start:ip:{}", throttle.client_ip(&req)),
+            throttle.config().start_per_ip,
+        )])
+        .await?;
+
+    let login = oidc::PendingLogin::new();
+    let lifetime = client.config().lifetime;
+
+    let res = oidc::authorization_url(client, login)
+        .and_then(|(url, login)| {
+            db::execute(
+                &pool,
+                CreateOidcLogin {
+                    state: login.state,
+                    nonce: login.nonce,
+                    code_verifier: login.code_verifier,
+                    lifetime,
+                },
+            )
+            .map_ok(|_| url)
+        })
+        .await;
+
+    match res {
+        Ok(url) => {
+            audit::record(
+                &req,
+                Event::new(AuthEventKind::VerificationStarted).detail("OpenID Connect".into()),
+            );
+
+            Ok(HttpResponse::Ok().json(ApiResponse::success(OidcAuthorization { url })))
+        }
+        Err(e) => {
+            error!("Failed to start OpenID Connect login! Error: {}", e);
+
+            let response = ApiResponse::<()>::from(None)
+                .with_status(ResponseStatus::Error)
+                .add_message("Internal server error".into());
+
+            Ok(HttpResponse::InternalServerError().json(response))
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct OidcVerifyForm {
+    /// The authorization code the provider returned.
+    code: String,
+
+    /// The state the provider returned.
+    state: String,
+
+    /// An optional, person-chosen name for the device the session is started on.
+    device: Option<String>,
+
+    /// Keep the session in a cookie instead of returning its token, for browsers.
+    #[serde(default)]
+    cookie: bool,
+}
+
+/// Route handler for completing a login with the OpenID Connect provider.
+///
+/// Redeems the authorization code and starts a session, creating a new person
+/// if the provider's subject hasn't been seen before.
+async fn complete_oidc_auth(
+    req: HttpRequest,
+    form: web::Form<OidcVerifyForm>,
+    pool: web::Data<Pool>,
+    oidc: web::Data<oidc::Provider>,
+    session_config: web::Data<SessionConfig>,
+) -> ActixResult<HttpResponse> {
+    if form.cookie && session_config.cookie.is_none() {
+        return Ok(cookie_sessions_disabled());
+    }
+
+    let use_cookie = form.cookie;
+    let response_config = session_config.clone();
+    let (device_label, user_agent) = device_details(&req, form.device.as_ref());
+
+    let identifier =
+        match redeem_oidc_code(&req, &pool, &oidc, form.code.clone(), form.state.clone()).await {
+            Ok(identifier) => identifier,
+            Err(response) => return Ok(response),
+        };
+
+    let pool_clone = pool.clone();
+
+    db::execute(
+        &pool,
+        LookupIdentiy {
+            kind: IdentityKind::Oidc,
+            identifier,
+        },
+    )
+    .and_then(move |ident| {
+        db::execute(
+            &pool_clone,
+            StartSession {
+                person_id: ident.person_id,
+                config: session_config.get_ref().clone(),
+                device_label,
+                user_agent,
+            },
+        )
+    })
+    .then(move |res| async move {
+        match res {
+            Ok(session) => {
+                info!(
+                    "Successfully verified identity for person {}",
+                    session.person_id
+                );
+
+                Ok(session_started(&req, session, use_cookie, &response_config))
+            }
+            Err(e) => {
+                error!("Failed to start session! Error: {}", e);
+
+                let response = ApiResponse::<()>::from(None)
+                    .with_status(ResponseStatus::Error)
+                    .add_message("Internal server error".into());
+
+                Ok(HttpResponse::InternalServerError().json(response))
+            }
+        }
+    })
+    .await
+}
+
+/// Use up a pending OIDC login and redeem the code the provider returned for it.
+///
+/// Returns the verified identifier, or the response to send if the login wasn't accepted.
+async fn redeem_oidc_code(
+    req: &HttpRequest,
+    pool: &Pool,
+    oidc: &oidc::Provider,
+    code: String,
+    state: String,
+) -> std::result::Result<String, HttpResponse> {
+    let client = match oidc {
+        Some(client) => client,
+        None => return Err(oidc_disabled()),
+    };
+
+    let login = match db::execute(pool, ConsumeOidcLogin { state }).await {
+        Ok(Some(login)) => login,
+        Ok(None) => {
+            warn!("Invalid or expired OpenID Connect state submitted!");
+
+            audit::record(
+                req,
+                Event::new(AuthEventKind::VerificationFailed)
+                    .detail("Invalid or expired OpenID Connect login".into()),
+            );
+
+            let response = ApiResponse::<()>::from(None)
+                .with_status(ResponseStatus::Fail)
+                .add_message("Invalid or expired login".into());
+
+            return Err(HttpResponse::Forbidden().json(response));
+        }
+        Err(e) => {
+            error!("Failed to check OpenID Connect login! Error: {}", e);
+
+            let response = ApiResponse::<()>::from(None)
+                .with_status(ResponseStatus::Error)
+                .add_message("Internal server error".into());
+
+            return Err(HttpResponse::InternalServerError().json(response));
+        }
+    };
+
+    match oidc::exchange(client, code, login.code_verifier, login.nonce).await {
+        Ok(claims) => {
+            info!("OpenID Connect subject {} verified!", claims.sub);
+
+            audit::record(
+                req,
+                Event::new(AuthEventKind::VerificationSucceeded)
+                    .identity(IdentityKind::Oidc, claims.identifier()),
+            );
+
+            Ok(claims.identifier())
+        }
+        Err(e) => {
+            warn!("Failed to redeem OpenID Connect code! Error: {}", e);
+
+            audit::record(
+                req,
+                Event::new(AuthEventKind::VerificationFailed)
+                    .detail(format!("OpenID Connect: {}", e)),
+            );
+
+            let response = ApiResponse::<()>::from(None)
+                .with_status(ResponseStatus::Fail)
+                .add_message("Unable to verify that login".into());
+
+            Err(HttpResponse::Forbidden().json(response))
+        }
+    }
+}
+
+fn oidc_disabled() -> HttpResponse {
+    let response = ApiResponse::<()>::from(None)
+        .with_status(ResponseStatus::Fail)
+        .add_message("OpenID Connect login is not enabled".into());
+
+    HttpResponse::NotFound().json(response)
+}
+
+/// The response for a newly started session.
+///
+/// If the client asked for a cookie, the session token is set in an HttpOnly
+/// cookie instead of being returned in the body.
+fn session_started(
+    req: &HttpRequest,
+    mut session: StartedSession,
+    use_cookie: bool,
+    config: &SessionConfig,
+) -> HttpResponse {
+    audit::record(
+        req,
+        Event::new(AuthEventKind::SessionStarted).person(session.person_id),
+    );
+
+    let mut builder = HttpResponse::Ok();
+
+    if use_cookie {
+        if let Some(session_token) = session.token.take() {
+            cookies::set_session(&mut builder, config, &session_token);
+        }
+    }
+
+    builder.json(ApiResponse::success(session))
+}
+
+fn cookie_sessions_disabled() -> HttpResponse {
+    let response = ApiResponse::<()>::from(None)
+        .with_status(ResponseStatus::Fail)
+        .add_message("Cookie sessions are not enabled".into());
+
+    HttpResponse::BadRequest().json(response)
+}
+
+/// Route handler for ending the current session.
+async fn logout(
+    req: HttpRequest,
+    person: models::Person,
+    pool: web::Data<Pool>,
+    session_config: web::Data<SessionConfig>,
+) -> ActixResult<HttpResponse> {
+    let session_id = models::session_token(&req)?;
+
+    db::execute(
+        &pool,
+        EndSession {
+            session_id,
+            person_id: person.id,
+        },
+    )
+    .then(move |res| async move {
+        match res {
+            Ok(_) => {
+                audit::record(
+                    &req,
+                    Event::new(AuthEventKind::SessionRevoked)
+                        .person(person.id)
+                        .detail("Logged out".into()),
+                );
+
+                let response = ApiResponse::<()>::from(None).add_message("Logged out".into());
+                let mut builder = HttpResponse::Ok();
+
+                if let Some(ref cookie_config) = session_config.cookie {
+                    cookies::clear_session(&mut builder, cookie_config);
+                }
+
+                Ok(builder.json(response))
+            }
+            Err(e) => {
+                error!("Unable to log out person {}! Error: {}", person.id, e);
+
+                let response = ApiResponse::<()>::from(None)
+                    .with_status(ResponseStatus::Error)
+                    .add_message("An unexpected error occurred".into());
+
+                Ok(HttpResponse::InternalServerError().json(response))
+            }
+        }
+    })
+    .await
+}
+
+/// Route handler for listing the current person's active sessions.
+async fn get_sessions(
+    req: HttpRequest,
+    person: models::Person,
+    pool: web::Data<Pool>,
+    session_config: web::Data<SessionConfig>,
+) -> ActixResult<HttpResponse> {
+    #[derive(Serialize)]
+    #[serde(rename = "sessions")]
+    struct Sessions(Vec<SessionSummary>);
+
+    let current_session_id = models::session_token(&req)?;
+
+    db::execute(
+        &pool,
+        GetSessions {
+            person_id: person.id,
+            current_session_id,
+            max_lifetime: session_config.max_lifetime,
+        },
+    )
+    .then(move |res| async move {
+        match res {
+            Ok(sessions) => Ok(HttpResponse::Ok().json(ApiResponse::success(Sessions(sessions)))),
+            Err(e) => {
+                error!(
+                    "Unable to list sessions for person {}! Error: {}",
+                    person.id, e
+                );
+
+                let unexpected_error = ApiResponse::<()>::from(None)
+                    .with_status(ResponseStatus::Error)
+                    .add_message("An unexpected error occurred".into());
+
+                Ok(HttpResponse::InternalServerError().json(unexpected_error))
+            }
+        }
+    })
+    .await
+}
+
+#[derive(Deserialize)]
+struct SessionIdForm {
+    id: i32,
+}
+
+/// Route handler for revoking one of the current person's sessions.
+async fn revoke_session(
+    req: HttpRequest,
+    person: models::Person,
+    info: web::Path<SessionIdForm>,
+    pool: web::Data<Pool>,
+) -> ActixResult<HttpResponse> {
+    let public_id = info.id;
+
+    db::execute(
+        &pool,
+        RevokeSession {
+            public_id,
+            person_id: person.id,
+        },
+    )
+    .then(move |res| async move {
+        match res {
+            Ok(0) => {
+                let not_found = ApiResponse::<()>::from(None)
+                    .with_status(ResponseStatus::Fail)
+                    .add_message("Could not find that session".into());
+
+                Ok(HttpResponse::NotFound().json(not_found))
+            }
+            Ok(_) => {
+                audit::record(
+                    &req,
+                    Event::new(AuthEventKind::SessionRevoked)
+                        .person(person.id)
+                        .detail(format!("Revoked session {}", public_id)),
+                );
+
+                let revoked = ApiResponse::<()>::from(None).add_message("Revoked".into());
+
+                Ok(HttpResponse::Ok().json(revoked))
+            }
+            Err(e) => {
+                error!(
+                    "Unable to revoke session for person {}! Error: {}",
+                    person.id, e
+                );
+
+                let unexpected_error = ApiResponse::<()>::from(None)
+                    .with_status(ResponseStatus::Error)
+                    .add_message("An unexpected error occurred".into());
+
+                Ok(HttpResponse::InternalServerError().json(unexpected_error))
+            }
+        }
+    })
+    .await
+}
+
+/// Route handler for logging out everywhere else.
+///
+/// Revokes every one of the current person's sessions except the one making the request.
+async fn revoke_other_sessions(
+    req: HttpRequest,
+    person: models::Person,
+    pool: web::Data<Pool>,
+) -> ActixResult<HttpResponse> {
+    let current_session_id = models::session_token(&req)?;
+
+    db::execute(
+        &pool,
+        RevokeOtherSessions {
+            person_id: person.id,
+            current_session_id,
+        },
+    )
+    .then(move |res| async move {
+        match res {
+            Ok(n) => {
+                info!("Person {} revoked {} other sessions", person.id, n);
+
+                audit::record(
+                    &req,
+                    Event::new(AuthEventKind::SessionRevoked)
+                        .person(person.id)
+                        .detail(format!("Revoked {} other sessions", n)),
+                );
+
+                let revoked = ApiResponse::<()>::from(None)
+                    .add_message(format!("Revoked {} other sessions", n));
+
+                Ok(HttpResponse::Ok().json(revoked))
+            }
+            Err(e) => {
+                error!(
+                    "Unable to revoke sessions for person {}! Error: {}",
+                    person.id, e
+                );
+
+                let unexpected_error = ApiResponse::<()>::from(None)
+                    .with_status(ResponseStatus::Error)
+                    .add_message("An unexpected error occurred".into());
+
+                Ok(HttpResponse::InternalServerError().json(unexpected_error))
+            }
+        }
+    })
+    .await
+}
+
+#[derive(Deserialize)]
+struct ApiTokenForm {
+    /// A name to help the person remember what the token is for.
+    name: String,
+
+    /// Space separated list of scopes to grant, e.g. `drinks:read drinks:write`.
+    scopes: String,
+}
+
+/// Route handler for minting a new personal API token.
+///
+/// The plaintext token is only ever returned in this response.
+async fn create_api_token(
+    person: models::Person,
+    form: web::Form<ApiTokenForm>,
+    pool: web::Data<Pool>,
+) -> ActixResult<HttpResponse> {
+    let name = form.name.trim();
+
+    if name.is_empty() || name.chars().count() > 128 {
+        let response = ApiResponse::<()>::from(None)
+            .with_status(ResponseStatus::Fail)
+            .add_message("Token names must be between 1 and 128 characters".into());
+
+        return Ok(HttpResponse::BadRequest().json(response));
+    }
+
+    let scopes = match auth::parse_scopes(&form.scopes) {
+        Ok(ref scopes) if scopes.is_empty() => {
+            let response = ApiResponse::<()>::from(None)
+                .with_status(ResponseStatus::Fail)
+                .add_message("At least one scope is required".into());
+
+            return Ok(HttpResponse::BadRequest().json(response));
+        }
+        Ok(scopes) => scopes,
+        Err(message) => {
+            let response = ApiResponse::<()>::from(None)
+                .with_status(ResponseStatus::Fail)
+                .add_message(message);
+
+            return Ok(HttpResponse::BadRequest().json(response));
+        }
+    };
+
+    db::execute(
+        &pool,
+        CreateApiToken {
+            person_id: person.id,
+            name: name.to_string(),
+            scopes,
+        },
+    )
+    .then(move |res| async move {
+        match res {
+            Ok(token) => Ok(HttpResponse::Ok().json(ApiResponse::success(token))),
+            Err(e) => {
+                error!(
+                    "Unable to create API token for person {}! Error: {}",
+                    person.id, e
+                );
+
+                let unexpected_error = ApiResponse::<()>::from(None)
+                    .with_status(ResponseStatus::Error)
+                    .add_message("An unexpected error occurred".into());
+
+                Ok(HttpResponse::InternalServerError().json(unexpected_error))
+            }
+        }
+    })
+    .await
+}
+
+/// Route handler for listing the current person's API tokens.
+async fn get_api_tokens(
+    person: models::Person,
+    pool: web::Data<Pool>,
+) -> ActixResult<HttpResponse> {
+    #[derive(Serialize)]
+    #[serde(rename = "tokens")]
+    struct Tokens(Vec<models::ApiToken>);
+
+    db::execute(
+        &pool,
+        GetApiTokens {
+            person_id: person.id,
+        },
+    )
+    .then(move |res| async move {
+        match res {
+            Ok(tokens) => Ok(HttpResponse::Ok().json(ApiResponse::success(Tokens(tokens)))),
+            Err(e) => {
+                error!(
+                    "Unable to list API tokens for person {}! Error: {}",
+                    person.id, e
+                );
+
+                let unexpected_error = ApiResponse::<()>::from(None)
+                    .with_status(ResponseStatus::Error)
+                    .add_message("An unexpected error occurred".into());
+
+                Ok(HttpResponse::InternalServerError().json(unexpected_error))
+            }
+        }
+    })
+    .await
+}
+
+#[derive(Deserialize)]
+struct ApiTokenIdForm {
+    id: i32,
+}
+
+/// Route handler for revoking one of the current person's API tokens.
+async fn delete_api_token(
+    person: models::Person,
+    info: web::Path<ApiTokenIdForm>,
+    pool: web::Data<Pool>,
+) -> ActixResult<HttpResponse> {
+    db::execute(
+        &pool,
+        DeleteApiToken {
+            token_id: info.id,
+            person_id: person.id,
+        },
+    )
+    .then(move |res| async move {
+        match res {
+            Ok(0) => {
+                let not_found = ApiResponse::<()>::from(None)
+                    .with_status(ResponseStatus::Fail)
+                    .add_message("Could not find that token".into());
+
+                Ok(HttpResponse::NotFound().json(not_found))
+            }
+            Ok(_) => {
+                let revoked = ApiResponse::<()>::from(None).add_message("Revoked".into());
+
+                Ok(HttpResponse::Ok().json(revoked))
+            }
+            Err(e) => {
+                error!(
+                    "Unable to revoke API token for person {}! Error: {}",
+                    person.id, e
+                );
+
+                let unexpected_error = ApiResponse::<()>::from(None)
+                    .with_status(ResponseStatus::Error)
+                    .add_message("An unexpected error occurred".into());
+
+                Ok(HttpResponse::InternalServerError().json(unexpected_error))
+            }
+        }
+    })
+    .await
+}
+
+/// Route handler for listing the identities the current person can log in with.
+async fn get_identities(
+    person: models::Person,
+    pool: web::Data<Pool>,
+) -> ActixResult<HttpResponse> {
+    #[derive(Serialize)]
+    #[serde(rename = "identities")]
+    struct Identities(Vec<models::Identity>);
+
+    db::execute(
+        &pool,
+        GetIdentities {
+            person_id: person.id,
+        },
+    )
+    .then(move |res| async move {
+        match res {
+            Ok(identities) => {
+                Ok(HttpResponse::Ok().json(ApiResponse::success(Identities(identities))))
+            }
+            Err(e) => {
+                error!(
+                    "Unable to list identities for person {}! Error: {}",
+                    person.id, e
+                );
+
+                let unexpected_error = ApiResponse::<()>::from(None)
+                    .with_status(ResponseStatus::Error)
+                    .add_message("An unexpected error occurred".into());
+
+                Ok(HttpResponse::InternalServerError().json(unexpected_error))
+            }
+        }
+    })
+    .await
+}
+
+#[derive(Deserialize)]
+struct LinkPhoneForm {
+    country_code: u16,
+    phone_number: String,
+
+    /// The code sent by `POST /auth`.
+    code: String,
+
+    /// Merge the account that already owns this phone number into this one.
+    #[serde(default)]
+    merge: bool,
+}
+
+/// Route handler for linking another phone number to the current person.
+async fn link_phone(
+    req: HttpRequest,
+    person: models::Person,
+    form: web::Form<LinkPhoneForm>,
+    pool: web::Data<Pool>,
+    store: web::Data<photos::Store>,
+    verifier: web::Data<verification::Provider>,
+    throttle: web::Data<Throttle>,
+) -> ActixResult<HttpResponse> {
+    let phone = match PhoneNumber::parse(form.country_code, &form.phone_number) {
+        Ok(phone) => phone,
+        Err(e) => {
+            let response = ApiResponse::<()>::from(None)
+                .with_status(ResponseStatus::Fail)
+                .add_message(format!("Invalid phone number: {}", e));
+
+            return Ok(HttpResponse::BadRequest().json(response));
+        }
+    };
+
+    if let Err(response) =
+        verify_phone_code(&req, &verifier, &throttle, &phone, form.code.clone()).await
+    {
+        return Ok(response);
+    }
+
+    Ok(attach_identity(
+        &pool,
+        &store,
+        person.id,
+        IdentityKind::Phone,
+        phone.e164(),
+        form.merge,
+    )
+    .await)
+}
+
+#[derive(Deserialize)]
+struct LinkEmailForm {
+    /// The token from a login link sent by `POST /auth/email`.
+    token: String,
+
+    /// Merge the account that already owns this email address into this one.
+    #[serde(default)]
+    merge: bool,
+}
+
+/// Route handler for linking another email address to the current person.
+async fn link_email(
+    req: HttpRequest,
+    person: models::Person,
+    form: web::Form<LinkEmailForm>,
+    pool: web::Data<Pool>,
+    store: web::Data<photos::Store>,
+) -> ActixResult<HttpResponse> {
+    let address = match consume_email_token(&req, &pool, form.token.clone()).await {
+        Ok(address) => address,
+        Err(response) => return Ok(response),
+    };
+
+    Ok(attach_identity(
+        &pool,
+        &store,
+        person.id,
+        IdentityKind::Email,
+        address,
+        form.merge,
+    )
+    .await)
+}
+
+#[derive(Deserialize)]
+struct LinkOidcForm {
+    /// The authorization code the provider returned for a login started with
+    /// `GET /auth/oidc/authorize`.
+    code: String,
+
+    /// The state the provider returned.
+    state: String,
+
+    /// Merge the account that already owns this login into this one.
+    #[serde(default)]
+    merge: bool,
+}
+
+/// Route handler for linking an OpenID Connect login to the current person.
+async fn link_oidc(
+    req: HttpRequest,
+    person: models::Person,
+    form: web::Form<LinkOidcForm>,
+    pool: web::Data<Pool>,
+    store: web::Data<photos::Store>,
+    oidc: web::Data<oidc::Provider>,
+) -> ActixResult<HttpResponse> {
+    let identifier =
+        match redeem_oidc_code(&req, &pool, &oidc, form.code.clone(), form.state.clone()).await {
+            Ok(identifier) => identifier,
+            Err(response) => return Ok(response),
+        };
+
+    Ok(attach_identity(
+        &pool,
+        &store,
+        person.id,
+        IdentityKind::Oidc,
+        identifier,
+        form.merge,
+    )
+    .await)
+}
+
+/// Attach a verified identity to a person, and describe what happened.
+async fn attach_identity(
+    pool: &Pool,
+    store: &photos::Store,
+    person_id: i32,
+    kind: IdentityKind,
+    identifier: String,
+    merge: bool,
+) -> HttpResponse {
+    let outcome = db::execute(
+        pool,
+        AttachIdentity {
+            person_id,
+            kind,
+            identifier,
+            merge,
+        },
+    )
+    .await;
+
+    match outcome {
+        Ok(AttachOutcome::Attached(identity)) => {
+            HttpResponse::Ok().json(ApiResponse::success(identity))
+        }
+        Ok(AttachOutcome::AlreadyAttached(identity)) => HttpResponse::Ok().json(
+            ApiResponse::success(identity).add_message("That identity is already linked".into()),
+        ),
+        Ok(AttachOutcome::Conflict) => {
+            let response = ApiResponse::<()>::from(None)
+                .with_status(ResponseStatus::Fail)
+                .add_message(format!(
+                    "That {} belongs to another account. Submit it again with merge=true \
+                     to merge that account into this one.",
+                    match kind {
+                        IdentityKind::Phone => "phone number",
+                        IdentityKind::Email => "email address",
+                        IdentityKind::Oidc => "login",
+                    }
+                ));
+
+            HttpResponse::Conflict().json(response)
+        }
+        Ok(AttachOutcome::Merged(report)) => {
+            photos::remove(store, report.dropped_avatar_key.iter().cloned().collect()).await;
+
+            let duplicates = report.possible_duplicate_drinks.len();
+            let dropped_handle = report.dropped_handle.clone();
+            let dropped_role = report.dropped_role.clone();
+            let mut response = ApiResponse::success(report).add_message("Accounts merged".into());
+
+            if duplicates > 0 {
+                response = response.add_message(format!(
+                    "{} of the merged drinks may be duplicates",
+                    duplicates
+                ));
+            }
+
+            if let Some(handle) = dropped_handle {
+                response = response.add_message(format!(
+                    "The merged account's handle @{} is no longer in use",
+                    handle
+                ));
+            }
+
+            if let Some(role) = dropped_role {
+                response =
+                    response.add_message(format!("The merged account's {} role wasn't kept", role));
+            }
+
+            HttpResponse::Ok().json(response)
+        }
+        Err(e) => {
+            error!(
+                "Unable to attach identity to person {}! Error: {}",
+                person_id, e
+            );
+
+            let response = ApiResponse::<()>::from(None)
+                .with_status(ResponseStatus::Error)
+                .add_message("Internal server error".into());
+
+            HttpResponse::InternalServerError().json(response)
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct IdentityPathForm {
+    kind: IdentityKind,
+    identifier: String,
+}
+
+/// Route handler for unlinking one of the current person's identities.
+///
+/// A person's last identity can't be removed, since they couldn't log in again.
+async fn unlink_identity(
+    person: models::Person,
+    info: web::Path<IdentityPathForm>,
+    pool: web::Data<Pool>,
+) -> ActixResult<HttpResponse> {
+    db::execute(
+        &pool,
+        DetachIdentity {
+            person_id: person.id,
+            kind: info.kind,
+            identifier: info.identifier.clone(),
+        },
+    )
+    .then(move |res| async move {
+        match res {
+            Ok(DetachOutcome::Detached) => {
+                let response = ApiResponse::<()>::from(None).add_message("Unlinked".into());
+
+                Ok(HttpResponse::Ok().json(response))
+            }
+            Ok(DetachOutcome::NotFound) => {
+                let not_found = ApiResponse::<()>::from(None)
+                    .with_status(ResponseStatus::Fail)
+                    .add_message("Could not find that identity".into());
+
+                Ok(HttpResponse::NotFound().json(not_found))
+            }
+            Ok(DetachOutcome::LastIdentity) => {
+                let response = ApiResponse::<()>::from(None)
+                    .with_status(ResponseStatus::Fail)
+                    .add_message("You can't remove the only way you have to log in".into());
+
+                Ok(HttpResponse::Conflict().json(response))
+            }
+            Err(e) => {
+                error!(
+                    "Unable to unlink identity for person {}! Error: {}",
+                    person.id, e
+                );
+
+                let unexpected_error = ApiResponse::<()>::from(None)
+                    .with_status(ResponseStatus::Error)
+                    .add_message("An unexpected error occurred".into());
+
+                Ok(HttpResponse::InternalServerError().json(unexpected_error))
+            }
+        }
+    })
+    .await
+}
+
+/*********************************************/
+/*  Profiles                                 */
+/*********************************************/
+
+/// Route handler for the current person's own profile and preferences.
+async fn get_me(person: models::Person) -> ActixResult<HttpResponse> {
+    Ok(HttpResponse::Ok().json(ApiResponse::success(person)))
+}
+
+#[derive(Deserialize)]
+struct ProfileForm {
+    display_name: Option<String>,
+    handle: Option<String>,
+    bio: Option<String>,
+    avatar_url: Option<String>,
+    timezone: Option<String>,
+    units: Option<String>,
+}
+
+/// Route handler for changing the current person's profile.
+///
+/// Only the submitted fields are changed; submitting an empty value clears an
+/// optional field.
+async fn update_me(
+    person: models::Person,
+    form: web::Form<ProfileForm>,
+    pool: web::Data<Pool>,
+    store: web::Data<photos::Store>,
+) -> ActixResult<HttpResponse> {
+    let changes = match profile_changes(&form) {
+        Ok(changes) => changes,
+        Err(message) => {
+            let response = ApiResponse::<()>::from(None)
+                .with_status(ResponseStatus::Fail)
+                .add_message(message);
+
+            return Ok(HttpResponse::BadRequest().json(response));
+        }
+    };
+
+    if changes.is_empty() {
+        return Ok(HttpResponse::Ok().json(ApiResponse::success(person)));
+    }
+
+    let person_id = person.id;
+
+    db::execute(&pool, UpdateProfile { person_id, changes })
+        .then(move |res| async move {
+            match res {
+                Ok(ProfileOutcome::Updated(person, replaced)) => {
+                    photos::remove(&store, replaced.into_iter().collect()).await;
+
+                    Ok(HttpResponse::Ok().json(ApiResponse::success(person)))
+                }
+                Ok(ProfileOutcome::HandleTaken) => {
+                    let response = ApiResponse::<()>::from(None)
+                        .with_status(ResponseStatus::Fail)
+                        .add_message("That handle is already taken".into());
+
+                    Ok(HttpResponse::Conflict().json(response))
+                }
+                Err(e) => {
+                    error!(
+                        "Unable to update the profile of person {}! Error: {}",
+                        person_id, e
+                    );
+
+                    let unexpected_error = ApiResponse::<()>::from(None)
+                        .with_status(ResponseStatus::Error)
+                        .add_message("An unexpected error occurred".into());
+
+                    Ok(HttpResponse::InternalServerError().json(unexpected_error))
+                }
+            }
+        })
+        .await
+}
+
+/// Check the submitted profile fields, describing the first problem found.
+fn profile_changes(form: &ProfileForm) -> std::result::Result<models::ProfileChanges, String> {
+    let required = |value: &Option<String>,
+                    check: fn(&str) -> std::result::Result<String, String>| {
+        value.as_ref().map(|value| check(value)).transpose()
+    };
+
+    Ok(models::ProfileChanges {
+        display_name: clearable(&form.display_name, profile::display_name)?,
+        handle: clearable(&form.handle, profile::handle)?,
+        bio: clearable(&form.bio, profile::bio)?,
+        avatar_url: clearable(&form.avatar_url, profile::avatar_url)?,
+        timezone: required(&form.timezone, profile::timezone)?,
+        units: required(&form.units, |units| {
+            profile::Units::from_str(units).map(|units| units.to_string())
+        })?,
+        avatar_key: form.avatar_url.as_ref().map(|_| None),
+    })
+}
+
+/// Route handler for uploading an avatar for the current person.
+///
+/// Expects a `multipart/form-data` body with a JPEG, PNG, GIF or WebP image of at
+/// most 10 MiB in its `avatar` field. The image is cropped square and re-encoded
+/// as JPEG, and `avatar_url` is pointed at it. Responds with the person.
+async fn upload_avatar(
+    person: models::Person,
+    mut payload: Multipart,
+    pool: web::Data<Pool>,
+    store: web::Data<photos::Store>,
+) -> ActixResult<HttpResponse> {
+    let upload = match read_upload(&mut payload, "avatar").await {
+        Ok(upload) => upload,
+        Err(response) => return Ok(response),
+    };
+
+    let processed = match upload {
+        Some(bytes) => photos::process_upload(bytes, photos::process_avatar).await,
+        None => Ok(Err("An avatar is required".to_string())),
+    };
+
+    let avatar = match processed {
+        Ok(Ok(avatar)) => avatar,
+        Ok(Err(message)) => {
+            let response = ApiResponse::<()>::from(None)
+                .with_status(ResponseStatus::Fail)
+                .add_message(message);
+
+            return Ok(HttpResponse::BadRequest().json(response));
+        }
+        Err(e) => return Ok(avatar_error(person.id, e)),
+    };
+
+    let avatar_key = photos::new_avatar_key();
+
+    if let Err(e) = photos::put(&store, vec![(avatar_key.clone(), avatar)]).await {
+        return Ok(avatar_error(person.id, e));
+    }
+
+    let saved = db::execute(
+        &pool,
+        SetAvatar {
+            person_id: person.id,
+            avatar_key: avatar_key.clone(),
+        },
+    )
+    .await;
+
+    match saved {
+        Ok((person, replaced)) => {
+            photos::remove(&store, replaced.into_iter().collect()).await;
+
+            Ok(HttpResponse::Ok().json(ApiResponse::success(person)))
+        }
+        Err(e) => {
+            photos::remove(&store, vec![avatar_key]).await;
+
+            Ok(avatar_error(person.id, e))
+        }
+    }
+}
+
+fn avatar_error(person_id: i32, e: error::Error) -> HttpResponse {
+    error!(
+        "Unable to save the avatar of person {}! Error: {}",
+        person_id, e
+    );
+
+    let unexpected_error = ApiResponse::<()>::from(None)
+        .with_status(ResponseStatus::Error)
+        .add_message("An unexpected error occurred".into());
+
+    HttpResponse::InternalServerError().json(unexpected_error)
+}
+
+#[derive(Deserialize)]
+struct HandleForm {
+    handle: String,
+}
+
+/// Route handler for looking at someone's public profile.
+async fn get_profile(
+    _viewer: models::Person,
+    info: web::Path<HandleForm>,
+    pool: web::Data<Pool>,
+) -> ActixResult<HttpResponse> {
+    let not_found = || {
+        let response = ApiResponse::<()>::from(None)
+            .with_status(ResponseStatus::Fail)
+            .add_message("Could not find that person".into());
+
+        HttpResponse::NotFound().json(response)
+    };
+
+    // A handle that could never have been chosen can't be found either
+    let handle = match profile::handle(&info.handle) {
+        Ok(handle) => handle,
+        Err(_) => return Ok(not_found()),
+    };
+
+    db::execute(&pool, GetPersonByHandle { handle })
+        .then(move |res| async move {
+            match res {
+                Ok(Some(person)) => {
+                    Ok(HttpResponse::Ok().json(ApiResponse::success(person.public_profile())))
+                }
+                Ok(None) => Ok(not_found()),
+                Err(e) => {
+                    error!("Unable to look up a profile! Error: {}", e);
+
+                    let unexpected_error = ApiResponse::<()>::from(None)
+                        .with_status(ResponseStatus::Error)
+                        .add_message("An unexpected error occurred".into());
+
+                    Ok(HttpResponse::InternalServerError().json(unexpected_error))
+                }
+            }
+        })
+        .await
+}
+
+/*********************************************/
+/*  Personal data and account deletion       */
+/*********************************************/
+
+#[derive(Deserialize)]
+struct ExportForm {
+    /// `json` (the default) or `csv`.
+    format: Option<String>,
+}
+
+/// Route handler for downloading everything stored about the current person.
+///
+/// The CSV export is a zip archive with one file for each kind of record.
+async fn export_personal_data(
+    person: models::Person,
+    form: web::Query<ExportForm>,
+    pool: web::Data<Pool>,
+) -> ActixResult<HttpResponse> {
+    let format = form.format.clone().unwrap_or("json".into());
+
+    if format != "json" && format != "csv" {
+        let response = ApiResponse::<()>::from(None)
+            .with_status(ResponseStatus::Fail)
+            .add_message("The export format must be json or csv".into());
+
+        return Ok(HttpResponse::BadRequest().json(response));
+    }
+
+    let data = match db::execute(
+        &pool,
+        GetPersonalData {
+            person_id: person.id,
+        },
+    )
+    .await
+    {
+        Ok(data) => data,
+        Err(e) => {
+            error!(
+                "Unable to gather personal data for person {}! Error: {}",
+                person.id, e
+            );
+
+            let unexpected_error = ApiResponse::<()>::from(None)
+                .with_status(ResponseStatus::Error)
+                .add_message("An unexpected error occurred".into());
+
+            return Ok(HttpResponse::InternalServerError().json(unexpected_error));
+        }
+    };
+
+    info!(
+        "Exporting personal data for person {} as {}",
+        person.id, format
+    );
+
+    let filename = format!(
+        "mug-club-{}-{}",
+        person.id,
+        data.exported_at.format("%Y-%m-%d")
+    );
+
+    if format == "csv" {
+        let archive = match export::csv_archive(&data) {
+            Ok(archive) => archive,
+            Err(e) => {
+                error!(
+                    "Unable to archive personal data for person {}! Error: {}",
+                    person.id, e
+                );
+
+                let unexpected_error = ApiResponse::<()>::from(None)
+                    .with_status(ResponseStatus::Error)
+                    .add_message("An unexpected error occurred".into());
+
+                return Ok(HttpResponse::InternalServerError().json(unexpected_error));
+            }
+        };
+
+        return Ok(HttpResponse::Ok()
+            .content_type("application/zip")
+            .header(
+                http::header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{}.zip\"", filename),
+            )
+            .body(archive));
+    }
+
+    Ok(HttpResponse::Ok()
+        .header(
+            http::header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"{}.json\"", filename),
+        )
+        .json(ApiResponse::success(data)))
+}
+
+#[derive(Deserialize)]
+struct ReverifyForm {
+    /// The token from a login link sent to one of the person's email addresses.
+    token: Option<String>,
+
+    /// Or one of the person's phone numbers, with a code sent by `POST /auth`.
+    country_code: Option<u16>,
+    phone_number: Option<String>,
+    code: Option<String>,
+}
+
+/// Make the current person prove again that they control one of their identities.
+///
+/// Returns the response to send if they didn't.
+async fn reverify(
+    req: &HttpRequest,
+    person: &models::Person,
+    form: &ReverifyForm,
+    pool: &Pool,
+    verifier: &verification::Provider,
+    throttle: &Throttle,
+) -> std::result::Result<(), HttpResponse> {
+    let (kind, identifier) = match form {
+        ReverifyForm {
+            token: Some(token), ..
+        } => (
+            IdentityKind::Email,
+            consume_email_token(req, pool, token.clone()).await?,
+        ),
+        ReverifyForm {
+            country_code: Some(country_code),
+            phone_number: Some(phone_number),
+            code: Some(code),
+            ..
+        } => {
+            let phone = match PhoneNumber::parse(*country_code, phone_number) {
+                Ok(phone) => phone,
+                Err(e) => {
+                    let response = ApiResponse::<()>::from(None)
+                        .with_status(ResponseStatus::Fail)
+                        .add_message(format!("Invalid phone number: {}", e));
+
+                    return Err(HttpResponse::BadRequest().json(response));
+                }
+            };
+
+            verify_phone_code(req, verifier, throttle, &phone, code.clone()).await?;
+
+            (IdentityKind::Phone, phone.e164())
+        }
+        _ => {
+            let response = ApiResponse::<()>::from(None)
+                .with_status(ResponseStatus::Fail)
+                .add_message(
+                    "Confirm this with a code sent to your phone or a link sent to your email"
+                        .into(),
+                );
+
+            return Err(HttpResponse::BadRequest().json(response));
+        }
+    };
+
+    let identities = match db::execute(
+        pool,
+        GetIdentities {
+            person_id: person.id,
+        },
+    )
+    .await
+    {
+        Ok(identities) => identities,
+        Err(e) => {
+            error!(
+                "Unable to list identities for person {}! Error: {}",
+                person.id, e
+            );
+
+            let unexpected_error = ApiResponse::<()>::from(None)
+                .with_status(ResponseStatus::Error)
+                .add_message("An unexpected error occurred".into());
+
+            return Err(HttpResponse::InternalServerError().json(unexpected_error));
+        }
+    };
+
+    let owned = identities
+        .iter()
+        .any(|identity| identity.kind == kind.as_str() && identity.identifier == identifier);
+
+    if !owned {
+        warn!(
+            "Person {} tried to reverify with someone else's {} '{}'",
+            person.id,
+            kind.as_str(),
+            identifier
+        );
+
+        let response = ApiResponse::<()>::from(None)
+            .with_status(ResponseStatus::Fail)
+            .add_message("That isn't one of your logins".into());
+
+        return Err(HttpResponse::Forbidden().json(response));
+    }
+
+    Ok(())
+}
+
+/// Route handler for deleting the current person's account.
+///
+/// The account is kept for a grace period during which it can be restored,
+/// and is then purged along with all of its data.
+async fn delete_account(
+    req: HttpRequest,
+    person: models::Person,
+    form: web::Form<ReverifyForm>,
+    pool: web::Data<Pool>,
+    verifier: web::Data<verification::Provider>,
+    throttle: web::Data<Throttle>,
+    deletion_config: web::Data<DeletionConfig>,
+) -> ActixResult<HttpResponse> {
+    if let Err(response) = reverify(&req, &person, &form, &pool, &verifier, &throttle).await {
+        return Ok(response);
+    }
+
+    let person = match db::execute(
+        &pool,
+        RequestDeletion {
+            person_id: person.id,
+        },
+    )
+    .await
+    {
+        Ok(person) => person,
+        Err(e) => {
+            error!(
+                "Unable to request deletion for person {}! Error: {}",
+                person.id, e
+            );
+
+            let unexpected_error = ApiResponse::<()>::from(None)
+                .with_status(ResponseStatus::Error)
+                .add_message("An unexpected error occurred".into());
+
+            return Ok(HttpResponse::InternalServerError().json(unexpected_error));
+        }
+    };
+
+    let requested_at = person.deletion_requested_at.unwrap_or(Utc::now());
+    let purge_on = requested_at + deletion_config.grace;
+
+    info!(
+        "Person {} asked to delete their account, which will be purged after {}",
+        person.id, purge_on
+    );
+
+    let response = ApiResponse::success(person).add_message(format!(
+        "Your account will be deleted on {} unless you restore it before then",
+        purge_on.format("%Y-%m-%d")
+    ));
+
+    Ok(HttpResponse::Ok().json(response))
+}
+
+/// Route handler for cancelling the deletion of the current person's account.
+async fn restore_account(
+    person: models::Person,
+    pool: web::Data<Pool>,
+) -> ActixResult<HttpResponse> {
+    if person.deletion_requested_at.is_none() {
+        let response = ApiResponse::<()>::from(None)
+            .with_status(ResponseStatus::Fail)
+            .add_message("Your account isn't being deleted".into());
+
+        return Ok(HttpResponse::BadRequest().json(response));
+    }
+
+    let person = match db::execute(
+        &pool,
+        CancelDeletion {
+            person_id: person.id,
+        },
+    )
+    .await
+    {
+        Ok(person) => person,
+        Err(e) => {
+            error!(
+                "Unable to cancel deletion for person {}! Error: {}",
+                person.id, e
+            );
+
+            let unexpected_error = ApiResponse::<()>::from(None)
+                .with_status(ResponseStatus::Error)
+                .add_message("An unexpected error occurred".into());
+
+            return Ok(HttpResponse::InternalServerError().json(unexpected_error));
+        }
+    };
 
-        Ok(Ok(verify_status))
-    })
-    .or_else(move |error| async move {
-        match error {
-            Error::AuthyError(e) => {
-                match e {
-                    // If there was an internal error, that the Authy crate has bubbled up.
-                    AuthyError::RequestError(e)
-                    | AuthyError::IoError(e)
-                    | AuthyError::JsonParseError(e) => {
-                        // Something awful happened
-                        warn!(
-                            "Unable to verify code, '{}', submitted for '{}' '{}'! Error: {}",
-                            verification_code_clone_2, full_number_clone_2.0, full_number_clone_2.1, e
-                        );
-
-                        let response = ApiResponse::<()>::from(None)
-                            .with_status(ResponseStatus::Error)
-                            .add_message("Internal server error".into());
-
-                        Ok(Err(
-                            HttpResponse::InternalServerError().json(response),
-                        ))
-                    }
-                    // If the verification code was incorrect
-                    // The Authy crate currently returns this as an Unauthorized API Key error.
-                    AuthyError::UnauthorizedKey(_) => {
-                        warn!(
-                            "Invalid verification code, '{}', submitted for '{}' '{}'!",
-                            verification_code_clone_2, full_number_clone_2.0, full_number_clone_2.1
-                        );
-
-                        let response = ApiResponse::<()>::from(None)
-                            .with_status(ResponseStatus::Fail)
-                            .add_message("Invalid verification code".into());
-
-                        Ok(Err(
-                            HttpResponse::Forbidden().json(response),
-                        ))
-                    }
-                    // If we received some other Authy error response.
-                    e => {
-                        warn!(
-                            "Unexpected authy error during verification, '{}', submitted for '{}' '{}'! Error: {}",
-                            verification_code_clone_2, full_number_clone_2.0, full_number_clone_2.1, e
-                        );
-
-                        let response = ApiResponse::<()>::from(None)
-                            .with_status(ResponseStatus::Fail)
-                            .add_message("Unable to verify the code".into());
-
-                        Ok(Err(
-                            HttpResponse::Forbidden().json(response),
-                        ))
-                    }
-                }
-            },
-            Error::FutureCanceled(_) => {
-                // Something awful happened
-                error!("Blocking for phone::check request was cancelled!");
+    info!("Person {} restored their account", person.id);
 
-                let response = ApiResponse::<()>::from(None)
-                    .with_status(ResponseStatus::Error)
-                    .add_message("Internal server error".into());
+    let response =
+        ApiResponse::success(person).add_message("Your account has been restored".into());
 
-                Ok(Err(HttpResponse::InternalServerError().json(response)))
-            },
-            other_error => {
-                error!("Unexpected error! {}", other_error);
+    Ok(HttpResponse::Ok().json(response))
+}
 
-                let response = ApiResponse::<()>::from(None)
+#[derive(Deserialize)]
+struct SignInsForm {
+    /// Only events older than this one.
+    before: Option<i32>,
+}
+
+/// Route handler for the current person's recent sign-ins and other authentication events.
+async fn get_sign_ins(
+    person: models::Person,
+    form: web::Query<SignInsForm>,
+    pool: web::Data<Pool>,
+) -> ActixResult<HttpResponse> {
+    db::execute(
+        &pool,
+        GetAuthEvents {
+            person_id: Some(person.id),
+            identifier: None,
+            kind: None,
+            before: form.before,
+            limit: 50,
+        },
+    )
+    .then(move |res| async move {
+        match res {
+            Ok(events) => Ok(HttpResponse::Ok().json(ApiResponse::success(AuthEvents(events)))),
+            Err(e) => {
+                error!(
+                    "Unable to list sign-ins for person {}! Error: {}",
+                    person.id, e
+                );
+
+                let unexpected_error = ApiResponse::<()>::from(None)
                     .with_status(ResponseStatus::Error)
-                    .add_message("Internal server error".into());
+                    .add_message("An unexpected error occurred".into());
 
-                Ok(Err(HttpResponse::InternalServerError().json(response)))
+                Ok(HttpResponse::InternalServerError().json(unexpected_error))
             }
         }
     })
-    
-    /*********************************************/
-    /*  Verified, find identity, start session   */
-    /*********************************************/
-    .then(move |res| {
-        match res {
-            Ok(Ok(_)) => Either::Left(lookup_idenity(&pool, full_number.0, full_number.1)
-                .and_then(move |ident| start_session(&pool_clone, ident.person_id))
-                .then(move |res| async move { match res {
-                    Ok(session) => {
-                        info!(
-                            "Successfully verified identity for person {}",
-                            session.person_id
-                        );
-        
-                        Ok(HttpResponse::Ok().json(ApiResponse::success(session)))
-                    }
-                    Err(e) => {
-                        error!("Failed to start session! Error: {}", e);
-        
-                        let response = ApiResponse::<()>::from(None)
-                            .with_status(ResponseStatus::Error)
-                            .add_message("Internal server error".into());
-        
-                        Ok(HttpResponse::InternalServerError().json(response))
-                    }
-                }})),
-            Ok(Err(res)) => Either::Right(futures::future::ready(Ok(res))),
-            Err(e) => Either::Right(futures::future::ready(Err(e))),
-            }
-        })
     .await
 }
 
@@ -586,10 +3192,17 @@ async fn test_auth(person: models::Person) -> ActixResult<HttpResponse> {
     #[serde(rename = "message")]
     struct TestResponse(String);
 
-    Ok(HttpResponse::Ok().json(ApiResponse::success(TestResponse(format!(
-        "Hello person {}",
-        person.id
-    )))))
+    let name = match person.display_name {
+        Some(ref display_name) => display_name.clone(),
+        None => format!("person {}", person.id),
+    };
+
+    Ok(
+        HttpResponse::Ok().json(ApiResponse::success(TestResponse(format!(
+            "Hello {}",
+            name
+        )))),
+    )
 }
 
 #[derive(Deserialize)]
@@ -662,13 +3275,385 @@ async fn search_brewery(
         .await
 }
 
+/*********************************************/
+/*  Administration                           */
+/*********************************************/
+
+#[derive(Deserialize)]
+struct PeopleForm {
+    role: Option<String>,
+}
+
+/// Route handler for listing people and their roles.
+async fn get_people(
+    _admin: RequirePerson<SiteAdmin>,
+    form: web::Query<PeopleForm>,
+    pool: web::Data<Pool>,
+) -> ActixResult<HttpResponse> {
+    #[derive(Serialize)]
+    #[serde(rename = "people")]
+    struct People(Vec<models::Person>);
+
+    let role = match form
+        .role
+        .as_ref()
+        .map(|role| Role::from_str(role))
+        .transpose()
+    {
+        Ok(role) => role,
+        Err(message) => {
+            let response = ApiResponse::<()>::from(None)
+                .with_status(ResponseStatus::Fail)
+                .add_message(message);
+
+            return Ok(HttpResponse::BadRequest().json(response));
+        }
+    };
+
+    db::execute(&pool, GetPeople { role })
+        .then(|res| async move {
+            match res {
+                Ok(people) => Ok(HttpResponse::Ok().json(ApiResponse::success(People(people)))),
+                Err(e) => {
+                    error!("Unable to list people! Error: {}", e);
+
+                    let unexpected_error = ApiResponse::<()>::from(None)
+                        .with_status(ResponseStatus::Error)
+                        .add_message("An unexpected error occurred".into());
+
+                    Ok(HttpResponse::InternalServerError().json(unexpected_error))
+                }
+            }
+        })
+        .await
+}
+
+#[derive(Deserialize)]
+struct AuthEventsForm {
+    person_id: Option<i32>,
+
+    /// A phone number in E.164 format, an email address or an OIDC `{issuer}|{sub}`.
+    identifier: Option<String>,
+    kind: Option<AuthEventKind>,
+
+    /// Only events older than this one.
+    before: Option<i32>,
+    limit: Option<i64>,
+}
+
+#[derive(Serialize)]
+#[serde(rename = "auth_events")]
+struct AuthEvents(Vec<models::AuthEvent>);
+
+/// Route handler for searching the authentication audit log.
+async fn get_auth_events(
+    _admin: RequirePerson<SiteAdmin>,
+    form: web::Query<AuthEventsForm>,
+    pool: web::Data<Pool>,
+) -> ActixResult<HttpResponse> {
+    let form = form.into_inner();
+
+    db::execute(
+        &pool,
+        GetAuthEvents {
+            person_id: form.person_id,
+            identifier: form.identifier,
+            kind: form.kind,
+            before: form.before,
+            limit: form.limit.unwrap_or(100).max(1).min(500),
+        },
+    )
+    .then(|res| async move {
+        match res {
+            Ok(events) => Ok(HttpResponse::Ok().json(ApiResponse::success(AuthEvents(events)))),
+            Err(e) => {
+                error!("Unable to list auth events! Error: {}", e);
+
+                let unexpected_error = ApiResponse::<()>::from(None)
+                    .with_status(ResponseStatus::Error)
+                    .add_message("An unexpected error occurred".into());
+
+                Ok(HttpResponse::InternalServerError().json(unexpected_error))
+            }
+        }
+    })
+    .await
+}
+
+#[derive(Deserialize)]
+struct PersonIdForm {
+    id: i32,
+}
+
+#[derive(Deserialize)]
+struct RoleForm {
+    role: String,
+}
+
+/// Route handler for changing a person's role.
+async fn set_role(
+    admin: RequirePerson<SiteAdmin>,
+    info: web::Path<PersonIdForm>,
+    form: web::Form<RoleForm>,
+    pool: web::Data<Pool>,
+) -> ActixResult<HttpResponse> {
+    let role = match Role::from_str(&form.role) {
+        Ok(role) => role,
+        Err(message) => {
+            let response = ApiResponse::<()>::from(None)
+                .with_status(ResponseStatus::Fail)
+                .add_message(message);
+
+            return Ok(HttpResponse::BadRequest().json(response));
+        }
+    };
+
+    // Otherwise the last site admin could lock everyone out of administration
+    if info.id == admin.id {
+        let response = ApiResponse::<()>::from(None)
+            .with_status(ResponseStatus::Fail)
+            .add_message("You can't change your own role".into());
+
+        return Ok(HttpResponse::Forbidden().json(response));
+    }
+
+    let admin_id = admin.id;
+
+    db::execute(
+        &pool,
+        SetRole {
+            person_id: info.id,
+            role,
+        },
+    )
+    .then(move |res| async move {
+        match res {
+            Ok(Some(person)) => {
+                info!(
+                    "Person {} changed the role of person {} to {}",
+                    admin_id, person.id, person.role
+                );
+
+                Ok(HttpResponse::Ok().json(ApiResponse::success(person)))
+            }
+            Ok(None) => {
+                let not_found = ApiResponse::<()>::from(None)
+                    .with_status(ResponseStatus::Fail)
+                    .add_message("Could not find that person".into());
+
+                Ok(HttpResponse::NotFound().json(not_found))
+            }
+            Err(e) => {
+                error!("Unable to change role! Error: {}", e);
+
+                let unexpected_error = ApiResponse::<()>::from(None)
+                    .with_status(ResponseStatus::Error)
+                    .add_message("An unexpected error occurred".into());
+
+                Ok(HttpResponse::InternalServerError().json(unexpected_error))
+            }
+        }
+    })
+    .await
+}
+
+#[derive(Deserialize)]
+struct CatalogIdForm {
+    id: i32,
+}
+
+#[derive(Deserialize)]
+struct RenameForm {
+    name: String,
+}
+
+/// Route handler for correcting a brewery's name.
+async fn rename_brewery(
+    _staff: RequirePerson<Bartender>,
+    info: web::Path<CatalogIdForm>,
+    form: web::Form<RenameForm>,
+    pool: web::Data<Pool>,
+) -> ActixResult<HttpResponse> {
+    let name = form.name.trim().to_string();
+
+    if name.is_empty() {
+        let response = ApiResponse::<()>::from(None)
+            .with_status(ResponseStatus::Fail)
+            .add_message("Names can't be empty".into());
+
+        return Ok(HttpResponse::BadRequest().json(response));
+    }
+
+    let edit = db::execute(
+        &pool,
+        RenameBrewery {
+            brewery_id: info.id,
+            name,
+        },
+    )
+    .await;
+
+    Ok(catalog_edit_response(edit, "brewery"))
+}
+
+/// Route handler for correcting a beer's name.
+async fn rename_beer(
+    _staff: RequirePerson<Bartender>,
+    info: web::Path<CatalogIdForm>,
+    form: web::Form<RenameForm>,
+    pool: web::Data<Pool>,
+) -> ActixResult<HttpResponse> {
+    let name = form.name.trim().to_string();
+
+    if name.is_empty() {
+        let response = ApiResponse::<()>::from(None)
+            .with_status(ResponseStatus::Fail)
+            .add_message("Names can't be empty".into());
+
+        return Ok(HttpResponse::BadRequest().json(response));
+    }
+
+    let edit = db::execute(
+        &pool,
+        RenameBeer {
+            beer_id: info.id,
+            name,
+        },
+    )
+    .await;
+
+    Ok(catalog_edit_response(edit, "beer"))
+}
+
+#[derive(Deserialize)]
+struct MergeForm {
+    /// The id of the entry to keep.
+    into: i32,
+}
+
+#[derive(Serialize)]
+#[serde(rename = "merge")]
+struct CatalogMerge {
+    from: i32,
+    into: i32,
+    moved: usize,
+}
+
+/// Route handler for merging a duplicate brewery into another.
+async fn merge_brewery(
+    _admin: RequirePerson<ClubAdmin>,
+    info: web::Path<CatalogIdForm>,
+    form: web::Form<MergeForm>,
+    pool: web::Data<Pool>,
+) -> ActixResult<HttpResponse> {
+    let (from, into) = (info.id, form.into);
+
+    if from == into {
+        let response = ApiResponse::<()>::from(None)
+            .with_status(ResponseStatus::Fail)
+            .add_message("Can't merge a brewery into itself".into());
+
+        return Ok(HttpResponse::BadRequest().json(response));
+    }
+
+    let edit = db::execute(&pool, MergeBrewery { from, into })
+        .await
+        .map(|edit| match edit {
+            CatalogEdit::Done(moved) => CatalogEdit::Done(CatalogMerge { from, into, moved }),
+            CatalogEdit::NotFound => CatalogEdit::NotFound,
+            CatalogEdit::Duplicate(id) => CatalogEdit::Duplicate(id),
+        });
+
+    Ok(catalog_edit_response(edit, "brewery"))
+}
+
+/// Route handler for merging a duplicate beer into another.
+async fn merge_beer(
+    _admin: RequirePerson<ClubAdmin>,
+    info: web::Path<CatalogIdForm>,
+    form: web::Form<MergeForm>,
+    pool: web::Data<Pool>,
+) -> ActixResult<HttpResponse> {
+    let (from, into) = (info.id, form.into);
+
+    if from == into {
+        let response = ApiResponse::<()>::from(None)
+            .with_status(ResponseStatus::Fail)
+            .add_message("Can't merge a beer into itself".into());
+
+        return Ok(HttpResponse::BadRequest().json(response));
+    }
+
+    let edit = db::execute(&pool, MergeBeer { from, into })
+        .await
+        .map(|edit| match edit {
+            CatalogEdit::Done(moved) => CatalogEdit::Done(CatalogMerge { from, into, moved }),
+            CatalogEdit::NotFound => CatalogEdit::NotFound,
+            CatalogEdit::Duplicate(id) => CatalogEdit::Duplicate(id),
+        });
+
+    Ok(catalog_edit_response(edit, "beer"))
+}
+
+/// Describe the result of moderating a beer or brewery.
+fn catalog_edit_response<T: serde::Serialize>(
+    edit: std::result::Result<CatalogEdit<T>, error::Error>,
+    what: &str,
+) -> HttpResponse {
+    match edit {
+        Ok(CatalogEdit::Done(result)) => HttpResponse::Ok().json(ApiResponse::success(result)),
+        Ok(CatalogEdit::NotFound) => {
+            let not_found = ApiResponse::<()>::from(None)
+                .with_status(ResponseStatus::Fail)
+                .add_message(format!("Could not find that {}", what));
+
+            HttpResponse::NotFound().json(not_found)
+        }
+        Ok(CatalogEdit::Duplicate(id)) => {
+            let response = ApiResponse::<()>::from(None)
+                .with_status(ResponseStatus::Fail)
+                .add_message(format!(
+                    "That would duplicate {} {}; merge them instead",
+                    what, id
+                ));
+
+            HttpResponse::Conflict().json(response)
+        }
+        Err(e) => {
+            error!("Unable to update {}! Error: {}", what, e);
+
+            let unexpected_error = ApiResponse::<()>::from(None)
+                .with_status(ResponseStatus::Error)
+                .add_message("An unexpected error occurred".into());
+
+            HttpResponse::InternalServerError().json(unexpected_error)
+        }
+    }
+}
+
 #[actix_rt::main]
 async fn main() -> std::io::Result<()> {
     dotenv::dotenv().ok();
     env_logger::init();
 
-    // Make sure an authy API key is set before starting.
-    let _ = std::env::var("AUTHY_API_KEY").expect("An authy API key is required!");
+    // Set up the phone verification provider before starting.
+    let verifier = verification::from_env();
+
+    // Set up the mailer used for email logins.
+    let mailer = mail::from_env();
+    let email_config = EmailLoginConfig::from_env();
+
+    // Set up login with an OpenID Connect provider, if one is configured.
+    let oidc = oidc::from_env();
+
+    // Read how long login sessions should last.
+    let session_config = SessionConfig::from_env();
+
+    // Read how long deleted accounts can be restored for.
+    let deletion_config = DeletionConfig::from_env();
+
+    // Set up the store that photos of drinks are kept in.
+    let photo_store = photos::from_env();
 
     // Read the port on which to listen.
     let port = u16::from_str(&std::env::var("PORT").unwrap_or("1234".into()))
@@ -686,12 +3671,74 @@ async fn main() -> std::io::Result<()> {
     let manager = ConnectionManager::<PgConnection>::new(database_url);
     let pool = Pool::new(manager).expect("Failed to create database connection pool!");
 
+    // Rewrite any phone identities saved before numbers were normalized.
+    match db::execute(&pool, NormalizePhoneIdentities).await {
+        Ok(0) => {}
+        Ok(normalized) => info!("Normalized {} phone identities", normalized),
+        Err(e) => error!("Unable to normalize phone identities! Error: {}", e),
+    }
+
+    // Set up rate limiting for login attempts.
+    let throttle = Throttle::from_env(&pool);
+
+    // Record authentication events for auditing.
+    let audit_log = AuditLog::new(pool.clone(), throttle.config().trust_forwarded);
+
+    // Clear out rate limit windows once they have closed.
+    let purge_throttle = throttle.clone();
+
+    actix_rt::spawn(async move {
+        let mut interval = actix_rt::time::interval(std::time::Duration::from_secs(10 * 60));
+
+        loop {
+            interval.tick().await;
+
+            if let Err(e) = purge_throttle.purge().await {
+                error!("Failed to purge closed rate limit windows! Error: {}", e);
+            }
+        }
+    });
+
+    // Permanently delete accounts once their grace period is over.
+    let purge_pool = pool.clone();
+    let purge_store = photo_store.clone();
+    let grace = deletion_config.grace;
+
+    actix_rt::spawn(async move {
+        let mut interval = actix_rt::time::interval(std::time::Duration::from_secs(60 * 60));
+
+        loop {
+            interval.tick().await;
+
+            match db::execute(&purge_pool, PurgeDeletedPersons { grace }).await {
+                Ok(purged) => {
+                    if purged.persons > 0 {
+                        info!("Purged {} deleted accounts", purged.persons);
+                    }
+
+                    photos::remove(&purge_store, purged.photo_keys).await;
+                }
+                Err(e) => error!("Failed to purge deleted accounts! Error: {}", e),
+            }
+        }
+    });
+
     info!("Listening on {}", listen_addr);
 
     HttpServer::new(move || {
         App::new()
             .data(pool.clone())
             .app_data(pool.clone())
+            .data(verifier.clone())
+            .data(mailer.clone())
+            .data(oidc.clone())
+            .data(throttle.clone())
+            .data(email_config.clone())
+            .data(session_config.clone())
+            .data(deletion_config.clone())
+            .data(photo_store.clone())
+            .app_data(session_config.clone())
+            .app_data(audit_log.clone())
             .wrap(Logger::default())
             .wrap(Cors::default())
             .route("/", web::get().to(index))
@@ -703,14 +3750,93 @@ async fn main() -> std::io::Result<()> {
                             .route(web::get().to(get_drinks))
                             .route(web::post().to(new_drink)),
                     )
-                    .service(web::resource("/{id}").route(web::delete().to(delete_drink))),
+                    .service(
+                        web::resource("/descriptors").route(web::get().to(get_flavor_descriptors)),
+                    )
+                    .service(
+                        web::resource("/import")
+                            .app_data(web::PayloadConfig::new(import::MAX_BYTES))
+                            .route(web::post().to(import_drinks)),
+                    )
+                    .service(
+                        web::resource("/{id}")
+                            .route(web::get().to(get_drink))
+                            .route(web::patch().to(update_drink))
+                            .route(web::delete().to(delete_drink)),
+                    )
+                    .service(
+                        web::resource("/{id}/tasting-notes")
+                            .route(web::put().to(set_tasting_notes))
+                            .route(web::delete().to(delete_tasting_notes)),
+                    )
+                    .service(web::resource("/{id}/photos").route(web::post().to(upload_photo)))
+                    .service(
+                        web::resource("/{id}/photos/{photo_id}")
+                            .route(web::delete().to(delete_photo)),
+                    ),
             )
+            .service(web::resource("/photos/{key}").route(web::get().to(get_photo)))
             .service(
                 web::scope("/auth")
                     .service(web::resource("").route(web::post().to(begin_auth)))
                     .service(web::resource("/verify").route(web::post().to(complete_auth)))
+                    .service(web::resource("/email").route(web::post().to(begin_email_auth)))
+                    .service(
+                        web::resource("/email/verify").route(web::post().to(complete_email_auth)),
+                    )
+                    .service(web::resource("/oidc/authorize").route(web::get().to(begin_oidc_auth)))
+                    .service(
+                        web::resource("/oidc/verify").route(web::post().to(complete_oidc_auth)),
+                    )
+                    .service(web::resource("/logout").route(web::post().to(logout)))
+                    .service(
+                        web::resource("/sessions")
+                            .route(web::get().to(get_sessions))
+                            .route(web::delete().to(revoke_other_sessions)),
+                    )
+                    .service(web::resource("/sessions/{id}").route(web::delete().to(revoke_session)))
+                    .service(
+                        web::resource("/tokens")
+                            .route(web::get().to(get_api_tokens))
+                            .route(web::post().to(create_api_token)),
+                    )
+                    .service(web::resource("/tokens/{id}").route(web::delete().to(delete_api_token)))
+                    .service(web::resource("/identities").route(web::get().to(get_identities)))
+                    .service(web::resource("/identities/phone").route(web::post().to(link_phone)))
+                    .service(web::resource("/identities/email").route(web::post().to(link_email)))
+                    .service(web::resource("/identities/oidc").route(web::post().to(link_oidc)))
+                    .service(
+                        web::resource("/identities/{kind}/{identifier}")
+                            .route(web::delete().to(unlink_identity)),
+                    )
                     .service(web::resource("/test").route(web::get().to(test_auth))),
             )
+            .service(
+                web::scope("/me")
+                    .service(
+                        web::resource("")
+                            .route(web::get().to(get_me))
+                            .route(web::patch().to(update_me))
+                            .route(web::delete().to(delete_account)),
+                    )
+                    .service(web::resource("/avatar").route(web::put().to(upload_avatar)))
+                    .service(web::resource("/export").route(web::get().to(export_personal_data)))
+                    .service(web::resource("/restore").route(web::post().to(restore_account)))
+                    .service(web::resource("/sign-ins").route(web::get().to(get_sign_ins))),
+            )
+            .service(web::resource("/people/{handle}").route(web::get().to(get_profile)))
+            .service(
+                web::scope("/admin")
+                    .service(web::resource("/people").route(web::get().to(get_people)))
+                    .service(web::resource("/auth-events").route(web::get().to(get_auth_events)))
+                    .service(web::resource("/people/{id}/role").route(web::put().to(set_role)))
+                    .service(web::resource("/brewery/{id}").route(web::patch().to(rename_brewery)))
+                    .service(
+                        web::resource("/brewery/{id}/merge").route(web::post().to(merge_brewery)),
+                    )
+                    .service(web::resource("/beer/{id}").route(web::patch().to(rename_beer)))
+                    .service(web::resource("/beer/{id}/merge").route(web::post().to(merge_beer))),
+            )
             .service(
                 web::scope("/search")
                     .service(web::resource("/beer").route(web::get().to(search_beer)))
@@ -1,6 +1,8 @@
 #![allow(proc_macro_derive_resolution_fallback)] // See: https://github.com/diesel-rs/diesel/issues/1785
 extern crate chrono;
 
+use crate::auth::Role;
+use crate::drinks::Rating;
 use crate::error::{Error, Result};
 use crate::schema::*;
 use actix_web::Error as ActixError;
@@ -10,13 +12,14 @@ use chrono::{DateTime, Utc};
 use futures::future::Either;
 use futures::future::Future;
 use futures::prelude::*;
-
-#[derive(Serialize, Queryable)]
+use std::str::FromStr;
 
 /*************************************/
 /* Brewery Models                    */
 /*************************************/
 
+#[derive(Serialize, Queryable)]
+#[serde(rename = "brewery")]
 pub struct Brewery {
     pub id: i32,
     pub name: String,
@@ -35,6 +38,7 @@ pub struct NewBrewery<'a> {
 /*************************************/
 
 #[derive(Serialize, Queryable)]
+#[serde(rename = "beer")]
 pub struct Beer {
     pub id: i32,
     pub name: String,
@@ -60,10 +64,17 @@ pub struct Drink {
     pub person_id: i32,
     pub drank_on: NaiveDate,
     pub beer_id: i32,
-    pub rating: i16,
     pub comment: Option<String>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
+    pub shared: bool,
+    pub drank_at: Option<DateTime<Utc>>,
+    pub serving: Option<String>,
+    pub volume_ml: Option<i32>,
+    pub price_cents: Option<i32>,
+    pub abv: Option<f32>,
+    pub venue: Option<String>,
+    pub rating: Rating,
 }
 
 #[derive(Insertable)]
@@ -72,8 +83,107 @@ pub struct NewDrink<'a> {
     pub person_id: &'a i32,
     pub drank_on: &'a NaiveDate,
     pub beer_id: &'a i32,
-    pub rating: &'a i16,
+    #[column_name = "rating_quarters"]
+    pub rating: &'a Rating,
     pub comment: Option<&'a String>,
+    pub shared: &'a bool,
+    pub drank_at: Option<&'a DateTime<Utc>>,
+    pub serving: Option<&'a String>,
+    pub volume_ml: Option<&'a i32>,
+    pub price_cents: Option<&'a i32>,
+    pub abv: Option<&'a f32>,
+    pub venue: Option<&'a String>,
+}
+
+/// Changes to a drink; fields left as `None` are kept as they are.
+#[derive(AsChangeset, Clone, Default)]
+#[table_name = "drink"]
+pub struct DrinkChanges {
+    pub drank_on: Option<NaiveDate>,
+    pub beer_id: Option<i32>,
+    #[column_name = "rating_quarters"]
+    pub rating: Option<Rating>,
+    pub comment: Option<Option<String>>,
+    pub shared: Option<bool>,
+    pub drank_at: Option<Option<DateTime<Utc>>>,
+    pub serving: Option<Option<String>>,
+    pub volume_ml: Option<Option<i32>>,
+    pub price_cents: Option<Option<i32>>,
+    pub abv: Option<Option<f32>>,
+    pub venue: Option<Option<String>>,
+}
+
+impl DrinkChanges {
+    pub fn is_empty(&self) -> bool {
+        self.drank_on.is_none()
+            && self.beer_id.is_none()
+            && self.rating.is_none()
+            && self.comment.is_none()
+            && self.shared.is_none()
+            && self.drank_at.is_none()
+            && self.serving.is_none()
+            && self.volume_ml.is_none()
+            && self.price_cents.is_none()
+            && self.abv.is_none()
+            && self.venue.is_none()
+    }
+}
+
+/*************************************/
+/* Drink Photo Models                */
+/*************************************/
+
+#[derive(Queryable)]
+pub struct DrinkPhoto {
+    pub id: i32,
+    pub drink_id: i32,
+
+    /// Where the full size photo is kept in the blob store.
+    pub blob_key: String,
+    pub thumbnail_key: String,
+    pub width: i32,
+    pub height: i32,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Insertable)]
+#[table_name = "drink_photo"]
+pub struct NewDrinkPhoto<'a> {
+    pub drink_id: i32,
+    pub blob_key: &'a str,
+    pub thumbnail_key: &'a str,
+    pub width: i32,
+    pub height: i32,
+}
+
+/*************************************/
+/* Tasting Note Models               */
+/*************************************/
+
+#[derive(Insertable)]
+#[table_name = "tasting_note"]
+pub struct NewTastingNote {
+    pub drink_id: i32,
+    pub appearance: Option<i16>,
+    pub aroma: Option<i16>,
+    pub flavor: Option<i16>,
+    pub mouthfeel: Option<i16>,
+    pub overall: Option<i16>,
+}
+
+#[derive(Insertable)]
+#[table_name = "tasting_note_descriptor"]
+pub struct NewTastingNoteDescriptor {
+    pub drink_id: i32,
+    pub descriptor_id: i32,
+}
+
+#[derive(Serialize, Queryable)]
+#[serde(rename = "flavor_descriptor")]
+pub struct FlavorDescriptor {
+    pub id: i32,
+    pub name: String,
 }
 
 /*************************************/
@@ -81,10 +191,84 @@ pub struct NewDrink<'a> {
 /*************************************/
 
 #[derive(Serialize, Queryable)]
+#[serde(rename = "person")]
 pub struct Person {
     pub id: i32,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
+    pub role: String,
+
+    /// When the person asked for their account to be deleted, if they have.
+    pub deletion_requested_at: Option<DateTime<Utc>>,
+    pub display_name: Option<String>,
+
+    /// The unique, lowercase name that others can find the person by.
+    pub handle: Option<String>,
+    pub bio: Option<String>,
+    pub avatar_url: Option<String>,
+
+    /// The IANA name of the person's home timezone.
+    pub timezone: String,
+
+    /// The units the person prefers, `metric` or `imperial`.
+    pub units: String,
+
+    /// The blob store key of an uploaded avatar, which `avatar_url` points at.
+    #[serde(skip_serializing)]
+    pub avatar_key: Option<String>,
+}
+
+impl Person {
+    pub fn role(&self) -> Role {
+        // The database only allows known roles, so this can't really fail
+        Role::from_str(&self.role).unwrap_or(Role::Member)
+    }
+
+    /// What anyone may see about this person.
+    pub fn public_profile(&self) -> PublicProfile {
+        PublicProfile {
+            handle: self.handle.clone(),
+            display_name: self.display_name.clone(),
+            bio: self.bio.clone(),
+            avatar_url: self.avatar_url.clone(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename = "profile")]
+pub struct PublicProfile {
+    pub handle: Option<String>,
+    pub display_name: Option<String>,
+    pub bio: Option<String>,
+    pub avatar_url: Option<String>,
+}
+
+/// Changes to a person's profile; `Some(None)` clears an optional field.
+#[derive(AsChangeset, Default)]
+#[table_name = "person"]
+pub struct ProfileChanges {
+    pub display_name: Option<Option<String>>,
+    pub handle: Option<Option<String>>,
+    pub bio: Option<Option<String>>,
+    pub avatar_url: Option<Option<String>>,
+    pub timezone: Option<String>,
+    pub units: Option<String>,
+
+    /// An uploaded avatar is replaced whenever `avatar_url` changes.
+    pub avatar_key: Option<Option<String>>,
+}
+
+impl ProfileChanges {
+    pub fn is_empty(&self) -> bool {
+        self.display_name.is_none()
+            && self.handle.is_none()
+            && self.bio.is_none()
+            && self.avatar_url.is_none()
+            && self.timezone.is_none()
+            && self.units.is_none()
+            && self.avatar_key.is_none()
+    }
 }
 
 impl FromRequest for Person {
@@ -94,37 +278,90 @@ impl FromRequest for Person {
 
     fn from_request(req: &HttpRequest, _payload: &mut actix_web::dev::Payload) -> Self::Future {
         use crate::db::GetLoggedInPerson;
-        use actix_web::error as awerror;
-        use actix_web::http::header::AUTHORIZATION;
 
         let pool = req
             .app_data::<crate::db::Pool>()
             .expect("Failed to access database pool!");
 
-        let auth = req
-            .headers()
-            .get(AUTHORIZATION)
-            .ok_or(awerror::ErrorUnauthorized(Error::SessionNotFound))
-            .and_then(|h| h.to_str().map_err(|e| awerror::ErrorBadRequest(e)));
+        let config = req
+            .app_data::<crate::config::SessionConfig>()
+            .expect("Failed to access session configuration!");
 
-        let auth = match auth {
+        let auth = match session_token(req) {
             Ok(auth) => auth,
-            Err(e) => return Either::Left(futures::future::ready(Err(Error::from(e)))),
+            Err(e) => return Either::Left(futures::future::ready(Err(e))),
         };
 
+        // Personal API tokens are only accepted by handlers that require a scope
+        if auth.starts_with(crate::auth::API_TOKEN_PREFIX) {
+            return Either::Left(futures::future::ready(Err(Error::SessionRequired)));
+        }
+
         Either::Right(crate::db::execute(
             &pool,
-            GetLoggedInPerson::from_session(auth.to_string()),
+            GetLoggedInPerson::from_session(auth, config.clone()),
         ))
     }
 }
 
+/// Read the session or API token supplied with a request.
+///
+/// This is the `Authorization` header, preferably with the `Bearer` scheme, or
+/// failing that the session cookie if cookie sessions are enabled.
+pub fn session_token(req: &HttpRequest) -> Result<String> {
+    use actix_web::http::header::AUTHORIZATION;
+
+    if let Some(auth) = req.headers().get(AUTHORIZATION) {
+        return auth
+            .to_str()
+            .map_err(|_| Error::MalformedToken)
+            .and_then(crate::auth::parse_authorization);
+    }
+
+    let cookie_config = req
+        .app_data::<crate::config::SessionConfig>()
+        .and_then(|config| config.cookie.clone());
+
+    match cookie_config {
+        Some(cookie_config) => {
+            crate::cookies::session_token(req, &cookie_config)?.ok_or(Error::SessionMissing)
+        }
+        None => Err(Error::SessionMissing),
+    }
+}
+
+/// The kinds of identifier that a person may log in with.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IdentityKind {
+    /// A phone number in E.164 format.
+    Phone,
+
+    /// A normalized email address.
+    Email,
+
+    /// An OpenID Connect subject, as `{issuer}|{sub}`.
+    Oidc,
+}
+
+impl IdentityKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            IdentityKind::Phone => "phone",
+            IdentityKind::Email => "email",
+            IdentityKind::Oidc => "oidc",
+        }
+    }
+}
+
 #[derive(Serialize, Queryable)]
+#[serde(rename = "identity")]
 pub struct Identity {
     pub identifier: String,
     pub person_id: i32,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
+    pub kind: String,
 }
 
 #[derive(Insertable)]
@@ -132,16 +369,23 @@ pub struct Identity {
 pub struct NewIdentity<'a> {
     pub identifier: &'a str,
     pub person_id: i32,
+    pub kind: &'a str,
 }
 
 #[derive(Serialize, Queryable)]
 #[serde(rename = "session")]
 pub struct Session {
+    /// The SHA-256 digest of the session token.
+    #[serde(skip_serializing)]
     pub id: String,
     pub person_id: i32,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
     pub expires_at: DateTime<Utc>,
+    pub public_id: i32,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub device_label: Option<String>,
+    pub user_agent: Option<String>,
 }
 
 #[derive(Insertable)]
@@ -150,8 +394,133 @@ pub struct NewSession<'a> {
     pub id: &'a str,
     pub person_id: i32,
     pub expires_at: DateTime<Utc>,
+    pub device_label: Option<&'a str>,
+    pub user_agent: Option<&'a str>,
 }
 
 /*********************/
 /* Login Sessions    */
 /*********************/
+
+/*********************/
+/* API Tokens        */
+/*********************/
+
+#[derive(Serialize, Queryable)]
+#[serde(rename = "token")]
+pub struct ApiToken {
+    pub id: i32,
+    pub person_id: i32,
+    pub name: String,
+
+    /// The SHA-256 digest of the token.
+    #[serde(skip_serializing)]
+    pub digest: String,
+    pub scopes: Vec<String>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Insertable)]
+#[table_name = "api_token"]
+pub struct NewApiToken<'a> {
+    pub person_id: i32,
+    pub name: &'a str,
+    pub digest: &'a str,
+    pub scopes: Vec<&'a str>,
+}
+
+/*********************/
+/* Email Login       */
+/*********************/
+
+#[derive(Queryable)]
+pub struct EmailChallenge {
+    pub id: i32,
+    pub email: String,
+    pub digest: String,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    pub consumed_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Insertable)]
+#[table_name = "email_challenge"]
+pub struct NewEmailChallenge<'a> {
+    pub email: &'a str,
+    pub digest: &'a str,
+    pub expires_at: DateTime<Utc>,
+}
+
+/*********************/
+/* OIDC Login        */
+/*********************/
+
+#[derive(Queryable)]
+pub struct OidcLogin {
+    /// The SHA-256 digest of the state parameter.
+    pub state: String,
+    pub nonce: String,
+    pub code_verifier: String,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(Insertable)]
+#[table_name = "oidc_login"]
+pub struct NewOidcLogin<'a> {
+    pub state: &'a str,
+    pub nonce: &'a str,
+    pub code_verifier: &'a str,
+    pub expires_at: DateTime<Utc>,
+}
+
+/*********************/
+/* Audit Log         */
+/*********************/
+
+#[derive(Serialize, Queryable)]
+#[serde(rename = "auth_event")]
+pub struct AuthEvent {
+    pub id: i32,
+    pub kind: String,
+    pub person_id: Option<i32>,
+    pub identity_kind: Option<String>,
+    pub identifier: Option<String>,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+    pub detail: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Insertable)]
+#[table_name = "auth_event"]
+pub struct NewAuthEvent<'a> {
+    pub kind: &'a str,
+    pub person_id: Option<i32>,
+    pub identity_kind: Option<&'a str>,
+    pub identifier: Option<&'a str>,
+    pub ip_address: Option<&'a str>,
+    pub user_agent: Option<&'a str>,
+    pub detail: Option<&'a str>,
+}
+
+/*********************/
+/* Rate Limiting     */
+/*********************/
+
+#[derive(Queryable)]
+pub struct AuthThrottle {
+    pub key: String,
+    pub hits: i32,
+    pub resets_at: DateTime<Utc>,
+}
+
+#[derive(Insertable)]
+#[table_name = "auth_throttle"]
+pub struct NewAuthThrottle<'a> {
+    pub key: &'a str,
+    pub hits: i32,
+    pub resets_at: DateTime<Utc>,
+}
@@ -0,0 +1,403 @@
+//! Logging in with an OpenID Connect provider.
+//!
+//! This is the authorization code flow with PKCE against a single issuer. The
+//! provider's endpoints and signing keys are found through its discovery
+//! document, so any compliant provider works, including a local mock for
+//! development. People are identified by the issuer and their `sub` claim.
+
+use std::sync::{Arc, RwLock};
+
+use actix_web::web;
+use futures::future::Future;
+use futures::prelude::*;
+use jsonwebtoken::{Algorithm, DecodingKey, Header, Validation};
+use reqwest::Url;
+use sha2::{Digest, Sha256};
+
+use crate::config::OidcConfig;
+use crate::error::{Error, Result};
+use crate::token;
+
+/// The parts of the provider's discovery document that we use.
+#[derive(Clone, Deserialize)]
+struct Metadata {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+}
+
+/// A public signing key from the provider's JWKS document.
+#[derive(Clone, Deserialize)]
+struct Jwk {
+    kid: Option<String>,
+    kty: String,
+    n: Option<String>,
+    e: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct JwkSet {
+    keys: Vec<Jwk>,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+/// The verified claims of an ID token.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct IdTokenClaims {
+    pub iss: String,
+    pub sub: String,
+    nonce: Option<String>,
+}
+
+impl IdTokenClaims {
+    /// The identifier for an OIDC identity; `sub` is only unique per issuer.
+    pub fn identifier(&self) -> String {
+        format!("{}|{}", self.iss, self.sub)
+    }
+}
+
+/// The secrets for a login that has been started but not completed.
+pub struct PendingLogin {
+    /// Ties the provider's response to the login that asked for it.
+    pub state: String,
+
+    /// Ties the ID token to the login that asked for it.
+    pub nonce: String,
+
+    /// The PKCE secret, which proves that whoever redeems the code started the login.
+    pub code_verifier: String,
+}
+
+impl PendingLogin {
+    pub fn new() -> PendingLogin {
+        PendingLogin {
+            state: token::generate_hex(32),
+            nonce: token::generate_hex(16),
+            code_verifier: token::generate_hex(32),
+        }
+    }
+}
+
+pub struct OidcClient {
+    config: OidcConfig,
+    http: reqwest::blocking::Client,
+    metadata: RwLock<Option<Metadata>>,
+    keys: RwLock<Vec<Jwk>>,
+}
+
+impl OidcClient {
+    pub fn new(config: OidcConfig) -> OidcClient {
+        OidcClient {
+            config,
+            http: reqwest::blocking::Client::new(),
+            metadata: RwLock::new(None),
+            keys: RwLock::new(Vec::new()),
+        }
+    }
+
+    pub fn config(&self) -> &OidcConfig {
+        &self.config
+    }
+
+    /// The provider URL to send a person to so that they can log in.
+    pub fn authorization_url(&self, login: &PendingLogin) -> Result<String> {
+        let metadata = self.metadata()?;
+
+        let url = Url::parse_with_params(
+            &metadata.authorization_endpoint,
+            &[
+                ("response_type", "code"),
+                ("client_id", &self.config.client_id),
+                ("redirect_uri", &self.config.redirect_uri),
+                ("scope", &self.config.scopes),
+                ("state", &login.state),
+                ("nonce", &login.nonce),
+                ("code_challenge", &code_challenge(&login.code_verifier)),
+                ("code_challenge_method", "S256"),
+            ],
+        )
+        .map_err(oidc_error)?;
+
+        Ok(url.to_string())
+    }
+
+    /// Redeem an authorization code, returning the claims of the verified ID token.
+    pub fn exchange(&self, code: &str, code_verifier: &str, nonce: &str) -> Result<IdTokenClaims> {
+        let metadata = self.metadata()?;
+
+        let mut request = self.http.post(&metadata.token_endpoint);
+        let mut form = vec![
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", &self.config.redirect_uri),
+            ("code_verifier", code_verifier),
+        ];
+
+        match self.config.client_secret {
+            Some(ref secret) => request = request.basic_auth(&self.config.client_id, Some(secret)),
+            None => form.push(("client_id", &self.config.client_id)),
+        }
+
+        let response = request
+            .form(&form)
+            .send()
+            .and_then(|response| response.error_for_status())
+            .and_then(|response| response.json::<TokenResponse>())
+            .map_err(oidc_error)?;
+
+        let header = jsonwebtoken::decode_header(&response.id_token).map_err(oidc_error)?;
+        let key = self.decoding_key(&header)?;
+
+        self.validate_id_token(&response.id_token, header.alg, &key, nonce)
+    }
+
+    fn validate_id_token(
+        &self,
+        id_token: &str,
+        algorithm: Algorithm,
+        key: &DecodingKey,
+        nonce: &str,
+    ) -> Result<IdTokenClaims> {
+        let mut validation = Validation::new(algorithm);
+        validation.leeway = 60;
+        validation.iss = Some(self.config.issuer.clone());
+        validation.set_audience(&[&self.config.client_id]);
+
+        let claims = jsonwebtoken::decode::<IdTokenClaims>(id_token, key, &validation)
+            .map_err(oidc_error)?
+            .claims;
+
+        if claims.nonce.as_ref().map(String::as_str) != Some(nonce) {
+            return Err(Error::OidcError("ID token nonce does not match".into()));
+        }
+
+        Ok(claims)
+    }
+
+    /// Find the key that an ID token was signed with.
+    ///
+    /// Tokens signed with an HMAC use the client secret. Otherwise the key is looked
+    /// up in the provider's JWKS, which is fetched again if the key isn't known yet,
+    /// since providers rotate their keys.
+    fn decoding_key(&self, header: &Header) -> Result<DecodingKey<'static>> {
+        match header.alg {
+            Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512 => {
+                return match self.config.client_secret {
+                    Some(ref secret) => {
+                        Ok(DecodingKey::from_secret(secret.as_bytes()).into_static())
+                    }
+                    None => Err(Error::OidcError(
+                        "ID token is signed with a client secret, but none is configured".into(),
+                    )),
+                };
+            }
+            Algorithm::RS256
+            | Algorithm::RS384
+            | Algorithm::RS512
+            | Algorithm::PS256
+            | Algorithm::PS384
+            | Algorithm::PS512 => {}
+            other => {
+                return Err(Error::OidcError(format!(
+                    "Unsupported ID token algorithm {:?}",
+                    other
+                )))
+            }
+        }
+
+        let find_key = |keys: &[Jwk]| -> Option<DecodingKey<'static>> {
+            let mut candidates = keys.iter().filter(|key| key.kty == "RSA");
+
+            let key = match header.kid {
+                Some(ref kid) => candidates.find(|key| key.kid.as_ref() == Some(kid)),
+                None => candidates.next(),
+            }?;
+
+            Some(DecodingKey::from_rsa_components(key.n.as_ref()?, key.e.as_ref()?).into_static())
+        };
+
+        if let Some(key) = find_key(&self.keys.read().unwrap()) {
+            return Ok(key);
+        }
+
+        let jwks = self
+            .http
+            .get(&self.metadata()?.jwks_uri)
+            .send()
+            .and_then(|response| response.error_for_status())
+            .and_then(|response| response.json::<JwkSet>())
+            .map_err(oidc_error)?;
+
+        let key = find_key(&jwks.keys);
+        *self.keys.write().unwrap() = jwks.keys;
+
+        key.ok_or(Error::OidcError("ID token signing key not found".into()))
+    }
+
+    /// The provider's discovery document, which is fetched once and then kept.
+    fn metadata(&self) -> Result<Metadata> {
+        if let Some(ref metadata) = *self.metadata.read().unwrap() {
+            return Ok(metadata.clone());
+        }
+
+        let url = format!(
+            "{}/.well-known/openid-configuration",
+            self.config.issuer.trim_end_matches('/')
+        );
+
+        let metadata = self
+            .http
+            .get(&url)
+            .send()
+            .and_then(|response| response.error_for_status())
+            .and_then(|response| response.json::<Metadata>())
+            .map_err(oidc_error)?;
+
+        if metadata.issuer != self.config.issuer {
+            return Err(Error::OidcError(format!(
+                "Discovery document is for issuer '{}'",
+                metadata.issuer
+            )));
+        }
+
+        *self.metadata.write().unwrap() = Some(metadata.clone());
+
+        Ok(metadata)
+    }
+}
+
+/// The shared client stored as application data, or `None` if OIDC login isn't configured.
+pub type Provider = Option<Arc<OidcClient>>;
+
+/// Build the OIDC client from `OidcConfig::from_env`.
+pub fn from_env() -> Provider {
+    OidcConfig::from_env().map(|config| {
+        info!("OpenID Connect login enabled for {}", config.issuer);
+
+        Arc::new(OidcClient::new(config))
+    })
+}
+
+/// Run `OidcClient::authorization_url` on the blocking thread pool.
+pub fn authorization_url(
+    client: &Arc<OidcClient>,
+    login: PendingLogin,
+) -> impl Future<Output = Result<(String, PendingLogin)>> {
+    let client = client.clone();
+
+    block(move || client.authorization_url(&login).map(|url| (url, login)))
+}
+
+/// Run `OidcClient::exchange` on the blocking thread pool.
+pub fn exchange(
+    client: &Arc<OidcClient>,
+    code: String,
+    code_verifier: String,
+    nonce: String,
+) -> impl Future<Output = Result<IdTokenClaims>> {
+    let client = client.clone();
+
+    block(move || client.exchange(&code, &code_verifier, &nonce))
+}
+
+/// The PKCE `S256` challenge for a code verifier.
+fn code_challenge(code_verifier: &str) -> String {
+    base64::encode_config(
+        Sha256::digest(code_verifier.as_bytes()),
+        base64::URL_SAFE_NO_PAD,
+    )
+}
+
+fn oidc_error<E: std::fmt::Display>(e: E) -> Error {
+    Error::OidcError(e.to_string())
+}
+
+fn block<F, T>(f: F) -> impl Future<Output = Result<T>>
+where
+    F: FnOnce() -> Result<T> + Send + 'static,
+    T: Send + 'static,
+{
+    use actix_web::error::BlockingError;
+    use futures::channel::oneshot::Canceled;
+
+    web::block(f).map(|res| match res {
+        Ok(r) => Ok(r),
+        Err(BlockingError::Error(e)) => Err(e),
+        Err(BlockingError::Canceled) => Err(Error::from(Canceled)),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+    use jsonwebtoken::EncodingKey;
+
+    fn client() -> OidcClient {
+        OidcClient::new(OidcConfig {
+            issuer: "https://idp.example.com".into(),
+            client_id: "mug-club".into(),
+            client_secret: Some("secret".into()),
+            redirect_uri: "https://mug.club/login/oidc".into(),
+            scopes: "openid".into(),
+            lifetime: Duration::minutes(10),
+        })
+    }
+
+    fn id_token(claims: serde_json::Value) -> String {
+        jsonwebtoken::encode(
+            &Header::new(Algorithm::HS256),
+            &claims,
+            &EncodingKey::from_secret(b"secret"),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_code_challenge() {
+        // From RFC 7636, Appendix B
+        assert_eq!(
+            "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM",
+            code_challenge("dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk")
+        );
+    }
+
+    #[test]
+    fn test_validate_id_token() {
+        let client = client();
+        let key = client.decoding_key(&Header::new(Algorithm::HS256)).unwrap();
+        let exp = (chrono::Utc::now() + Duration::minutes(5)).timestamp();
+
+        let valid = id_token(serde_json::json!({
+            "iss": "https://idp.example.com",
+            "aud": "mug-club",
+            "sub": "1234",
+            "nonce": "abc",
+            "exp": exp,
+        }));
+        let claims = client
+            .validate_id_token(&valid, Algorithm::HS256, &key, "abc")
+            .unwrap();
+
+        assert_eq!("https://idp.example.com|1234", claims.identifier());
+        assert!(client
+            .validate_id_token(&valid, Algorithm::HS256, &key, "xyz")
+            .is_err());
+
+        let other_audience = id_token(serde_json::json!({
+            "iss": "https://idp.example.com",
+            "aud": "someone-else",
+            "sub": "1234",
+            "nonce": "abc",
+            "exp": exp,
+        }));
+        assert!(client
+            .validate_id_token(&other_audience, Algorithm::HS256, &key, "abc")
+            .is_err());
+    }
+}
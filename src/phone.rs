@@ -0,0 +1,359 @@
+//! Phone number normalization.
+//!
+//! Phone numbers are identities, so the same phone must always produce the same
+//! identifier no matter how it was typed. Numbers are normalized to E.164,
+//! e.g. `+15552345678`, after validating the length of the national number.
+
+use std::fmt;
+use std::str::FromStr;
+
+/// The numbering rules for a single country calling code.
+struct NumberingPlan {
+    country_code: u16,
+
+    /// The digit dialed before national numbers within the country, e.g. `0` in the UK.
+    trunk_prefix: Option<char>,
+
+    /// The allowed lengths of the national significant number.
+    min_length: usize,
+    max_length: usize,
+}
+
+const fn plan(
+    country_code: u16,
+    trunk_prefix: Option<char>,
+    min_length: usize,
+    max_length: usize,
+) -> NumberingPlan {
+    NumberingPlan {
+        country_code,
+        trunk_prefix,
+        min_length,
+        max_length,
+    }
+}
+
+/// Numbering plans for the countries we know the rules for.
+///
+/// Numbers for other country codes are only checked against the overall E.164 limits.
+const NUMBERING_PLANS: &[NumberingPlan] = &[
+    plan(1, None, 10, 10),       // North American Numbering Plan
+    plan(7, None, 10, 10),       // Russia, Kazakhstan
+    plan(20, Some('0'), 8, 10),  // Egypt
+    plan(27, Some('0'), 9, 9),   // South Africa
+    plan(30, None, 10, 10),      // Greece
+    plan(31, Some('0'), 9, 9),   // Netherlands
+    plan(32, Some('0'), 8, 9),   // Belgium
+    plan(33, Some('0'), 9, 9),   // France
+    plan(34, None, 9, 9),        // Spain
+    plan(39, None, 6, 11),       // Italy
+    plan(41, Some('0'), 9, 9),   // Switzerland
+    plan(43, Some('0'), 4, 13),  // Austria
+    plan(44, Some('0'), 9, 10),  // United Kingdom
+    plan(45, None, 8, 8),        // Denmark
+    plan(46, Some('0'), 7, 13),  // Sweden
+    plan(47, None, 8, 8),        // Norway
+    plan(48, None, 9, 9),        // Poland
+    plan(49, Some('0'), 6, 13),  // Germany
+    plan(52, None, 10, 10),      // Mexico
+    plan(55, Some('0'), 10, 11), // Brazil
+    plan(61, Some('0'), 9, 9),   // Australia
+    plan(64, Some('0'), 8, 10),  // New Zealand
+    plan(81, Some('0'), 9, 10),  // Japan
+    plan(82, Some('0'), 8, 10),  // South Korea
+    plan(86, Some('0'), 10, 11), // China
+    plan(91, Some('0'), 10, 10), // India
+    plan(351, None, 9, 9),       // Portugal
+    plan(353, Some('0'), 7, 9),  // Ireland
+    plan(358, Some('0'), 5, 12), // Finland
+];
+
+/// The maximum number of digits in an E.164 number, including the country code.
+const MAX_E164_DIGITS: usize = 15;
+
+/// The shortest national number accepted for countries without a known numbering plan.
+const MIN_NATIONAL_DIGITS: usize = 4;
+
+#[derive(Debug, PartialEq)]
+pub enum PhoneError {
+    /// Country calling codes are one to three digits.
+    InvalidCountryCode(u16),
+
+    /// The number contained something other than digits and common separators.
+    InvalidCharacters,
+
+    /// The number had a leading `+` but didn't start with the given country code.
+    CountryCodeMismatch,
+
+    /// The national number was too short or too long.
+    InvalidLength,
+
+    /// The national number can't be dialed, such as a North American area code
+    /// starting with 0 or 1.
+    InvalidNumber,
+}
+
+impl fmt::Display for PhoneError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PhoneError::InvalidCountryCode(code) => write!(f, "Invalid country code {}", code),
+            PhoneError::InvalidCharacters => write!(f, "Phone numbers may only contain digits"),
+            PhoneError::CountryCodeMismatch => {
+                write!(f, "Phone number does not match the country code")
+            }
+            PhoneError::InvalidLength => write!(f, "Phone number is the wrong length"),
+            PhoneError::InvalidNumber => write!(f, "Phone number is not a valid number"),
+        }
+    }
+}
+
+/// A validated phone number.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PhoneNumber {
+    country_code: u16,
+    national_number: String,
+}
+
+impl PhoneNumber {
+    /// Parse and validate a phone number as typed by a person.
+    ///
+    /// Spaces, dashes, dots and parentheses are ignored. A national trunk prefix
+    /// (such as the leading `0` of UK numbers) is removed, and the number may also
+    /// be given in international format starting with `+` and the country code.
+    pub fn parse(country_code: u16, number: &str) -> Result<PhoneNumber, PhoneError> {
+        if country_code == 0 || country_code > 999 {
+            return Err(PhoneError::InvalidCountryCode(country_code));
+        }
+
+        let number = number.trim();
+        let international = number.starts_with('+');
+        let number = number.trim_start_matches('+');
+
+        if !number
+            .chars()
+            .all(|c| c.is_ascii_digit() || " -.()".contains(c))
+        {
+            return Err(PhoneError::InvalidCharacters);
+        }
+
+        let mut digits = number
+            .chars()
+            .filter(|c| c.is_ascii_digit())
+            .collect::<String>();
+
+        if international {
+            let prefix = country_code.to_string();
+
+            if !digits.starts_with(&prefix) {
+                return Err(PhoneError::CountryCodeMismatch);
+            }
+
+            digits = digits[prefix.len()..].to_string();
+        }
+
+        let country_digits = country_code.to_string().len();
+        let plan = NUMBERING_PLANS
+            .iter()
+            .find(|plan| plan.country_code == country_code);
+
+        let national_number = match plan {
+            Some(plan) => {
+                let national_number = match plan.trunk_prefix {
+                    Some(trunk) if !international && digits.starts_with(trunk) => &digits[1..],
+                    _ => &digits[..],
+                };
+
+                if national_number.len() < plan.min_length
+                    || national_number.len() > plan.max_length
+                {
+                    return Err(PhoneError::InvalidLength);
+                }
+
+                national_number.to_string()
+            }
+            None => {
+                if digits.len() < MIN_NATIONAL_DIGITS
+                    || country_digits + digits.len() > MAX_E164_DIGITS
+                {
+                    return Err(PhoneError::InvalidLength);
+                }
+
+                digits
+            }
+        };
+
+        // North American area codes never start with 0 or 1
+        if country_code == 1 && national_number.starts_with(|c| c == '0' || c == '1') {
+            return Err(PhoneError::InvalidNumber);
+        }
+
+        Ok(PhoneNumber {
+            country_code,
+            national_number,
+        })
+    }
+
+    /// Parse a phone identity saved before numbers were normalized, which is
+    /// the country code followed by the number exactly as it was typed.
+    ///
+    /// Country calling codes are prefix-free, so a known numbering plan whose
+    /// code starts the identifier is the one it was saved with. Otherwise the
+    /// country code's length is unknown, but any split that parses gives the
+    /// same E.164 number. Returns `None` if the identifier doesn't parse.
+    pub fn parse_legacy(identifier: &str) -> Option<PhoneNumber> {
+        let known = NUMBERING_PLANS
+            .iter()
+            .map(|plan| plan.country_code.to_string())
+            .find(|code| identifier.starts_with(code.as_str()));
+
+        match known {
+            Some(code) => {
+                PhoneNumber::parse(u16::from_str(&code).ok()?, &identifier[code.len()..]).ok()
+            }
+            None => (1..=3).find_map(|length| {
+                let country_code = u16::from_str(identifier.get(..length)?).ok()?;
+
+                PhoneNumber::parse(country_code, &identifier[length..]).ok()
+            }),
+        }
+    }
+
+    pub fn country_code(&self) -> u16 {
+        self.country_code
+    }
+
+    /// The national significant number, without any trunk prefix.
+    pub fn national_number(&self) -> &str {
+        &self.national_number
+    }
+
+    /// The number in E.164 format, which is used as its identifier.
+    pub fn e164(&self) -> String {
+        format!("+{}{}", self.country_code, self.national_number)
+    }
+}
+
+impl fmt::Display for PhoneNumber {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.e164())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_formatting_is_ignored() {
+        let canonical = PhoneNumber::parse(1, "5552345678").unwrap();
+
+        for number in &[
+            "555-234-5678",
+            "(555) 234-5678",
+            "555.234.5678",
+            " 555 234 5678 ",
+        ] {
+            assert_eq!(canonical, PhoneNumber::parse(1, number).unwrap());
+        }
+
+        assert_eq!("+15552345678", canonical.e164());
+        assert_eq!("5552345678", canonical.national_number());
+    }
+
+    #[test]
+    fn test_international_format() {
+        assert_eq!(
+            "+447700900123",
+            PhoneNumber::parse(44, "+44 7700 900123").unwrap().e164()
+        );
+        assert_eq!(
+            Err(PhoneError::CountryCodeMismatch),
+            PhoneNumber::parse(1, "+44 7700 900123")
+        );
+    }
+
+    #[test]
+    fn test_trunk_prefix() {
+        assert_eq!(
+            PhoneNumber::parse(44, "07700 900123").unwrap(),
+            PhoneNumber::parse(44, "7700 900123").unwrap()
+        );
+        assert_eq!(
+            "+61412345678",
+            PhoneNumber::parse(61, "0412 345 678").unwrap().e164()
+        );
+    }
+
+    #[test]
+    fn test_lengths() {
+        assert_eq!(
+            Err(PhoneError::InvalidLength),
+            PhoneNumber::parse(1, "555-2345")
+        );
+        assert_eq!(
+            Err(PhoneError::InvalidLength),
+            PhoneNumber::parse(1, "555-234-56789")
+        );
+        assert_eq!(
+            Err(PhoneError::InvalidLength),
+            PhoneNumber::parse(33, "06 12 34 56")
+        );
+        assert!(PhoneNumber::parse(33, "06 12 34 56 78").is_ok());
+    }
+
+    #[test]
+    fn test_north_american_numbers() {
+        assert_eq!(
+            PhoneNumber::parse(1, "5551234567").unwrap(),
+            PhoneNumber::parse(1, "555-123-4567").unwrap()
+        );
+        assert_eq!(
+            Err(PhoneError::InvalidNumber),
+            PhoneNumber::parse(1, "055-234-5678")
+        );
+        assert_eq!(
+            Err(PhoneError::InvalidNumber),
+            PhoneNumber::parse(1, "155-234-5678")
+        );
+    }
+
+    #[test]
+    fn test_legacy_identifiers() {
+        let legacy = |identifier| PhoneNumber::parse_legacy(identifier).map(|number| number.e164());
+
+        assert_eq!(Some("+15551234567".into()), legacy("1(555) 123-4567"));
+        assert_eq!(Some("+447700900123".into()), legacy("4407700 900123"));
+        assert_eq!(Some("+447700900123".into()), legacy("44+44 7700 900123"));
+        assert_eq!(Some("+2341234567890".into()), legacy("2341234567890"));
+        assert_eq!(None, legacy("1055-234-5678"));
+        assert_eq!(None, legacy("1555"));
+    }
+
+    #[test]
+    fn test_unknown_country() {
+        assert_eq!(
+            "+2341234567890",
+            PhoneNumber::parse(234, "1234567890").unwrap().e164()
+        );
+        assert_eq!(
+            Err(PhoneError::InvalidLength),
+            PhoneNumber::parse(234, "123")
+        );
+        assert_eq!(
+            Err(PhoneError::InvalidLength),
+            PhoneNumber::parse(234, "1234567890123")
+        );
+        assert_eq!(
+            Err(PhoneError::InvalidCountryCode(0)),
+            PhoneNumber::parse(0, "5552345678")
+        );
+    }
+
+    #[test]
+    fn test_invalid_characters() {
+        assert_eq!(
+            Err(PhoneError::InvalidCharacters),
+            PhoneNumber::parse(1, "555-CALL-NOW")
+        );
+    }
+}
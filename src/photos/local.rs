@@ -0,0 +1,92 @@
+use std::fs;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+
+use super::BlobStore;
+use crate::error::{Error, Result};
+
+/// Keeps every blob as a file in a directory.
+pub struct LocalStore {
+    directory: PathBuf,
+}
+
+impl LocalStore {
+    pub fn new<P: Into<PathBuf>>(directory: P) -> LocalStore {
+        LocalStore {
+            directory: directory.into(),
+        }
+    }
+
+    /// The file a key is kept in, refusing keys that could reach outside the directory.
+    fn path(&self, key: &str) -> Result<PathBuf> {
+        let valid = !key.is_empty()
+            && !key.starts_with('.')
+            && key
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.');
+
+        if valid {
+            Ok(self.directory.join(key))
+        } else {
+            Err(Error::StorageError(format!("Invalid key '{}'", key)))
+        }
+    }
+}
+
+impl BlobStore for LocalStore {
+    fn put(&self, key: &str, bytes: &[u8]) -> Result<()> {
+        let path = self.path(key)?;
+
+        fs::create_dir_all(&self.directory).map_err(storage_error)?;
+        fs::write(&path, bytes).map_err(storage_error)
+    }
+
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        // A key that could never have been put can't be found either
+        let path = match self.path(key) {
+            Ok(path) => path,
+            Err(_) => return Ok(None),
+        };
+
+        match fs::read(path) {
+            Ok(bytes) => Ok(Some(bytes)),
+            Err(ref e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(storage_error(e)),
+        }
+    }
+
+    fn delete(&self, key: &str) -> Result<()> {
+        match fs::remove_file(self.path(key)?) {
+            Ok(()) => Ok(()),
+            Err(ref e) if e.kind() == ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(storage_error(e)),
+        }
+    }
+}
+
+fn storage_error(e: std::io::Error) -> Error {
+    Error::StorageError(e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_local_store() {
+        let directory = std::env::temp_dir().join(format!("photos-{}", std::process::id()));
+        let store = LocalStore::new(&directory);
+
+        store.put("abc123.jpg", b"photo").unwrap();
+        assert_eq!(Some(b"photo".to_vec()), store.get("abc123.jpg").unwrap());
+
+        store.delete("abc123.jpg").unwrap();
+        assert_eq!(None, store.get("abc123.jpg").unwrap());
+        assert!(store.delete("abc123.jpg").is_ok());
+
+        assert_eq!(None, store.get("../secrets").unwrap());
+        assert!(store.put("nested/key.jpg", b"photo").is_err());
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,399 @@
+//! Photos attached to drinks, and uploaded avatars.
+//!
+//! Uploads are decoded and re-encoded as JPEG before they are kept, which
+//! throws away EXIF metadata such as where the photo was taken. A smaller
+//! thumbnail is made alongside each photo, and avatars are cropped square.
+//!
+//! The encoded images live in a `BlobStore`, selected through the
+//! `PHOTO_STORE` variable, and are served back out under `/photos/{key}`.
+//! A drink's photos are only served to whoever can see the drink, while
+//! avatars are served to anyone. Keys are long and random, so they can't be
+//! guessed.
+
+use std::io::Cursor;
+use std::sync::Arc;
+
+use actix_web::web;
+use futures::future::Future;
+use futures::prelude::*;
+use image::imageops::FilterType;
+use image::{DynamicImage, GenericImageView, ImageOutputFormat};
+
+use crate::error::{Error, Result};
+use crate::token;
+
+mod local;
+
+pub use self::local::LocalStore;
+
+/// The largest upload accepted, in bytes.
+pub const MAX_UPLOAD_BYTES: usize = 10 * 1024 * 1024;
+
+/// The most pixels an upload may have, which keeps decoding it to a sane
+/// amount of memory; a small file can still claim to be a huge image.
+const MAX_PIXELS: u64 = 40_000_000;
+
+/// Photos are scaled down to fit within this many pixels on each side.
+const MAX_DIMENSION: u32 = 2048;
+
+const THUMBNAIL_DIMENSION: u32 = 320;
+
+/// Avatars are cropped square and scaled to this many pixels on each side.
+const AVATAR_DIMENSION: u32 = 256;
+
+const JPEG_QUALITY: u8 = 85;
+
+pub trait BlobStore: Send + Sync {
+    /// Keep `bytes` under `key`, replacing anything already there.
+    fn put(&self, key: &str, bytes: &[u8]) -> Result<()>;
+
+    /// Fetch what is kept under `key`, if anything.
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>>;
+
+    /// Remove what is kept under `key`. Removing a missing key is not an error.
+    fn delete(&self, key: &str) -> Result<()>;
+}
+
+/// The shared store handle stored as application data.
+pub type Store = Arc<dyn BlobStore>;
+
+/// Build the blob store selected by the `PHOTO_STORE` variable.
+///
+/// - `local` (the default) keeps photos as files in `PHOTO_DIRECTORY`, which
+///   defaults to `photos`.
+pub fn from_env() -> Store {
+    match std::env::var("PHOTO_STORE")
+        .unwrap_or("local".into())
+        .as_str()
+    {
+        "local" => Arc::new(LocalStore::new(
+            std::env::var("PHOTO_DIRECTORY").unwrap_or("photos".into()),
+        )),
+        other => panic!("Unknown photo store '{}'!", other),
+    }
+}
+
+/// Where a kept photo or thumbnail can be fetched from.
+pub fn url(key: &str) -> String {
+    format!("/photos/{}", key)
+}
+
+/// A new random key for a photo, and one for its thumbnail.
+pub fn new_keys() -> (String, String) {
+    let key = token::generate_hex(16);
+
+    (format!("{}.jpg", key), format!("{}-thumb.jpg", key))
+}
+
+/// A new random key for an avatar.
+pub fn new_avatar_key() -> String {
+    format!("{}-avatar.jpg", token::generate_hex(16))
+}
+
+/// An uploaded photo, ready to be kept.
+pub struct Processed {
+    pub photo: Vec<u8>,
+    pub thumbnail: Vec<u8>,
+    pub width: i32,
+    pub height: i32,
+}
+
+/// Decode an uploaded image and re-encode it, and a thumbnail of it, as JPEG.
+///
+/// Fails with a message for the person if the upload isn't an image.
+pub fn process(bytes: &[u8]) -> std::result::Result<Processed, String> {
+    let mut photo = decode(bytes)?;
+
+    if photo.width() > MAX_DIMENSION || photo.height() > MAX_DIMENSION {
+        photo = photo.resize(MAX_DIMENSION, MAX_DIMENSION, FilterType::Lanczos3);
+    }
+
+    let thumbnail = photo.thumbnail(THUMBNAIL_DIMENSION, THUMBNAIL_DIMENSION);
+
+    Ok(Processed {
+        width: photo.width() as i32,
+        height: photo.height() as i32,
+        photo: jpeg(&photo)?,
+        thumbnail: jpeg(&thumbnail)?,
+    })
+}
+
+/// Decode an uploaded avatar and re-encode it as a square JPEG, cropping off
+/// whatever doesn't fit around the middle.
+pub fn process_avatar(bytes: &[u8]) -> std::result::Result<Vec<u8>, String> {
+    let avatar =
+        decode(bytes)?.resize_to_fill(AVATAR_DIMENSION, AVATAR_DIMENSION, FilterType::Lanczos3);
+
+    jpeg(&avatar)
+}
+
+/// Decode an uploaded image, turning it upright according to its EXIF
+/// orientation, as the metadata saying which way up it goes doesn't survive
+/// re-encoding.
+///
+/// The size of the image is read from its header first, so that anything
+/// larger than `MAX_PIXELS` is refused before it is decoded.
+fn decode(bytes: &[u8]) -> std::result::Result<DynamicImage, String> {
+    let not_an_image = |_| "Images must be JPEG, PNG, GIF or WebP".to_string();
+
+    let (width, height) = image::io::Reader::new(Cursor::new(bytes))
+        .with_guessed_format()
+        .map_err(|e| e.to_string())?
+        .into_dimensions()
+        .map_err(not_an_image)?;
+
+    if u64::from(width) * u64::from(height) > MAX_PIXELS {
+        return Err(format!(
+            "Images can be at most {} megapixels",
+            MAX_PIXELS / 1_000_000
+        ));
+    }
+
+    let decoded = image::load_from_memory(bytes).map_err(not_an_image)?;
+
+    Ok(upright(decoded, orientation(bytes)))
+}
+
+/// The EXIF orientation of an image, from 1 to 8, which is 1 if it has none.
+fn orientation(bytes: &[u8]) -> u32 {
+    exif::Reader::new()
+        .read_from_container(&mut Cursor::new(bytes))
+        .ok()
+        .and_then(|exif| {
+            exif.get_field(exif::Tag::Orientation, exif::In::PRIMARY)
+                .and_then(|field| field.value.get_uint(0))
+        })
+        .unwrap_or(1)
+}
+
+/// Turn an image upright given its EXIF orientation.
+fn upright(image: DynamicImage, orientation: u32) -> DynamicImage {
+    match orientation {
+        2 => image.fliph(),
+        3 => image.rotate180(),
+        4 => image.flipv(),
+        5 => image.rotate90().fliph(),
+        6 => image.rotate90(),
+        7 => image.rotate270().fliph(),
+        8 => image.rotate270(),
+        _ => image,
+    }
+}
+
+fn jpeg(image: &DynamicImage) -> std::result::Result<Vec<u8>, String> {
+    let mut encoded = Vec::new();
+
+    image
+        .write_to(&mut encoded, ImageOutputFormat::Jpeg(JPEG_QUALITY))
+        .map_err(|e| format!("Unable to encode photo: {}", e))?;
+
+    Ok(encoded)
+}
+
+/// Run `process` or `process_avatar` on the blocking thread pool.
+pub fn process_upload<T: Send + 'static>(
+    bytes: Vec<u8>,
+    process: fn(&[u8]) -> std::result::Result<T, String>,
+) -> impl Future<Output = Result<std::result::Result<T, String>>> {
+    use actix_web::error::BlockingError;
+    use futures::channel::oneshot::Canceled;
+
+    web::block(move || Ok::<_, Error>(process(&bytes))).map(|res| match res {
+        Ok(r) => Ok(r),
+        Err(BlockingError::Error(e)) => Err(e),
+        Err(BlockingError::Canceled) => Err(Error::from(Canceled)),
+    })
+}
+
+/// Run `BlobStore::put` for each of `blobs` on the blocking thread pool.
+pub fn put(store: &Store, blobs: Vec<(String, Vec<u8>)>) -> impl Future<Output = Result<()>> {
+    use actix_web::error::BlockingError;
+    use futures::channel::oneshot::Canceled;
+
+    let store = store.clone();
+
+    web::block(move || {
+        blobs
+            .iter()
+            .map(|(key, bytes)| store.put(key, bytes))
+            .collect::<Result<()>>()
+    })
+    .map(|res| match res {
+        Ok(r) => Ok(r),
+        Err(BlockingError::Error(e)) => Err(e),
+        Err(BlockingError::Canceled) => Err(Error::from(Canceled)),
+    })
+}
+
+/// Run `BlobStore::get` on the blocking thread pool.
+pub fn get(store: &Store, key: String) -> impl Future<Output = Result<Option<Vec<u8>>>> {
+    use actix_web::error::BlockingError;
+    use futures::channel::oneshot::Canceled;
+
+    let store = store.clone();
+
+    web::block(move || store.get(&key)).map(|res| match res {
+        Ok(r) => Ok(r),
+        Err(BlockingError::Error(e)) => Err(e),
+        Err(BlockingError::Canceled) => Err(Error::from(Canceled)),
+    })
+}
+
+/// Remove `keys` from the store on the blocking thread pool.
+///
+/// Their database rows are already gone by the time this is called, so
+/// failures are only logged; a leftover blob is unreachable but harmless.
+pub async fn remove(store: &Store, keys: Vec<String>) {
+    let store = store.clone();
+
+    let removed = web::block(move || {
+        for key in &keys {
+            if let Err(e) = store.delete(key) {
+                error!("Unable to remove photo '{}'! Error: {}", key, e);
+            }
+        }
+
+        Ok::<_, Error>(())
+    })
+    .await;
+
+    if removed.is_err() {
+        error!("Unable to remove photos!");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage};
+
+    /// Encode a blank image of the given size as PNG.
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let mut encoded = Vec::new();
+
+        DynamicImage::ImageRgb8(RgbImage::new(width, height))
+            .write_to(&mut encoded, ImageOutputFormat::Png)
+            .unwrap();
+
+        encoded
+    }
+
+    /// Encode a black 400x300 image with a white square in its top left corner
+    /// as JPEG, tagged with the given EXIF orientation.
+    fn jpeg_with_orientation(orientation: u8) -> Vec<u8> {
+        let marked = RgbImage::from_fn(400, 300, |x, y| match (x, y) {
+            (0..=99, 0..=99) => Rgb([255, 255, 255]),
+            _ => Rgb([0, 0, 0]),
+        });
+        let encoded = jpeg(&DynamicImage::ImageRgb8(marked)).unwrap();
+
+        // A big-endian TIFF header, then an IFD with only the orientation in it
+        let mut exif = b"Exif\0\0MM\0\x2a\0\0\0\x08\0\x01\x01\x12\0\x03\0\0\0\x01".to_vec();
+        exif.extend_from_slice(&[0, orientation, 0, 0, 0, 0, 0, 0]);
+
+        // Insert it as an APP1 segment straight after the start of image marker
+        let mut tagged = encoded[..2].to_vec();
+        tagged.extend_from_slice(&[0xff, 0xe1]);
+        tagged.extend_from_slice(&(exif.len() as u16 + 2).to_be_bytes());
+        tagged.extend_from_slice(&exif);
+        tagged.extend_from_slice(&encoded[2..]);
+
+        tagged
+    }
+
+    fn is_white(image: &DynamicImage, x: u32, y: u32) -> bool {
+        image.get_pixel(x, y).0[0] > 128
+    }
+
+    #[test]
+    fn test_orientation() {
+        assert_eq!(6, orientation(&jpeg_with_orientation(6)));
+        assert_eq!(1, orientation(&png(4, 3)));
+        assert_eq!(1, orientation(b"not an image"));
+    }
+
+    #[test]
+    fn test_upright() {
+        // White on the left, black on the right
+        let image = DynamicImage::ImageRgb8(RgbImage::from_fn(2, 1, |x, _| match x {
+            0 => Rgb([255, 255, 255]),
+            _ => Rgb([0, 0, 0]),
+        }));
+
+        let turned = upright(image.clone(), 6);
+        assert_eq!((1, 2), turned.dimensions());
+        assert!(is_white(&turned, 0, 0));
+
+        let turned = upright(image.clone(), 8);
+        assert_eq!((1, 2), turned.dimensions());
+        assert!(is_white(&turned, 0, 1));
+
+        assert!(is_white(&upright(image.clone(), 2), 1, 0));
+        assert!(is_white(&upright(image.clone(), 3), 1, 0));
+        assert!(is_white(&upright(image, 1), 0, 0));
+    }
+
+    #[test]
+    fn test_process() {
+        let processed = process(&jpeg_with_orientation(6)).unwrap();
+
+        // Turned a quarter clockwise, which puts the white square top right
+        assert_eq!((300, 400), (processed.width, processed.height));
+
+        let photo = image::load_from_memory(&processed.photo).unwrap();
+        assert_eq!((300, 400), photo.dimensions());
+        assert!(is_white(&photo, 250, 50));
+        assert!(!is_white(&photo, 50, 50));
+
+        // Nothing is left to say which way up it goes, or anything else
+        assert!(exif::Reader::new()
+            .read_from_container(&mut Cursor::new(&processed.photo))
+            .is_err());
+
+        let thumbnail = image::load_from_memory(&processed.thumbnail).unwrap();
+        assert_eq!((240, THUMBNAIL_DIMENSION), thumbnail.dimensions());
+
+        assert!(process(b"not an image").is_err());
+    }
+
+    #[test]
+    fn test_process_too_many_pixels() {
+        // Claim to be 8000x8000 in the header, without the pixels to match
+        let mut huge = png(1, 1);
+        huge[16..24].copy_from_slice(&[0, 0, 0x1f, 0x40, 0, 0, 0x1f, 0x40]);
+
+        let crc = crc32(&huge[12..29]);
+        huge[29..33].copy_from_slice(&crc.to_be_bytes());
+
+        assert_eq!(
+            Some("Images can be at most 40 megapixels".to_string()),
+            process(&huge).err()
+        );
+    }
+
+    /// The CRC of a PNG chunk.
+    fn crc32(bytes: &[u8]) -> u32 {
+        let mut crc = !0u32;
+
+        for &byte in bytes {
+            crc ^= u32::from(byte);
+
+            for _ in 0..8 {
+                crc = match crc & 1 {
+                    1 => (crc >> 1) ^ 0xedb8_8320,
+                    _ => crc >> 1,
+                };
+            }
+        }
+
+        !crc
+    }
+
+    #[test]
+    fn test_process_avatar() {
+        let avatar = image::load_from_memory(&process_avatar(&png(400, 300)).unwrap()).unwrap();
+
+        assert_eq!((AVATAR_DIMENSION, AVATAR_DIMENSION), avatar.dimensions());
+        assert!(process_avatar(b"not an image").is_err());
+    }
+}
@@ -0,0 +1,118 @@
+//! Checking the profile details that people choose for themselves.
+//!
+//! Each function takes what the person submitted and returns the value to
+//! store, or a message explaining what was wrong with it.
+
+use std::fmt;
+use std::str::FromStr;
+
+use chrono_tz::Tz;
+use regex::Regex;
+
+/// The units a person prefers volumes and the like to be shown in.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Units {
+    Metric,
+    Imperial,
+}
+
+impl Units {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Units::Metric => "metric",
+            Units::Imperial => "imperial",
+        }
+    }
+}
+
+impl fmt::Display for Units {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Units {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Units, String> {
+        match s.trim().to_lowercase().as_str() {
+            "metric" => Ok(Units::Metric),
+            "imperial" => Ok(Units::Imperial),
+            _ => Err(format!(
+                "Unknown units '{}', expected metric or imperial",
+                s
+            )),
+        }
+    }
+}
+
+/// A handle, which is matched case-insensitively and may be given with a leading `@`.
+pub fn handle(handle: &str) -> Result<String, String> {
+    lazy_static! {
+        static ref HANDLE: Regex = Regex::new("^[a-z0-9_]{3,32}$").unwrap();
+    }
+
+    let handle = handle.trim().trim_start_matches('@').to_lowercase();
+
+    if HANDLE.is_match(&handle) {
+        Ok(handle)
+    } else {
+        Err("Handles must be 3 to 32 letters, digits or underscores".into())
+    }
+}
+
+pub fn display_name(name: &str) -> Result<String, String> {
+    limited(name, 64, "Display names")
+}
+
+pub fn bio(bio: &str) -> Result<String, String> {
+    limited(bio, 500, "Bios")
+}
+
+/// An avatar hosted elsewhere, rather than uploaded.
+pub fn avatar_url(url: &str) -> Result<String, String> {
+    let invalid = || "Avatars must be an http or https URL".to_string();
+    let parsed = reqwest::Url::parse(url.trim()).map_err(|_| invalid())?;
+
+    match parsed.scheme() {
+        "http" | "https" if parsed.as_str().len() <= 2048 => Ok(parsed.to_string()),
+        _ => Err(invalid()),
+    }
+}
+
+/// An IANA timezone name, such as `America/Chicago`.
+pub fn timezone(timezone: &str) -> Result<String, String> {
+    Tz::from_str(timezone.trim())
+        .map(|tz| tz.name().to_string())
+        .map_err(|_| format!("Unknown timezone '{}'", timezone))
+}
+
+fn limited(value: &str, max: usize, what: &str) -> Result<String, String> {
+    let value = value.trim();
+
+    if value.chars().count() <= max {
+        Ok(value.to_string())
+    } else {
+        Err(format!("{} can be at most {} characters", what, max))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_handle() {
+        assert_eq!(Ok("hop_head".into()), handle(" @Hop_Head "));
+        assert!(handle("ab").is_err());
+        assert!(handle("hop head").is_err());
+        assert!(handle(&"a".repeat(33)).is_err());
+    }
+
+    #[test]
+    fn test_timezone() {
+        assert_eq!(Ok("America/Chicago".into()), timezone("America/Chicago"));
+        assert_eq!(Ok("UTC".into()), timezone("UTC"));
+        assert!(timezone("America/Springfield").is_err());
+    }
+}
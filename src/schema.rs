@@ -1,3 +1,38 @@
+table! {
+    api_token (id) {
+        id -> Int4,
+        person_id -> Int4,
+        name -> Varchar,
+        digest -> Bpchar,
+        scopes -> Array<Text>,
+        last_used_at -> Nullable<Timestamptz>,
+        created_at -> Timestamptz,
+        updated_at -> Timestamptz,
+    }
+}
+
+table! {
+    auth_event (id) {
+        id -> Int4,
+        kind -> Varchar,
+        person_id -> Nullable<Int4>,
+        identity_kind -> Nullable<Varchar>,
+        identifier -> Nullable<Varchar>,
+        ip_address -> Nullable<Varchar>,
+        user_agent -> Nullable<Varchar>,
+        detail -> Nullable<Varchar>,
+        created_at -> Timestamptz,
+    }
+}
+
+table! {
+    auth_throttle (key) {
+        key -> Text,
+        hits -> Int4,
+        resets_at -> Timestamptz,
+    }
+}
+
 table! {
     beer (id) {
         id -> Int4,
@@ -23,19 +58,58 @@ table! {
         person_id -> Int4,
         drank_on -> Date,
         beer_id -> Int4,
-        rating -> Int2,
         comment -> Nullable<Varchar>,
         created_at -> Timestamptz,
         updated_at -> Timestamptz,
+        shared -> Bool,
+        drank_at -> Nullable<Timestamptz>,
+        serving -> Nullable<Varchar>,
+        volume_ml -> Nullable<Int4>,
+        price_cents -> Nullable<Int4>,
+        abv -> Nullable<Float4>,
+        venue -> Nullable<Varchar>,
+        rating_quarters -> Int2,
+    }
+}
+
+table! {
+    drink_photo (id) {
+        id -> Int4,
+        drink_id -> Int4,
+        blob_key -> Varchar,
+        thumbnail_key -> Varchar,
+        width -> Int4,
+        height -> Int4,
+        created_at -> Timestamptz,
+        updated_at -> Timestamptz,
     }
 }
 
 table! {
-    identity (identifier) {
+    email_challenge (id) {
+        id -> Int4,
+        email -> Varchar,
+        digest -> Bpchar,
+        created_at -> Timestamptz,
+        expires_at -> Timestamptz,
+        consumed_at -> Nullable<Timestamptz>,
+    }
+}
+
+table! {
+    flavor_descriptor (id) {
+        id -> Int4,
+        name -> Varchar,
+    }
+}
+
+table! {
+    identity (kind, identifier) {
         identifier -> Varchar,
         person_id -> Int4,
         created_at -> Timestamptz,
         updated_at -> Timestamptz,
+        kind -> Varchar,
     }
 }
 
@@ -46,6 +120,20 @@ table! {
         created_at -> Timestamptz,
         updated_at -> Timestamptz,
         expires_at -> Timestamptz,
+        public_id -> Int4,
+        last_used_at -> Nullable<Timestamptz>,
+        device_label -> Nullable<Varchar>,
+        user_agent -> Nullable<Varchar>,
+    }
+}
+
+table! {
+    oidc_login (state) {
+        state -> Bpchar,
+        nonce -> Varchar,
+        code_verifier -> Varchar,
+        created_at -> Timestamptz,
+        expires_at -> Timestamptz,
     }
 }
 
@@ -54,13 +142,64 @@ table! {
         id -> Int4,
         created_at -> Timestamptz,
         updated_at -> Timestamptz,
+        role -> Varchar,
+        deletion_requested_at -> Nullable<Timestamptz>,
+        display_name -> Nullable<Varchar>,
+        handle -> Nullable<Varchar>,
+        bio -> Nullable<Varchar>,
+        avatar_url -> Nullable<Varchar>,
+        timezone -> Varchar,
+        units -> Varchar,
+        avatar_key -> Nullable<Varchar>,
+    }
+}
+
+table! {
+    tasting_note (drink_id) {
+        drink_id -> Int4,
+        appearance -> Nullable<Int2>,
+        aroma -> Nullable<Int2>,
+        flavor -> Nullable<Int2>,
+        mouthfeel -> Nullable<Int2>,
+        overall -> Nullable<Int2>,
+        created_at -> Timestamptz,
+        updated_at -> Timestamptz,
+    }
+}
+
+table! {
+    tasting_note_descriptor (drink_id, descriptor_id) {
+        drink_id -> Int4,
+        descriptor_id -> Int4,
     }
 }
 
+joinable!(api_token -> person (person_id));
+joinable!(auth_event -> person (person_id));
 joinable!(beer -> brewery (brewery_id));
 joinable!(drink -> beer (beer_id));
 joinable!(drink -> person (person_id));
+joinable!(drink_photo -> drink (drink_id));
 joinable!(identity -> person (person_id));
 joinable!(login_session -> person (person_id));
+joinable!(tasting_note -> drink (drink_id));
+joinable!(tasting_note_descriptor -> flavor_descriptor (descriptor_id));
+joinable!(tasting_note_descriptor -> tasting_note (drink_id));
 
-allow_tables_to_appear_in_same_query!(beer, brewery, drink, identity, login_session, person,);
+allow_tables_to_appear_in_same_query!(
+    api_token,
+    auth_event,
+    auth_throttle,
+    beer,
+    brewery,
+    drink,
+    drink_photo,
+    email_challenge,
+    flavor_descriptor,
+    identity,
+    login_session,
+    oidc_login,
+    person,
+    tasting_note,
+    tasting_note_descriptor,
+);
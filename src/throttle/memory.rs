@@ -0,0 +1,109 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use chrono::{Duration, Utc};
+
+use super::{ThrottleStore, Window};
+use crate::error::Result;
+
+/// Keeps rate limit windows in the process.
+///
+/// Limits are lost on restart and aren't shared between instances, so this is
+/// meant for development and single-instance deployments.
+pub struct MemoryStore {
+    windows: Mutex<HashMap<String, Window>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> MemoryStore {
+        MemoryStore {
+            windows: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl ThrottleStore for MemoryStore {
+    fn hit(&self, key: &str, window: Duration) -> Result<Window> {
+        let now = Utc::now();
+        let mut windows = self.windows.lock().unwrap();
+
+        let open = windows
+            .get(key)
+            .filter(|open| open.resets_at > now)
+            .cloned()
+            .unwrap_or(Window {
+                hits: 0,
+                resets_at: now + window,
+            });
+
+        let hit = Window {
+            hits: open.hits + 1,
+            ..open
+        };
+        windows.insert(key.to_string(), hit);
+
+        Ok(hit)
+    }
+
+    fn get(&self, key: &str) -> Result<Option<Window>> {
+        let now = Utc::now();
+
+        Ok(self
+            .windows
+            .lock()
+            .unwrap()
+            .get(key)
+            .filter(|window| window.resets_at > now)
+            .cloned())
+    }
+
+    fn clear(&self, key: &str) -> Result<()> {
+        self.windows.lock().unwrap().remove(key);
+
+        Ok(())
+    }
+
+    fn purge(&self) -> Result<usize> {
+        let now = Utc::now();
+        let mut windows = self.windows.lock().unwrap();
+        let before = windows.len();
+
+        windows.retain(|_, window| window.resets_at > now);
+
+        Ok(before - windows.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hits_are_counted_per_key() {
+        let store = MemoryStore::new();
+
+        assert_eq!(1, store.hit("a", Duration::minutes(1)).unwrap().hits);
+        assert_eq!(2, store.hit("a", Duration::minutes(1)).unwrap().hits);
+        assert_eq!(1, store.hit("b", Duration::minutes(1)).unwrap().hits);
+        assert_eq!(2, store.get("a").unwrap().unwrap().hits);
+
+        store.clear("a").unwrap();
+
+        assert_eq!(None, store.get("a").unwrap());
+        assert_eq!(1, store.hit("a", Duration::minutes(1)).unwrap().hits);
+    }
+
+    #[test]
+    fn test_closed_windows_reset() {
+        let store = MemoryStore::new();
+
+        store.hit("a", Duration::zero()).unwrap();
+        store.hit("b", Duration::zero()).unwrap();
+
+        assert_eq!(None, store.get("a").unwrap());
+        assert_eq!(1, store.hit("a", Duration::minutes(1)).unwrap().hits);
+
+        assert_eq!(1, store.purge().unwrap());
+        assert_eq!(1, store.get("a").unwrap().unwrap().hits);
+    }
+}
@@ -0,0 +1,235 @@
+//! Rate limiting for login attempts.
+//!
+//! Every attempt to send or check a verification code is counted against a
+//! set of keys, such as the phone number and the client's IP address. Each key
+//! gets a fixed window that opens on its first hit; once a window has seen more
+//! hits than its limit allows, further attempts are refused until it closes.
+//!
+//! Counters live in a `ThrottleStore`, selected through the `THROTTLE_STORE`
+//! variable, so that limits survive restarts and are shared between instances.
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use actix_web::{web, HttpRequest};
+use chrono::{DateTime, Duration, Utc};
+use futures::future::Future;
+use futures::prelude::*;
+
+use crate::config::{Limit, ThrottleConfig};
+use crate::db::Pool;
+use crate::error::{Error, Result};
+
+mod memory;
+mod postgres;
+
+pub use self::memory::MemoryStore;
+pub use self::postgres::PostgresStore;
+
+/// The state of a key's current window.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Window {
+    pub hits: u32,
+    pub resets_at: DateTime<Utc>,
+}
+
+impl Window {
+    /// Whole seconds until the window closes, rounded up.
+    fn retry_after(&self) -> i64 {
+        let remaining = self.resets_at - Utc::now();
+
+        (remaining.num_milliseconds() + 999).max(1000) / 1000
+    }
+}
+
+pub trait ThrottleStore: Send + Sync {
+    /// Count a hit against `key`, opening a window of length `window` if none is open.
+    fn hit(&self, key: &str, window: Duration) -> Result<Window>;
+
+    /// Look at the open window for `key` without counting a hit.
+    fn get(&self, key: &str) -> Result<Option<Window>>;
+
+    /// Close the window for `key`, if there is one.
+    fn clear(&self, key: &str) -> Result<()>;
+
+    /// Drop every closed window, returning how many there were.
+    fn purge(&self) -> Result<usize>;
+}
+
+/// The shared store handle.
+pub type Store = Arc<dyn ThrottleStore>;
+
+/// Rate limits for the login endpoints, stored as application data.
+#[derive(Clone)]
+pub struct Throttle {
+    store: Store,
+    config: ThrottleConfig,
+}
+
+impl Throttle {
+    /// Build the throttle from the environment.
+    ///
+    /// `THROTTLE_STORE` selects where counters are kept: `postgres` (the default)
+    /// uses the `auth_throttle` table, and `memory` keeps them in the process.
+    /// The limits themselves are read by `ThrottleConfig::from_env`.
+    pub fn from_env(pool: &Pool) -> Throttle {
+        let store: Store = match std::env::var("THROTTLE_STORE")
+            .unwrap_or("postgres".into())
+            .as_str()
+        {
+            "postgres" => Arc::new(PostgresStore::new(pool.clone())),
+            "memory" => {
+                warn!("Using the in-memory throttle store; limits will reset on restart!");
+
+                Arc::new(MemoryStore::new())
+            }
+            other => panic!("Unknown throttle store '{}'!", other),
+        };
+
+        Throttle {
+            store,
+            config: ThrottleConfig::from_env(),
+        }
+    }
+
+    pub fn config(&self) -> &ThrottleConfig {
+        &self.config
+    }
+
+    /// The address of the client that made a request, for use in keys.
+    pub fn client_ip(&self, req: &HttpRequest) -> String {
+        client_ip(req, self.config.trust_forwarded)
+    }
+
+    /// Count an attempt against each key.
+    ///
+    /// Fails with `Error::RateLimited` if any key is over its limit, giving the
+    /// longest time the client has to wait.
+    pub fn attempt(&self, keys: Vec<(String, Limit)>) -> impl Future<Output = Result<()>> {
+        let store = self.store.clone();
+
+        block(move || {
+            let mut retry_after = None;
+
+            for (key, limit) in keys {
+                let window = store.hit(&key, limit.window)?;
+
+                if window.hits > limit.max {
+                    warn!("Rate limit exceeded for '{}'", key);
+
+                    retry_after = retry_after.max(Some(window.retry_after()));
+                }
+            }
+
+            match retry_after {
+                Some(seconds) => Err(Error::RateLimited(seconds)),
+                None => Ok(()),
+            }
+        })
+    }
+
+    /// Fail with `Error::RateLimited` if too many wrong codes have been
+    /// submitted for `key`, without counting an attempt.
+    pub fn check_lockout(&self, key: String) -> impl Future<Output = Result<()>> {
+        let store = self.store.clone();
+        let limit = self.config.failed_codes;
+
+        block(move || match store.get(&key)? {
+            Some(window) if window.hits >= limit.max => {
+                warn!("'{}' is locked out after too many failed codes", key);
+
+                Err(Error::RateLimited(window.retry_after()))
+            }
+            _ => Ok(()),
+        })
+    }
+
+    /// Record a wrong code submitted for `key`.
+    ///
+    /// Returns `true` if this was the failure that locked `key` out.
+    pub fn record_failure(&self, key: String) -> impl Future<Output = Result<bool>> {
+        let store = self.store.clone();
+        let limit = self.config.failed_codes;
+
+        block(move || {
+            store
+                .hit(&key, limit.window)
+                .map(|window| window.hits == limit.max)
+        })
+    }
+
+    /// Forget the failed codes for `key`, after a correct one.
+    pub fn clear(&self, key: String) -> impl Future<Output = Result<()>> {
+        let store = self.store.clone();
+
+        block(move || store.clear(&key))
+    }
+
+    /// Drop the closed windows of every key, so that the store only holds open ones.
+    pub fn purge(&self) -> impl Future<Output = Result<usize>> {
+        let store = self.store.clone();
+
+        block(move || store.purge())
+    }
+}
+
+/// The address of the client that made a request.
+///
+/// The last `X-Forwarded-For` entry, which our proxy appended, is only used if
+/// `trust_forwarded` is set. Any earlier entries were sent by the client, as
+/// is the whole header without a proxy, so they could be anything.
+pub fn client_ip(req: &HttpRequest, trust_forwarded: bool) -> String {
+    let forwarded = req
+        .headers()
+        .get("X-Forwarded-For")
+        .and_then(|header| header.to_str().ok())
+        .and_then(|header| header.rsplit(',').next())
+        .map(|ip| ip.trim().to_string())
+        .filter(|ip| !ip.is_empty());
+
+    match forwarded {
+        Some(ip) if trust_forwarded => ip,
+        _ => req
+            .peer_addr()
+            .map(|addr: SocketAddr| addr.ip().to_string())
+            .unwrap_or("unknown".into()),
+    }
+}
+
+fn block<F, T>(f: F) -> impl Future<Output = Result<T>>
+where
+    F: FnOnce() -> Result<T> + Send + 'static,
+    T: Send + 'static,
+{
+    use actix_web::error::BlockingError;
+    use futures::channel::oneshot::Canceled;
+
+    web::block(f).map(|res| match res {
+        Ok(r) => Ok(r),
+        Err(BlockingError::Error(e)) => Err(e),
+        Err(BlockingError::Canceled) => Err(Error::from(Canceled)),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    #[test]
+    fn test_client_ip() {
+        let req = TestRequest::get()
+            .peer_addr("10.0.0.1:443".parse().unwrap())
+            .header("X-Forwarded-For", "1.2.3.4, 203.0.113.7")
+            .to_http_request();
+
+        assert_eq!("203.0.113.7", client_ip(&req, true));
+        assert_eq!("10.0.0.1", client_ip(&req, false));
+
+        let req = TestRequest::get()
+            .peer_addr("10.0.0.1:443".parse().unwrap())
+            .to_http_request();
+
+        assert_eq!("10.0.0.1", client_ip(&req, true));
+    }
+}
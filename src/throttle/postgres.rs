@@ -0,0 +1,57 @@
+use chrono::Duration;
+
+use super::{ThrottleStore, Window};
+use crate::db::{ClearThrottle, GetThrottle, HitThrottle, Pool, PurgeThrottles, Query};
+use crate::error::Result;
+use crate::models::AuthThrottle;
+
+/// Keeps rate limit windows in the `auth_throttle` table.
+pub struct PostgresStore {
+    pool: Pool,
+}
+
+impl PostgresStore {
+    pub fn new(pool: Pool) -> PostgresStore {
+        PostgresStore { pool }
+    }
+}
+
+impl From<AuthThrottle> for Window {
+    fn from(throttle: AuthThrottle) -> Window {
+        Window {
+            hits: throttle.hits as u32,
+            resets_at: throttle.resets_at,
+        }
+    }
+}
+
+impl ThrottleStore for PostgresStore {
+    fn hit(&self, key: &str, window: Duration) -> Result<Window> {
+        let query = HitThrottle {
+            key: key.to_string(),
+            window,
+        };
+
+        query.execute(self.pool.get()?).map(Window::from)
+    }
+
+    fn get(&self, key: &str) -> Result<Option<Window>> {
+        let query = GetThrottle {
+            key: key.to_string(),
+        };
+
+        Ok(query.execute(self.pool.get()?)?.map(Window::from))
+    }
+
+    fn clear(&self, key: &str) -> Result<()> {
+        let query = ClearThrottle {
+            key: key.to_string(),
+        };
+
+        query.execute(self.pool.get()?).map(|_| ())
+    }
+
+    fn purge(&self) -> Result<usize> {
+        PurgeThrottles.execute(self.pool.get()?)
+    }
+}
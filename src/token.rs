@@ -0,0 +1,50 @@
+//! Secret bearer tokens handed out to clients.
+//!
+//! Only the SHA-256 digest of a token is ever stored in the database, so the
+//! plaintext exists solely in the response that first hands it to the client.
+//! Tokens are long random strings, so an unkeyed digest is sufficient.
+
+use rand::Rng;
+use sha2::{Digest, Sha256};
+use textnonce::TextNonce;
+
+/// Generate a new random 64 character token.
+pub fn generate() -> String {
+    TextNonce::sized(64).unwrap().to_string()
+}
+
+/// Generate a new random token that is safe to use in URLs, as `bytes` of hex.
+pub fn generate_hex(bytes: usize) -> String {
+    let mut rng = rand::thread_rng();
+
+    (0..bytes)
+        .map(|_| format!("{:02x}", rng.gen::<u8>()))
+        .collect()
+}
+
+/// The hex encoded SHA-256 digest of a token, as it is stored in the database.
+pub fn digest(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_digest() {
+        assert_eq!(
+            "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08",
+            digest("test")
+        );
+        assert_eq!(64, digest(&generate()).len());
+    }
+
+    #[test]
+    fn test_generate_hex() {
+        let token = generate_hex(32);
+
+        assert_eq!(64, token.len());
+        assert!(token.chars().all(|c| c.is_ascii_hexdigit()));
+    }
+}
@@ -0,0 +1,53 @@
+use ::authy::api::phone;
+use ::authy::AuthyError;
+
+use super::{CheckOutcome, VerificationProvider};
+use crate::error::{Error, Result};
+
+/// Verifies phone numbers by SMS using the Authy phone verification API.
+pub struct AuthyProvider {
+    client: ::authy::Client,
+}
+
+impl AuthyProvider {
+    pub fn new(api_key: &str) -> AuthyProvider {
+        AuthyProvider {
+            client: ::authy::Client::new("https://api.authy.com", api_key),
+        }
+    }
+}
+
+impl VerificationProvider for AuthyProvider {
+    fn start(&self, country_code: u16, phone_number: &str) -> Result<String> {
+        let (status, _start) = phone::start(
+            &self.client,
+            phone::ContactType::SMS,
+            country_code,
+            phone_number,
+            Some(6),
+            None,
+        )
+        .map_err(|e| Error::VerificationError(e.to_string()))?;
+
+        Ok(status.message)
+    }
+
+    fn check(&self, country_code: u16, phone_number: &str, code: &str) -> Result<CheckOutcome> {
+        match phone::check(&self.client, country_code, phone_number, code) {
+            Ok(status) if status.success => Ok(CheckOutcome::Verified),
+            Ok(_) => Ok(CheckOutcome::InvalidCode),
+
+            // If there was an internal error, that the Authy crate has bubbled up.
+            Err(AuthyError::RequestError(e))
+            | Err(AuthyError::IoError(e))
+            | Err(AuthyError::JsonParseError(e)) => Err(Error::VerificationError(e.to_string())),
+
+            // If the verification code was incorrect
+            // The Authy crate currently returns this as an Unauthorized API Key error.
+            Err(AuthyError::UnauthorizedKey(_)) => Ok(CheckOutcome::InvalidCode),
+
+            // If we received some other Authy error response.
+            Err(e) => Ok(CheckOutcome::Rejected(e.to_string())),
+        }
+    }
+}
@@ -0,0 +1,119 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use rand::Rng;
+
+use super::{CheckOutcome, VerificationProvider};
+use crate::error::Result;
+
+/// An in-process provider for development and integration tests.
+///
+/// Nothing is sent anywhere. Either a single fixed code is accepted for every
+/// phone number, or a random code is generated for each `start` and written to the log.
+pub struct LocalProvider {
+    fixed_code: Option<String>,
+    pending: Mutex<HashMap<String, String>>,
+}
+
+impl LocalProvider {
+    /// Accept `code` for every phone number.
+    pub fn fixed(code: String) -> LocalProvider {
+        LocalProvider {
+            fixed_code: Some(code),
+            pending: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Generate a random code for each verification and log it.
+    pub fn logged() -> LocalProvider {
+        LocalProvider {
+            fixed_code: None,
+            pending: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn key(country_code: u16, phone_number: &str) -> String {
+        format!("{}:{}", country_code, phone_number)
+    }
+}
+
+impl VerificationProvider for LocalProvider {
+    fn start(&self, country_code: u16, phone_number: &str) -> Result<String> {
+        let code = match self.fixed_code {
+            Some(ref code) => code.clone(),
+            None => format!("{:06}", rand::thread_rng().gen_range(0, 1_000_000)),
+        };
+
+        info!(
+            "Verification code for '{}' '{}' is {}",
+            country_code, phone_number, code
+        );
+
+        self.pending
+            .lock()
+            .unwrap()
+            .insert(Self::key(country_code, phone_number), code);
+
+        Ok("Verification code logged by the local provider.".into())
+    }
+
+    fn check(&self, country_code: u16, phone_number: &str, code: &str) -> Result<CheckOutcome> {
+        if let Some(ref fixed_code) = self.fixed_code {
+            return Ok(match fixed_code == code {
+                true => CheckOutcome::Verified,
+                false => CheckOutcome::InvalidCode,
+            });
+        }
+
+        let mut pending = self.pending.lock().unwrap();
+        let key = Self::key(country_code, phone_number);
+
+        match pending.get(&key) {
+            Some(expected) if expected == code => {
+                pending.remove(&key);
+                Ok(CheckOutcome::Verified)
+            }
+            _ => Ok(CheckOutcome::InvalidCode),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fixed_code() {
+        let provider = LocalProvider::fixed("123456".into());
+
+        assert_eq!(
+            CheckOutcome::Verified,
+            provider.check(1, "5551234567", "123456").unwrap()
+        );
+        assert_eq!(
+            CheckOutcome::InvalidCode,
+            provider.check(1, "5551234567", "654321").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_logged_code_is_single_use() {
+        let provider = LocalProvider::logged();
+        provider.start(1, "5551234567").unwrap();
+
+        let code = provider.pending.lock().unwrap()["1:5551234567"].clone();
+
+        assert_eq!(
+            CheckOutcome::InvalidCode,
+            provider.check(44, "5551234567", &code).unwrap()
+        );
+        assert_eq!(
+            CheckOutcome::Verified,
+            provider.check(1, "5551234567", &code).unwrap()
+        );
+        assert_eq!(
+            CheckOutcome::InvalidCode,
+            provider.check(1, "5551234567", &code).unwrap()
+        );
+    }
+}
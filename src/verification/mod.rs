@@ -0,0 +1,112 @@
+//! Phone number verification providers.
+//!
+//! A provider is responsible for delivering a one-time code to a phone number
+//! and later checking a code submitted by the person holding that phone.
+//! Handlers only ever deal with the provider-neutral `CheckOutcome` and `Error`,
+//! so the backend can be swapped through the `VERIFICATION_PROVIDER` variable.
+
+use std::sync::Arc;
+
+use actix_web::web;
+use futures::future::Future;
+use futures::prelude::*;
+
+use crate::error::{Error, Result};
+
+mod authy;
+mod local;
+
+pub use self::authy::AuthyProvider;
+pub use self::local::LocalProvider;
+
+/// The result of checking a verification code.
+#[derive(Debug, PartialEq)]
+pub enum CheckOutcome {
+    /// The code was correct for the phone number.
+    Verified,
+
+    /// The code was incorrect or has expired.
+    InvalidCode,
+
+    /// The provider refused to verify the code for some other reason.
+    Rejected(String),
+}
+
+pub trait VerificationProvider: Send + Sync {
+    /// Send a verification code to the given phone number.
+    ///
+    /// Returns a message from the provider that may be shown to the person.
+    fn start(&self, country_code: u16, phone_number: &str) -> Result<String>;
+
+    /// Check a verification code that was submitted for the given phone number.
+    ///
+    /// An `Err` is only returned if the provider could not be reached or
+    /// misbehaved; an incorrect code is reported as `CheckOutcome::InvalidCode`.
+    fn check(&self, country_code: u16, phone_number: &str, code: &str) -> Result<CheckOutcome>;
+}
+
+/// The shared provider handle stored as application data.
+pub type Provider = Arc<dyn VerificationProvider>;
+
+/// Build the verification provider selected by the `VERIFICATION_PROVIDER` variable.
+///
+/// - `authy` (the default) sends real SMS messages and requires `AUTHY_API_KEY`.
+/// - `local` never leaves the process. If `LOCAL_VERIFICATION_CODE` is set then that code
+///   is always accepted, otherwise a random code is generated and written to the log.
+pub fn from_env() -> Provider {
+    match std::env::var("VERIFICATION_PROVIDER")
+        .unwrap_or("authy".into())
+        .as_str()
+    {
+        "authy" => Arc::new(AuthyProvider::new(
+            &std::env::var("AUTHY_API_KEY").expect("An authy API key is required!"),
+        )),
+        "local" => {
+            warn!("Using the local verification provider; no SMS messages will be sent!");
+
+            match std::env::var("LOCAL_VERIFICATION_CODE") {
+                Ok(code) => Arc::new(LocalProvider::fixed(code)),
+                Err(_) => Arc::new(LocalProvider::logged()),
+            }
+        }
+        other => panic!("Unknown verification provider '{}'!", other),
+    }
+}
+
+/// Run `VerificationProvider::start` on the blocking thread pool.
+pub fn start(
+    provider: &Provider,
+    country_code: u16,
+    phone_number: String,
+) -> impl Future<Output = Result<String>> {
+    let provider = provider.clone();
+
+    block(move || provider.start(country_code, &phone_number))
+}
+
+/// Run `VerificationProvider::check` on the blocking thread pool.
+pub fn check(
+    provider: &Provider,
+    country_code: u16,
+    phone_number: String,
+    code: String,
+) -> impl Future<Output = Result<CheckOutcome>> {
+    let provider = provider.clone();
+
+    block(move || provider.check(country_code, &phone_number, &code))
+}
+
+fn block<F, T>(f: F) -> impl Future<Output = Result<T>>
+where
+    F: FnOnce() -> Result<T> + Send + 'static,
+    T: Send + 'static,
+{
+    use actix_web::error::BlockingError;
+    use futures::channel::oneshot::Canceled;
+
+    web::block(f).map(|res| match res {
+        Ok(r) => Ok(r),
+        Err(BlockingError::Error(e)) => Err(e),
+        Err(BlockingError::Canceled) => Err(Error::from(Canceled)),
+    })
+}